@@ -0,0 +1,281 @@
+//! `#[derive(Applyable)]`, so you can build a CRDT out of other CRDTs without writing
+//! `apply_without_idempotency_check` yourself. You don't need to depend on this crate directly: `crdts` exports the
+//! derive along with the trait, so `use crdts::replicant::Applyable;` gets you both.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident, Type};
+
+/// Derives `Applyable` for a struct whose fields are all `Applyable`, or an enum whose variants each hold one
+/// `Applyable`.
+///
+/// It makes a description enum named after your type (`Post` gets `PostDescription`), with one variant for every
+/// field or variant, holding a description for that field's type. The variants of the description are the names of
+/// the fields in `CamelCase`, or the same as your enum's variants. The description derives `Serialize` and
+/// `Deserialize`, so you need `serde` (with the `derive` feature) in your dependencies.
+///
+/// For a struct, every operation changes one field, and the fields don't know about each other. So the struct
+/// converges as long as each field does. It works just like a tuple, but with names.
+///
+/// ```
+/// use crdts::replicant::{create_account, create_crdt, create_crdt_info, get_random_id, Applyable, Nat, CRDT};
+/// use crdts::types::{LwwRegister, OrSet};
+/// use sodiumoxide::crypto::sign;
+///
+/// #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Applyable)]
+/// struct Post {
+///     title: LwwRegister<String>,
+///     likes: Nat,
+///     tags: OrSet<String>,
+/// }
+///
+/// let post = Post {
+///     title: LwwRegister::new("Untitled".to_string()),
+///     likes: Nat::from(0),
+///     tags: OrSet::new(),
+/// };
+/// let initial = create_crdt(create_crdt_info(post, get_random_id()));
+/// let (pk1, sk1) = sign::gen_keypair();
+/// let (pk2, sk2) = sign::gen_keypair();
+/// let account1 = create_account(pk1, sk1);
+/// let account2 = create_account(pk2, sk2);
+///
+/// let crdt1 = initial
+///     .clone()
+///     .apply_desc(&account1, PostDescription::Title("Hello".to_string()))
+///     .apply_desc(&account1, PostDescription::Likes(1));
+/// let crdt2 = initial
+///     .apply_desc(&account2, PostDescription::Likes(1))
+///     .apply_desc(&account2, PostDescription::Tags(OrSet::add("news".to_string())));
+///
+/// let missing = crdt2.ops_since(crdt1.state_vector()).collect::<Vec<_>>();
+/// let merged = missing.into_iter().fold(crdt1, CRDT::apply);
+/// assert_eq!(merged.value.title.value(), "Hello");
+/// assert_eq!(merged.value.likes.value, 2);
+/// assert!(merged.value.tags.contains(&"news".to_string()));
+/// assert_eq!(Post::NAME, "Post");
+/// ```
+///
+/// For an enum, the value is always whichever variant it started out as (nothing changes which variant it is), and
+/// operations meant for any other variant do nothing. That's handy when a project can hold one of a few kinds of
+/// thing.
+///
+/// ```
+/// use crdts::replicant::{create_account, create_crdt, create_crdt_info, get_random_id, Applyable};
+/// use crdts::types::{PNCounter, Text};
+/// use sodiumoxide::crypto::sign;
+///
+/// #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Applyable)]
+/// enum Widget {
+///     Note(Text),
+///     Tally(PNCounter),
+/// }
+///
+/// let (pk, sk) = sign::gen_keypair();
+/// let account = create_account(pk, sk);
+/// let crdt = create_crdt(create_crdt_info(Widget::Tally(PNCounter::default()), get_random_id()))
+///     .apply_desc(&account, WidgetDescription::Tally(3))
+///     .apply_desc(&account, WidgetDescription::Note(Text::new().insert(0, "ignored").unwrap()));
+/// match crdt.value {
+///     Widget::Tally(counter) => assert_eq!(counter.value(), 3),
+///     Widget::Note(_) => unreachable!(),
+/// }
+/// ```
+///
+/// Every field has to be `Applyable`, and the error points at the one that isn't:
+///
+/// ```compile_fail
+/// use crdts::replicant::Applyable;
+///
+/// #[derive(Clone, Applyable)]
+/// struct Post {
+///     title: String,
+/// }
+/// ```
+///
+/// The fields need names, since they become the names of the description's variants:
+///
+/// ```compile_fail
+/// use crdts::replicant::{Applyable, Nat};
+///
+/// #[derive(Clone, Applyable)]
+/// struct Likes(Nat);
+/// ```
+///
+/// Enum variants have to hold exactly one `Applyable`:
+///
+/// ```compile_fail
+/// use crdts::replicant::{Applyable, Nat};
+///
+/// #[derive(Clone, Applyable)]
+/// enum Widget {
+///     Empty,
+///     Tally(Nat),
+/// }
+/// ```
+///
+/// And generic types aren't supported yet:
+///
+/// ```compile_fail
+/// use crdts::replicant::Applyable;
+///
+/// #[derive(Clone, Applyable)]
+/// struct Pair<A: Applyable> {
+///     first: A,
+///     second: A,
+/// }
+/// ```
+#[proc_macro_derive(Applyable)]
+pub fn derive_applyable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+// `foo_bar` becomes `FooBar`.
+fn camel_case(ident: &Ident) -> Ident {
+    let name = ident.to_string();
+    let name = name.trim_start_matches("r#");
+    let camel = name
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<String>();
+    Ident::new(&camel, ident.span())
+}
+
+// The description of `ty`. If `ty` isn't `Applyable`, the error points at it.
+fn description_of(ty: &Type) -> TokenStream2 {
+    quote_spanned! {ty.span()=> <#ty as ::crdts::replicant::Applyable>::Description}
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "#[derive(Applyable)] doesn't support generic types yet",
+        ));
+    }
+    let name = &input.ident;
+    let vis = &input.vis;
+    let description = format_ident!("{}Description", name);
+
+    // One (variant, field type) pair for every variant of the description, along with how to apply it
+    let (variants, types, apply) = match &input.data {
+        Data::Struct(data) => {
+            let fields = match &data.fields {
+                Fields::Named(fields) => &fields.named,
+                _ => {
+                    return Err(Error::new_spanned(
+                        &data.fields,
+                        "#[derive(Applyable)] needs the fields to have names, since they name the description's variants",
+                    ))
+                }
+            };
+            let field_names = fields
+                .iter()
+                .map(|field| field.ident.clone().unwrap())
+                .collect::<Vec<_>>();
+            let variants = field_names.iter().map(camel_case).collect::<Vec<_>>();
+            let types = fields
+                .iter()
+                .map(|field| field.ty.clone())
+                .collect::<Vec<_>>();
+            let apply = quote! {
+                let mut value = self;
+                match desc {
+                    #(#description::#variants(desc) => {
+                        value.#field_names = ::crdts::replicant::Applyable::apply_without_idempotency_check(
+                            value.#field_names,
+                            desc,
+                            user_pub_key,
+                            counter,
+                            time,
+                        );
+                    })*
+                }
+                value
+            };
+            (variants, types, apply)
+        }
+        Data::Enum(data) => {
+            let mut variants = vec![];
+            let mut types = vec![];
+            for variant in &data.variants {
+                match &variant.fields {
+                    Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                        variants.push(variant.ident.clone());
+                        types.push(fields.unnamed[0].ty.clone());
+                    }
+                    _ => {
+                        return Err(Error::new_spanned(
+                            variant,
+                            "#[derive(Applyable)] needs every variant to hold exactly one Applyable, like `Tally(PNCounter)`",
+                        ))
+                    }
+                }
+            }
+            let apply = quote! {
+                // There's no other variant to ignore if the enum only has one
+                #[allow(unreachable_patterns)]
+                match (self, desc) {
+                    #((#name::#variants(value), #description::#variants(desc)) => {
+                        #name::#variants(::crdts::replicant::Applyable::apply_without_idempotency_check(
+                            value,
+                            desc,
+                            user_pub_key,
+                            counter,
+                            time,
+                        ))
+                    })*
+                    (value, _) => value,
+                }
+            };
+            (variants, types, apply)
+        }
+        Data::Union(data) => {
+            return Err(Error::new_spanned(
+                data.union_token,
+                "#[derive(Applyable)] doesn't support unions",
+            ))
+        }
+    };
+
+    let descriptions = types.iter().map(description_of);
+    let doc = format!("Changes one part of a `{}`.", name);
+    let name_str = name.to_string();
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ::serde::Serialize, ::serde::Deserialize)]
+        #vis enum #description {
+            #(#variants(#descriptions),)*
+        }
+
+        impl ::crdts::replicant::Applyable for #name {
+            const NAME: &'static str = #name_str;
+
+            type Description = #description;
+
+            fn apply_without_idempotency_check(
+                self,
+                desc: Self::Description,
+                user_pub_key: ::crdts::replicant::UserPubKey,
+                counter: ::crdts::replicant::Counter,
+                time: ::crdts::replicant::Time,
+            ) -> Self {
+                #apply
+            }
+        }
+    })
+}
@@ -0,0 +1,132 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use crdts::replicant::Applyable;
+use crdts::storage::read_project_type;
+use crdts::sync::{Address, Daemon};
+
+const USAGE: &str = "Usage: penny-daemon [--listen <address>]... [--peer <address>]... [--interval <milliseconds>] <project>...
+
+Serves the given projects to other daemons, and replicates them from the daemons given with --peer.
+An address is either a TCP socket address like 127.0.0.1:7878, or the path of a Unix domain socket.";
+
+// Read the command line arguments, then start listening, connecting to our peers, and watching the project
+// directories for new operations. All of that happens in the background, so the main thread just waits forever.
+fn main() {
+    let mut listen_addresses: Vec<Address> = vec![];
+    let mut peer_addresses: Vec<Address> = vec![];
+    let mut interval = Duration::from_millis(1000);
+    let mut project_basedirs: Vec<PathBuf> = vec![];
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" | "--peer" | "--interval" => {
+                let value = args.next().unwrap_or_else(|| exit_with_usage());
+                match arg.as_str() {
+                    "--listen" => listen_addresses.push(parse_address(&value)),
+                    "--peer" => peer_addresses.push(parse_address(&value)),
+                    _ => {
+                        interval = Duration::from_millis(
+                            value.parse().unwrap_or_else(|_| exit_with_usage()),
+                        )
+                    }
+                }
+            }
+            "--help" | "-h" => exit_with_usage(),
+            project_name => project_basedirs.push(PathBuf::from(project_name)),
+        }
+    }
+    if project_basedirs.is_empty() {
+        exit_with_usage();
+    }
+
+    // A daemon works with one type of CRDT, so every project it serves has to hold the same type.
+    let project_type = read_project_type(&project_basedirs[0]);
+    if let Some(other) = project_basedirs
+        .iter()
+        .find(|basedir| read_project_type(basedir) != project_type)
+    {
+        println!(
+            "{} holds a different type of CRDT to {}. Please run a separate daemon for each type.",
+            other.to_string_lossy(),
+            project_basedirs[0].to_string_lossy()
+        );
+        std::process::exit(1);
+    }
+    serve_project_type(
+        &project_type,
+        &project_basedirs,
+        listen_addresses,
+        peer_addresses,
+        interval,
+    );
+    println!("I don't know how to sync a {}", project_type);
+    std::process::exit(1);
+}
+
+// Serve the projects as whichever type they hold, from the list of types in `crdts::project_types!`. Only returns
+// if it's a type we don't know.
+macro_rules! project_type_dispatch {
+    ($($name:literal => $type:ty = $initial_value:expr,)*) => {
+        fn serve_project_type(
+            project_type: &str,
+            project_basedirs: &[PathBuf],
+            listen_addresses: Vec<Address>,
+            peer_addresses: Vec<Address>,
+            interval: Duration,
+        ) {
+            $(
+                if project_type == <$type as Applyable>::NAME {
+                    serve::<$type>(project_basedirs, listen_addresses, peer_addresses, interval);
+                }
+            )*
+        }
+    };
+}
+
+crdts::project_types!(project_type_dispatch);
+
+fn serve<T>(
+    project_basedirs: &[PathBuf],
+    listen_addresses: Vec<Address>,
+    peer_addresses: Vec<Address>,
+    interval: Duration,
+) -> !
+where
+    T: Applyable + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    T::Description: Serialize + DeserializeOwned + Ord + fmt::Debug + Send + 'static,
+{
+    let daemon = Daemon::<T>::open(project_basedirs);
+    for address in listen_addresses {
+        let address = daemon
+            .listen(&address)
+            .unwrap_or_else(|e| panic!("Couldn't listen on {}: {}", address, e));
+        println!("Listening on {}", address);
+    }
+    for address in peer_addresses {
+        println!("Replicating with {}", address);
+        daemon.connect(address, interval);
+    }
+    daemon.watch(interval);
+
+    loop {
+        thread::park();
+    }
+}
+
+fn parse_address(address: &str) -> Address {
+    address
+        .parse()
+        .unwrap_or_else(|e| panic!("Couldn't understand the address {}: {}", address, e))
+}
+
+fn exit_with_usage() -> ! {
+    println!("{}", USAGE);
+    std::process::exit(1)
+}
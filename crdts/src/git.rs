@@ -0,0 +1,313 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::replicant::verify_encoded_operation;
+use crate::storage::{decode_user_pub_key, operation_file_name};
+
+// Operations never change once they're written, so git should never try to diff or merge them line by line.
+// Instead, any merge goes through `penny git-merge`.
+const GITATTRIBUTES: &str = "*.pennyop -text -diff merge=penny
+project.penny -text -diff merge=penny
+";
+
+// The operation index can always be rebuilt from the operations, and every clone keeps its own. Temporary files are
+// only left behind if penny crashed while writing an operation.
+const GITIGNORE: &str = "/index/
+*.tmp
+";
+
+// We use this to recognise a pre-commit hook we installed ourselves, so we know it's safe to replace.
+const HOOK_MARKER: &str = "# Installed by `penny git-setup`";
+
+/// Set up the git repository containing a project so that syncing it through git is safe:
+///  - `.gitattributes` routes `*.pennyop` and `project.penny` to our merge driver
+///  - `.gitignore` leaves out the operation index and any leftover temporary files
+///  - the merge driver itself is registered in the repository's config
+///  - a pre-commit hook checks the signature of every operation before it can be committed
+///
+/// `penny` is the path of the executable that git should run for the merge driver and the hook.
+pub fn setup(project_basedir: &Path, penny: &Path) -> Result<(), String> {
+    if !project_basedir.join("project.penny").exists() {
+        return Err(format!(
+            "There's no project at {}",
+            project_basedir.to_string_lossy()
+        ));
+    }
+    let penny = penny.to_string_lossy();
+
+    add_lines(&project_basedir.join(".gitattributes"), GITATTRIBUTES)?;
+    add_lines(&project_basedir.join(".gitignore"), GITIGNORE)?;
+
+    // The merge driver
+    git(
+        project_basedir,
+        &["config", "merge.penny.name", "penny operation merge driver"],
+    )?;
+    git(
+        project_basedir,
+        &[
+            "config",
+            "merge.penny.driver",
+            &format!("\"{}\" git-merge %O %A %B %P", penny),
+        ],
+    )?;
+
+    // The pre-commit hook
+    let hooks_dir = PathBuf::from(git(project_basedir, &["rev-parse", "--git-path", "hooks"])?);
+    let hooks_dir = if hooks_dir.is_absolute() {
+        hooks_dir
+    } else {
+        project_basedir.join(hooks_dir)
+    };
+    let hook_path = hooks_dir.join("pre-commit");
+    let hook = format!(
+        "#!/bin/sh\n{}: refuse to commit operations that aren't properly signed.\nexec \"{}\" git-verify\n",
+        HOOK_MARKER, penny
+    );
+    match fs::read_to_string(&hook_path) {
+        Ok(existing) if !existing.contains(HOOK_MARKER) => {
+            return Err(format!(
+                "There's already a pre-commit hook at {}. I don't want to overwrite it, so please add this line to it yourself:\n\"{}\" git-verify",
+                hook_path.display(),
+                penny
+            ))
+        }
+        _ => {
+            fs::create_dir_all(&hooks_dir)
+                .and_then(|_| fs::write(&hook_path, hook))
+                .and_then(|_| make_executable(&hook_path))
+                .map_err(|e| format!("Couldn't write {}: {}", hook_path.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+// Add each of `lines` to the end of the file at `path`, unless it's already there.
+fn add_lines(path: &Path, lines: &str) -> Result<(), String> {
+    let existing = fs::read_to_string(path).unwrap_or_default();
+    let missing = lines
+        .lines()
+        .filter(|line| !existing.lines().any(|existing| existing == *line))
+        .map(|line| format!("{}\n", line))
+        .collect::<String>();
+    if !missing.is_empty() {
+        let separator = if existing.is_empty() || existing.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        fs::write(path, format!("{}{}{}", existing, separator, missing))
+            .map_err(|e| format!("Couldn't write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// This is the merge driver. Git calls it whenever both sides of a merge have a file at the same path but with
+/// different contents, which should never happen to a penny project unless something has gone wrong.
+/// The merged result is written to `ours`, like git expects.
+///
+/// Two different operations with the same path means a user made two different operations with the same counter,
+/// probably because they copied a project directory along with their keys. If only one of them has a valid
+/// signature we keep that one. If they both do, the user's history has forked: their later operations each follow
+/// on from one side or the other, so there's no keeping just one of them. We refuse the merge, like `git-verify`
+/// refuses a commit that rewrites history.
+pub fn merge(ours: &Path, theirs: &Path, path: &str) -> Result<(), String> {
+    let read = |file: &Path| {
+        fs::read(file).map_err(|e| format!("Couldn't read {}: {}", file.display(), e))
+    };
+    let (our_bytes, their_bytes) = (read(ours)?, read(theirs)?);
+    if our_bytes == their_bytes {
+        return Ok(());
+    }
+    let path = Path::new(path);
+    if path.file_name() == Some(OsStr::new("project.penny")) {
+        return Err(format!(
+            "The two versions of {} are different projects, so they can't be merged.",
+            path.display()
+        ));
+    }
+
+    let merged = resolve_operation_collision(path, our_bytes, their_bytes)?;
+    fs::write(ours, merged).map_err(|e| format!("Couldn't write {}: {}", ours.display(), e))
+}
+
+fn resolve_operation_collision(
+    path: &Path,
+    ours: Vec<u8>,
+    theirs: Vec<u8>,
+) -> Result<Vec<u8>, String> {
+    let our_error = verify_operation(path, &ours);
+    let their_error = verify_operation(path, &theirs);
+    match (our_error, their_error) {
+        (Ok(()), Ok(())) => Err(format!(
+            "{} was written twice with different contents, so the history of the user who made it has forked \
+             (maybe they copied the project along with their keys). Penny can't merge that. Keep one side of the \
+             user's folder and drop the other.",
+            path.display()
+        )),
+        (Ok(()), Err(_)) => Ok(ours),
+        (Err(_), Ok(())) => Ok(theirs),
+        (Err(e), Err(_)) => Err(e),
+    }
+}
+
+/// This is what the pre-commit hook runs. It checks every operation that's about to be committed, and refuses
+/// if any of them isn't properly signed by the user whose folder it's in, or if an existing operation was changed.
+pub fn verify_staged() -> Result<(), String> {
+    let mut problems = vec![];
+    for (status, path) in staged_changes()? {
+        let is_operation = path.ends_with(".pennyop");
+        let is_project = Path::new(&path).file_name() == Some(OsStr::new("project.penny"));
+        match status.as_str() {
+            "A" if is_operation => {
+                let contents = git_bytes(Path::new("."), &["show", &format!(":{}", path)])?;
+                if let Err(e) = verify_operation(Path::new(&path), &contents) {
+                    problems.push(e);
+                }
+            }
+            "M" if is_operation || is_project => problems.push(format!(
+                "{} has been changed, but it's never supposed to change once it's written.",
+                path
+            )),
+            _ => {}
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join("\n"))
+    }
+}
+
+/// Check that the operation in `contents` was signed by the user whose folder `path` is in, and that it's stored
+/// under the right file name.
+pub fn verify_operation(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let user_dir = path
+        .parent()
+        .and_then(Path::file_name)
+        .ok_or_else(|| format!("{} isn't inside a user's folder", path.display()))?;
+    let user_pub_key = decode_user_pub_key(&user_dir.to_string_lossy())?;
+    let counter = verify_encoded_operation(contents, &user_pub_key).ok_or_else(|| {
+        format!(
+            "{} isn't signed by the user whose folder it's in!",
+            path.display()
+        )
+    })?;
+    let expected_file_name = operation_file_name(counter.pun());
+    if path.file_name() != Some(OsStr::new(&expected_file_name)) {
+        return Err(format!(
+            "{} should be called {}",
+            path.display(),
+            expected_file_name
+        ));
+    }
+    Ok(())
+}
+
+// Returns the status letter and path of every staged change.
+fn staged_changes() -> Result<Vec<(String, String)>, String> {
+    let output = git_bytes(
+        Path::new("."),
+        &["diff", "--cached", "--name-status", "--no-renames", "-z"],
+    )?;
+    let fields = output
+        .split(|byte| *byte == 0)
+        .filter(|field| !field.is_empty())
+        .map(|field| String::from_utf8_lossy(field).into_owned())
+        .collect::<Vec<_>>();
+    Ok(fields
+        .chunks(2)
+        .filter(|chunk| chunk.len() == 2)
+        .map(|chunk| (chunk[0].clone(), chunk[1].clone()))
+        .collect())
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = git_bytes(dir, args)?;
+    Ok(String::from_utf8_lossy(&output).trim_end().to_string())
+}
+
+fn git_bytes(dir: &Path, args: &[&str]) -> Result<Vec<u8>, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Couldn't run git: {}", e))?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(format!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ))
+    }
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{create_account, create_crdt, create_crdt_info, get_random_id, Nat};
+    use crate::storage::operation_path;
+    use sodiumoxide::crypto::sign;
+
+    #[test]
+    fn only_forged_collisions_get_resolved() {
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+        let info = create_crdt_info(Nat::from(0), get_random_id());
+
+        // The same user makes two different operations with the same counter in two copies of the project
+        let mut copy1 = create_crdt(info).apply_desc(&account, 1);
+        let mut copy2 = create_crdt(info).apply_desc(&account, 2);
+        let operation1 = copy1
+            .flush()
+            .into_values()
+            .max_by_key(|op| op.data.counter())
+            .unwrap();
+        let operation2 = copy2
+            .flush()
+            .into_values()
+            .max_by_key(|op| op.data.counter())
+            .unwrap();
+        let path = operation_path::<Nat>(&operation1, Path::new("project"));
+        assert_eq!(
+            path,
+            operation_path::<Nat>(&operation2, Path::new("project"))
+        );
+
+        let bytes1 = bincode::serialize(&operation1.data).unwrap();
+        let bytes2 = bincode::serialize(&operation2.data).unwrap();
+        assert_eq!(verify_operation(&path, &bytes1), Ok(()));
+        assert_eq!(verify_operation(&path, &bytes2), Ok(()));
+
+        // Both are properly signed, so the user's history has forked and we can't pick one
+        assert!(resolve_operation_collision(&path, bytes1.clone(), bytes2.clone()).is_err());
+        assert!(resolve_operation_collision(&path, bytes2.clone(), bytes1.clone()).is_err());
+
+        // A forged operation never wins
+        let mut forged = bytes2;
+        *forged.last_mut().unwrap() ^= 1;
+        assert!(verify_operation(&path, &forged).is_err());
+        assert_eq!(
+            resolve_operation_collision(&path, forged.clone(), bytes1.clone()),
+            Ok(bytes1.clone())
+        );
+        assert!(resolve_operation_collision(&path, forged.clone(), forged).is_err());
+    }
+}
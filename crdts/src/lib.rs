@@ -0,0 +1,8 @@
+pub mod git;
+pub mod replicant;
+pub mod simulator;
+pub mod storage;
+pub mod sync;
+#[cfg(any(test, feature = "testkit"))]
+pub mod testkit;
+pub mod types;
@@ -1,4 +1,3 @@
-use base64::{CharacterSet, Config};
 use directories_next::ProjectDirs;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
@@ -13,31 +12,149 @@ use std::io::Read;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
-mod replicant;
-use replicant::{
+use crdts::git;
+use crdts::replicant::{
     create_account, create_crdt, create_crdt_info, get_random_id, Account, Applyable, CRDTInfo,
-    Counter, Nat, Operation, OperationSigned, UserPubKey, UserSecKey, CRDT,
+    Nat, UserPubKey, UserSecKey, CRDT,
+};
+use crdts::storage::{
+    base64_config, create_project, decode_user_pub_key, encode_user_pub_key, operation_index,
+    project_users, read_project_info, read_project_type, restore_operations, save_operations,
+};
+use crdts::types::json_document::JsonValue;
+use crdts::types::rich_text::Mark;
+use crdts::types::{
+    DwFlag, EwFlag, GSet, Graph, JsonDocument, LwwRegister, MvRegister, OrSet, PNCounter, RichText,
+    Sequence, Table, Text, Tree, TwoPhaseSet,
 };
 
 use ansi_term::Colour::Red;
 
-// We're going to be serializing the operations with bincode, converting them to text with base64,
-// then writing them to disk. This is the base64 config we're going to be using.
-fn base64_config() -> Config {
-    Config::new(CharacterSet::UrlSafe, false)
-}
-
 fn main() {
+    #[cfg(windows)]
     let _ = ansi_term::enable_ansi_support();
     let args: Vec<String> = env::args().collect();
 
-    if args.len() >= 2 {
-        let project_name: &str = &args[1];
-        attempt_to_open_project(project_name);
-    } else {
-        println!("Input the name of the project");
+    match args.get(1).map(String::as_str) {
+        Some("git-setup") => match args.get(2) {
+            Some(project_name) => git_setup(project_name),
+            None => println!("Input the name of the project to set up"),
+        },
+        // These two are run by git itself, once `git-setup` has been run.
+        Some("git-merge") => match (args.get(3), args.get(4), args.get(5)) {
+            (Some(ours), Some(theirs), Some(path)) => {
+                exit_on_error(git::merge(Path::new(ours), Path::new(theirs), path))
+            }
+            _ => exit_with_error(
+                "Usage: penny git-merge <base> <ours> <theirs> <path> (git runs this for you)"
+                    .to_string(),
+            ),
+        },
+        Some("git-verify") => exit_on_error(git::verify_staged()),
+        Some("csv") => match args.get(2) {
+            Some(project_name) => print_csv(project_name),
+            None => println!("Input the name of the table project to print"),
+        },
+        Some("log") => match args.get(2) {
+            Some(project_name) => exit_on_error(print_log(project_name, &args[3..])),
+            None => println!("{}", LOG_USAGE),
+        },
+        Some("init") => match (args.get(2), args.get(3).map(String::as_str), args.get(4)) {
+            (Some(project_name), None, None) => init_project(project_name, "nat"),
+            (Some(project_name), Some("--type"), Some(project_type)) => {
+                init_project(project_name, project_type)
+            }
+            _ => println!(
+                "Usage: penny init <project> [--type <{}>]",
+                PROJECT_TYPES.join("|")
+            ),
+        },
+        Some(project_name) => attempt_to_open_project(project_name),
+        None => println!("Input the name of the project"),
+    }
+}
+
+// Set up the git repository the project is in so it can be synced safely with git.
+fn git_setup(project_name: &str) {
+    let penny = env::current_exe().expect("Couldn't figure out where the penny executable is");
+    exit_on_error(git::setup(Path::new(project_name), &penny));
+    println!(
+        "Set up {} to be synced with git. Operations will be checked before every commit.",
+        project_name
+    );
+}
+
+// Print a table project as CSV, so it can be opened in a spreadsheet.
+fn print_csv(project_name: &str) {
+    let project_basedir = Path::new(project_name);
+    if read_project_type(project_basedir) != Table::NAME {
+        exit_with_error(format!("{} isn't a table", project_name));
+    }
+    let crdt = create_crdt(read_project_info::<Table>(project_basedir));
+    let crdt = restore_operations::<Table>(crdt, project_basedir);
+    print!("{}", crdt.value.to_csv());
+}
+
+const LOG_USAGE: &str =
+    "Usage: penny log <project> [--user <user>] [--since <seconds>] [--until <seconds>]";
+
+// Print who made which operations and when, oldest first. Times are in seconds since the unix epoch, and users are
+// shown by the name of their operations folder.
+fn print_log(project_name: &str, options: &[String]) -> Result<(), String> {
+    let project_basedir = Path::new(project_name);
+    if !project_basedir.join("project.penny").exists() {
+        return Err(format!("There's no project at {}", project_name));
+    }
+    let mut users = None;
+    let mut since = Duration::from_secs(0);
+    let mut until = Duration::from_secs(u64::MAX);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or(LOG_USAGE)?;
+        let seconds = || {
+            value
+                .parse()
+                .map(Duration::from_secs)
+                .map_err(|_| format!("{} isn't a number of seconds", value))
+        };
+        match option.as_str() {
+            "--user" => users = Some(vec![decode_user_pub_key(value)?]),
+            "--since" => since = seconds()?,
+            "--until" => until = seconds()?,
+            _ => return Err(LOG_USAGE.to_string()),
+        }
+    }
+
+    let mut operations = users
+        .unwrap_or_else(|| project_users(project_basedir))
+        .iter()
+        .flat_map(|user_pub_key| operation_index(project_basedir, user_pub_key))
+        .filter(|operation| (since..until).contains(&operation.time))
+        .collect::<Vec<_>>();
+    operations.sort_by_key(|operation| (operation.time, operation.user_pub_key, operation.pun));
+    for operation in operations {
+        println!(
+            "{}.{:09} {} #{}",
+            operation.time.as_secs(),
+            operation.time.subsec_nanos(),
+            encode_user_pub_key(&operation.user_pub_key),
+            operation.pun
+        );
     }
+    Ok(())
+}
+
+fn exit_on_error(result: Result<(), String>) {
+    if let Err(e) = result {
+        exit_with_error(e);
+    }
+}
+
+fn exit_with_error(message: String) -> ! {
+    eprintln!("{}", Red.paint(message));
+    std::process::exit(1);
 }
 
 // Attempt to open the project file. If it exists, try to read the project. If it doesn't,
@@ -49,30 +166,108 @@ fn attempt_to_open_project(project_name: &str) {
     let pennyfile_dir = project_basedir.join(std::path::Path::new(&project_file_str));
 
     match File::open(&pennyfile_dir) {
-        Ok(file) => read_project(file, project_basedir, pennyfile_dir),
+        Ok(_) => read_project(project_basedir, pennyfile_dir),
         Err(_) => create_new_project(project_name, project_basedir, pennyfile_dir),
     }
 }
 
-// First, we read the info file from the project file, and use the restore_operations function
+// First, we figure out what type of CRDT the project holds, then open it as that type.
+fn read_project(project_basedir: &Path, pennyfile_dir: PathBuf) {
+    println!("Looking for a project at {:?}.", pennyfile_dir);
+    let project_type = read_project_type(project_basedir);
+    if !open_project_of_type(&project_type, project_basedir, pennyfile_dir) {
+        exit_with_error(format!(
+            "This project holds a {}, which I don't know how to open.",
+            project_type
+        ));
+    }
+}
+
+// The parts of opening and creating projects that depend on the type, generated from the list of types in
+// `crdts::project_types!`.
+macro_rules! project_type_dispatch {
+    ($($name:literal => $type:ty = $initial_value:expr,)*) => {
+        // The types you can pass to `penny init --type`.
+        const PROJECT_TYPES: &[&str] = &[$($name),*];
+
+        // Open a project holding the type whose `Applyable::NAME` is `project_type`. Returns false if we don't know
+        // that type.
+        fn open_project_of_type(
+            project_type: &str,
+            project_basedir: &Path,
+            pennyfile_dir: PathBuf,
+        ) -> bool {
+            $(
+                if project_type == <$type as Applyable>::NAME {
+                    open_project::<$type>(project_basedir, pennyfile_dir);
+                    return true;
+                }
+            )*
+            false
+        }
+
+        // Create a project holding the type called `project_type` on the command line. Returns false if there's no
+        // such type.
+        fn create_project_of_type(project_type: &str, project_basedir: &Path) -> bool {
+            $(
+                if project_type == $name {
+                    create_project(
+                        project_basedir,
+                        &create_crdt_info::<$type>($initial_value, get_random_id()),
+                    );
+                    return true;
+                }
+            )*
+            false
+        }
+    };
+}
+
+crdts::project_types!(project_type_dispatch);
+
+// We read the info file from the project file, and use the restore_operations function
 // to collect all operations that have been recorded. Then we make an account and call the `run`
 // function to ask the user how they want to change it
-fn read_project(mut file: File, project_basedir: &Path, pennyfile_dir: PathBuf) {
-    println!("Looking for a project at {:?}.", pennyfile_dir);
-    let mut contents = vec![];
-    file.read_to_end(&mut contents).unwrap();
-    let project_info: CRDTInfo<Nat> = bincode::deserialize(&contents).unwrap();
+fn open_project<T>(project_basedir: &Path, pennyfile_dir: PathBuf)
+where
+    T: Interactive + Serialize + DeserializeOwned,
+    T::Description: Serialize + DeserializeOwned + Ord,
+
+    T: std::fmt::Display,
+    T: std::fmt::Debug,
+    T::Description: std::fmt::Debug,
+{
+    let project_info: CRDTInfo<T> = read_project_info(project_basedir);
 
     let crdt = create_crdt(project_info);
-    let crdt = restore_operations::<Nat>(crdt, project_basedir);
+    let crdt = restore_operations::<T>(crdt, project_basedir);
 
     let DirectoryLevelUserInfo { pk, sk, .. } = get_keypair(&pennyfile_dir);
     let account = create_account(pk, sk);
 
-    println!("Testing the {} CRDT", Nat::NAME);
+    println!("Testing the {} CRDT", T::NAME);
     run(crdt, account, project_basedir);
 }
 
+// Create a new project holding the given type of CRDT.
+fn init_project(project_name: &str, project_type: &str) {
+    let project_basedir = Path::new(project_name);
+    if project_basedir.join("project.penny").exists() {
+        exit_with_error(format!("There's already a project at {}", project_name));
+    }
+    if !create_project_of_type(project_type, project_basedir) {
+        exit_with_error(format!(
+            "I don't know what a {} is. Try one of: {}",
+            project_type,
+            PROJECT_TYPES.join(", ")
+        ));
+    }
+    println!(
+        "I created a new {} project at {:?}.",
+        project_type, project_basedir
+    );
+}
+
 // We ask the user if they want to create a new project, and create it if so.
 fn create_new_project(project_name: &str, project_basedir: &Path, pennyfile_dir: PathBuf) {
     print!(
@@ -84,177 +279,469 @@ fn create_new_project(project_name: &str, project_basedir: &Path, pennyfile_dir:
     io::stdin().read_line(&mut contents).unwrap();
     if contents.trim() == "y" {
         let info: CRDTInfo<Nat> = create_crdt_info(Nat::from(0), get_random_id());
-        let info = bincode::serialize(&info).expect("somehow there was a serialization error");
-        let _test: CRDTInfo<Nat> = bincode::deserialize(&info).unwrap();
-        fs::create_dir_all(project_basedir).unwrap();
-        {
-            let mut project_file = File::create(&pennyfile_dir).unwrap();
-            project_file.write_all(&info).unwrap();
-        }
+        create_project(project_basedir, &info);
         println!("I created a new project at {:?}.", pennyfile_dir);
     }
 }
 
-// Repeatedly ask the user for a new operation. We'll apply it to the crdt. Once the user exits we'll save
-// all their operations to disk
-fn run<T: Applyable>(mut crdt: CRDT<T>, mut account: Account, project_basedir: &Path)
-where
-    T: Applyable,
-    T: Serialize,
-    T::Description: Serialize,
-    T::Description: Ord,
-    T::Description: std::str::FromStr,
+// Everything the command line needs to know to let the user edit a type of CRDT.
+trait Interactive: Applyable {
+    // What we ask the user for
+    const PROMPT: &'static str;
 
-    T: std::fmt::Display,
-    T: std::fmt::Debug,
-    T::Description: std::fmt::Debug,
-{
-    loop {
-        println!("Current value: {}", Red.paint(format!("{}", crdt.value)));
-        print!("Increment: ");
-        io::stdout().flush().unwrap();
-        let mut increment = String::new();
-        io::stdin().read_line(&mut increment).unwrap();
-        match increment.trim().parse() {
-            Ok(increment) => {
-                crdt = crdt.apply_desc(&mut account, increment);
+    // Turn what the user typed into a description. `Ok(None)` means they're done, and an error means we should
+    // tell them what was wrong and ask again.
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String>;
+}
+
+impl Interactive for Nat {
+    const PROMPT: &'static str = "Increment";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        Ok(input.parse().ok())
+    }
+}
+
+impl Interactive for PNCounter {
+    const PROMPT: &'static str = "Increment (negative to decrement)";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        Ok(input.parse().ok())
+    }
+}
+
+impl Interactive for LwwRegister<String> {
+    const PROMPT: &'static str = "New value (leave empty to quit)";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        if input.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(input.to_string()))
+        }
+    }
+}
+
+// When there's a conflict, the value is shown as a numbered list, and the user can pick one of them to keep.
+impl Interactive for MvRegister<String> {
+    const PROMPT: &'static str =
+        "New value (`pick <n>` to resolve a conflict, leave empty to quit)";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let pick = input
+            .strip_prefix("pick ")
+            .and_then(|n| n.trim().parse::<usize>().ok());
+        match pick {
+            Some(n) if self.is_conflicted() => {
+                let value = n
+                    .checked_sub(1)
+                    .and_then(|i| self.values().nth(i))
+                    .ok_or_else(|| format!("There's no value number {}", n))?;
+                Ok(Some(self.write(value.clone())))
             }
-            _ => break,
+            _ => Ok(Some(self.write(input.to_string()))),
         }
     }
-    save_operations::<T>(crdt.flush(), project_basedir);
 }
 
-// Crawl through the `operations` folder to find all the user operations folders (the folder name is the user's
-// public key). Then read and apply all the operations within.
-fn restore_operations<T>(crdt: CRDT<T>, project_basedir: &Path) -> CRDT<T>
-where
-    T: Applyable + Serialize + DeserializeOwned,
-    T::Description: Serialize + DeserializeOwned + Ord,
+impl Interactive for OrSet<String> {
+    const PROMPT: &'static str = "`+<element>` to add, `-<element>` to remove, leave empty to quit";
 
-    T: std::fmt::Debug,
-    T::Description: std::fmt::Debug,
-{
-    let operation_dir = project_basedir.join("operations");
-    let mut all_operations: Vec<Operation<T::Description>> = vec![];
-    if operation_dir.exists() {
-        for user_entry in fs::read_dir(&operation_dir).expect(&format!(
-            "Trying to read the '{}' folder, but couldn't open it for whatever reason",
-            operation_dir.to_string_lossy()
-        )) {
-            let user_entry = user_entry.expect(&format!(
-                "ran into an error when reading an entry in the '{}' folder",
-                operation_dir.to_string_lossy()
-            ));
-
-            let path = user_entry.path();
-
-            if path.is_dir() {
-                all_operations.extend(get_operations_in_path::<T>(&path));
-            } else {
-                panic!(
-                    "I only expected directories in {}, but I came across {}, which is a file!",
-                    operation_dir.to_string_lossy(),
-                    path.to_string_lossy()
-                );
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        if let Some(element) = input.strip_prefix('+') {
+            Ok(Some(OrSet::add(element.to_string())))
+        } else if let Some(element) = input.strip_prefix('-') {
+            self.remove(element.to_string())
+                .map(Some)
+                .ok_or_else(|| format!("{} isn't in the set", element))
+        } else {
+            Err("Start with + to add or - to remove".to_string())
+        }
+    }
+}
+
+impl Interactive for GSet<String> {
+    const PROMPT: &'static str = "Element to add (leave empty to quit)";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        if input.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(input.to_string()))
+        }
+    }
+}
+
+impl Interactive for TwoPhaseSet<String> {
+    const PROMPT: &'static str =
+        "`+<element>` to add, `-<element>` to remove for good, leave empty to quit";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        if let Some(element) = input.strip_prefix('+') {
+            self.add(element.to_string())
+                .map(Some)
+                .ok_or_else(|| format!("{} has been removed, so it can't be added again", element))
+        } else if let Some(element) = input.strip_prefix('-') {
+            self.remove(element.to_string())
+                .map(Some)
+                .ok_or_else(|| format!("{} isn't in the set", element))
+        } else {
+            Err("Start with + to add or - to remove".to_string())
+        }
+    }
+}
+
+impl Interactive for Text {
+    const PROMPT: &'static str =
+        "`i <index> <text>` to insert, `d <index> <count>` to delete, leave empty to quit";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let mut parts = input.splitn(3, ' ');
+        let command = parts.next();
+        let index = parts
+            .next()
+            .and_then(|index| index.parse::<usize>().ok())
+            .ok_or("That needs an index")?;
+        let too_far = || format!("The text is only {} characters long", self.len());
+        if index > self.len() {
+            return Err(too_far());
+        }
+        match (command, parts.next()) {
+            (Some("i"), Some(text)) => self.insert(index, text).map(Some).ok_or_else(too_far),
+            (Some("d"), Some(count)) => {
+                let count = count.parse().map_err(|_| "That needs a count")?;
+                Ok(Some(self.delete(index, count)))
             }
+            _ => Err("Start with i to insert or d to delete".to_string()),
         }
-        all_operations.into_iter().fold(crdt, CRDT::apply)
-    } else {
-        crdt
     }
 }
 
-// Read through a user operations directory and return a vector of all the operations within.
-fn get_operations_in_path<T>(base_path: &PathBuf) -> Vec<Operation<T::Description>>
-where
-    T: Applyable + DeserializeOwned,
-    T::Description: DeserializeOwned,
-{
-    let user_pub_key: UserPubKey = {
-        let user_pub_key = base_path.components().into_iter().last().unwrap();
-        let user_pub_key = match user_pub_key {
-            std::path::Component::Normal(osstr) => osstr.to_string_lossy(),
-            _ => panic!(
-                "The last element of {} wasn't a normal part of a path",
-                base_path.to_string_lossy()
-            ),
+// The text is shown as Markdown.
+impl Interactive for RichText {
+    const PROMPT: &'static str = "`i <index> <text>` to insert, `d <index> <count>` to delete, \
+`bold|italic <index> <count>` or `link <index> <count> <url>` to format (put `un` in front to remove it), \
+leave empty to quit";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let mut parts = input.splitn(3, ' ');
+        let command = parts.next().unwrap_or_default();
+        let index = parts
+            .next()
+            .and_then(|index| index.parse::<usize>().ok())
+            .ok_or("That needs an index")?;
+        let too_far = || format!("The text is only {} characters long", self.len());
+        if index > self.len() {
+            return Err(too_far());
+        }
+        let rest = parts.next().ok_or("That needs more arguments")?;
+        if command == "i" {
+            return self.insert(index, rest).map(Some).ok_or_else(too_far);
+        }
+        let mut rest = rest.splitn(2, ' ');
+        let count = rest
+            .next()
+            .and_then(|count| count.parse::<usize>().ok())
+            .ok_or("That needs a count")?;
+        let (remove, mark) = match command.strip_prefix("un") {
+            Some(mark) => (true, mark),
+            None => (false, command),
+        };
+        let mark = match (mark, rest.next()) {
+            ("d", _) if !remove => return Ok(Some(self.delete(index, count))),
+            ("bold", _) => Mark::Bold,
+            ("italic", _) => Mark::Italic,
+            ("link", Some(url)) => Mark::Link(url.to_string()),
+            ("link", None) if remove => Mark::Link(String::new()),
+            _ => return Err(format!("I don't know how to {}", input)),
+        };
+        let desc = if remove {
+            self.unformat(index, count, mark)
+        } else {
+            self.format(index, count, mark)
         };
-        let user_pub_key_decoded = base64::decode_config(user_pub_key.as_bytes(), base64_config())
-            .expect(&format!("{} couldn't be decoded as base64!", user_pub_key));
+        desc.map(Some)
+            .ok_or_else(|| "There's no text there".to_string())
+    }
+}
 
-        bincode::deserialize(&user_pub_key_decoded).expect(&format!(
-            "{} couldn't be converted to a valid public key!",
-            user_pub_key
-        ))
-    };
+impl Interactive for Sequence<String> {
+    const PROMPT: &'static str = "`i <index> <item>` to insert, `d <index>` to delete, \
+`m <from> <to>` to move, leave empty to quit";
 
-    fs::read_dir(&base_path)
-        .expect(&format!(
-            "Trying to read the '{}' folder, but couldn't open it for whatever reason",
-            base_path.to_string_lossy()
-        ))
-        .map(|operation| {
-            let operation_signed: OperationSigned<T::Description> = {
-                let mut operation_bytes = vec![];
-                let operation_path = operation.unwrap().path();
-                let mut file = OpenOptions::new()
-                    .read(true)
-                    .write(false)
-                    .create(false)
-                    .open(&operation_path)
-                    .unwrap();
-                file.read_to_end(&mut operation_bytes).unwrap();
-                bincode::deserialize(&operation_bytes).expect(&format!(
-                    "The file at {} couldn't be decoded into a valid operation!",
-                    operation_path.to_string_lossy()
-                ))
-            };
-            let operation = Operation {
-                user_pub_key,
-                data: operation_signed,
-            };
-            operation
-        })
-        .collect()
-}
-
-// Record some operations to a user's operation folder.
-fn save_operations<T>(
-    mut operations: HashMap<Counter, Operation<T::Description>>,
-    project_basedir: &Path,
-) where
-    T: Applyable + Serialize,
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let mut parts = input.splitn(3, ' ');
+        let command = parts.next();
+        let index = parts
+            .next()
+            .and_then(|index| index.parse::<usize>().ok())
+            .ok_or("That needs an index")?;
+        let too_far = || format!("The list is only {} items long", self.len());
+        match (command, parts.next()) {
+            (Some("i"), Some(item)) => self
+                .insert(index, item.to_string())
+                .map(Some)
+                .ok_or_else(too_far),
+            (Some("d"), None) => self.delete(index).map(Some).ok_or_else(too_far),
+            (Some("m"), Some(to)) => {
+                let to = to
+                    .parse::<usize>()
+                    .map_err(|_| "That needs an index to move to")?;
+                self.move_element(index, to).map(Some).ok_or_else(too_far)
+            }
+            _ => Err("Start with i to insert, d to delete or m to move".to_string()),
+        }
+    }
+}
+
+// Paths look like `todos/0/title`. A value is either JSON, or one of `map`, `list`, `text` or `counter` to make an
+// empty one.
+impl Interactive for JsonDocument {
+    const PROMPT: &'static str = "`set|insert <path> <value>`, `remove <path>`, `add <path> <n>`, \
+`type <path> <index> <text>` or `erase <path> <index> <count>`, leave empty to quit";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let mut parts = input.splitn(3, ' ');
+        let command = parts.next().unwrap_or_default();
+        let path = parts
+            .next()
+            .ok_or("That needs a path")?
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        let rest = parts.next();
+        let value = || match rest {
+            Some("map") => Ok(JsonValue::Map),
+            Some("list") => Ok(JsonValue::List),
+            Some("text") => Ok(JsonValue::Text),
+            Some("counter") => Ok(JsonValue::Counter(0)),
+            Some(json) => serde_json::from_str(json)
+                .map(|json| JsonValue::register(&json))
+                .map_err(|e| format!("That isn't JSON: {}", e)),
+            None => Err("That needs a value".to_string()),
+        };
+        let mut rest_parts = rest.unwrap_or_default().splitn(2, ' ');
+        let mut number = || rest_parts.next().ok_or("That needs a number");
+        let desc = match command {
+            "set" => self.set(&path, value()?),
+            "insert" => self.insert(&path, value()?),
+            "remove" => self.remove(&path),
+            "add" => {
+                let amount = number()?.parse().map_err(|_| "That needs a number")?;
+                self.increment(&path, amount)
+            }
+            "type" => {
+                let index = number()?.parse().map_err(|_| "That needs an index")?;
+                let text = rest_parts.next().ok_or("That needs some text")?;
+                self.insert_text(&path, index, text)
+            }
+            "erase" => {
+                let index = number()?.parse().map_err(|_| "That needs an index")?;
+                let count = number()?.parse().map_err(|_| "That needs a count")?;
+                self.delete_text(&path, index, count)
+            }
+            _ => return Err(format!("I don't know how to {}", command)),
+        };
+        desc.map(Some).map_err(|e| e.to_string())
+    }
+}
+
+// Paths look like `photos/2020`, and `/` is the top.
+impl Interactive for Tree {
+    const PROMPT: &'static str =
+        "`mk <path>` to make a folder, `mv <path> <to path>` to move one, \
+`rn <path> <name>` to rename one, `rm <path>` to delete one, leave empty to quit";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let mut parts = input.splitn(3, ' ');
+        let command = parts.next().unwrap_or_default();
+        let path = parts
+            .next()
+            .ok_or("That needs a path")?
+            .split('/')
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+        let missing = |path: &[&str]| format!("There's no folder at /{}", path.join("/"));
+        let find = |path: &[&str]| self.find(path).ok_or_else(|| missing(path));
+        // Every folder but the top one has a path
+        if path.is_empty() && ["mv", "rn", "rm"].contains(&command) {
+            return Err("The top folder can't be renamed, moved or deleted".to_string());
+        }
+        let desc = match (command, parts.next()) {
+            ("mk", None) => {
+                let (name, parent) = path.split_last().ok_or("That needs a name")?;
+                self.create(find(parent)?, name)
+                    .ok_or_else(|| missing(parent))
+            }
+            ("mv", Some(to)) => {
+                let to = to
+                    .split('/')
+                    .filter(|name| !name.is_empty())
+                    .collect::<Vec<_>>();
+                self.move_node(find(&path)?, find(&to)?)
+                    .ok_or_else(|| "That would put a folder inside itself".to_string())
+            }
+            ("rn", Some(name)) => self
+                .rename(find(&path)?, name)
+                .ok_or_else(|| missing(&path)),
+            ("rm", None) => self.delete(find(&path)?).ok_or_else(|| missing(&path)),
+            _ => return Err("Start with mk, mv, rn or rm".to_string()),
+        };
+        desc.map(Some)
+    }
+}
+
+impl Interactive for Graph<String> {
+    const PROMPT: &'static str =
+        "`+<vertex>` or `-<vertex>` to add or remove a vertex, `+<from> <to>` or `-<from> <to>` to add or remove an \
+edge, leave empty to quit";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let (adding, rest) = if let Some(rest) = input.strip_prefix('+') {
+            (true, rest)
+        } else if let Some(rest) = input.strip_prefix('-') {
+            (false, rest)
+        } else {
+            return Err("Start with + to add or - to remove".to_string());
+        };
+        let desc = match rest.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [vertex] if adding => Some(Graph::add_vertex(vertex.to_string())),
+            [vertex] => self.remove_vertex(vertex.to_string()),
+            [from, to] if adding => self.add_edge(from.to_string(), to.to_string()),
+            [from, to] => self.remove_edge(from.to_string(), to.to_string()),
+            _ => return Err("That needs one vertex, or two for an edge".to_string()),
+        };
+        desc.map(Some)
+            .ok_or_else(|| format!("{} isn't in the graph", rest.trim()))
+    }
+}
+
+// Rows and columns count from 0. `penny csv <project>` prints the whole table as CSV.
+impl Interactive for Table {
+    const PROMPT: &'static str =
+        "`set <row> <column> <text>`, `i|d row|column <index>` to insert or delete, \
+`m row|column <from> <to>` to move, leave empty to quit";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let parts = input.splitn(4, ' ').collect::<Vec<_>>();
+        let number = |i: usize, what: &str| {
+            parts
+                .get(i)
+                .and_then(|n| n.parse::<usize>().ok())
+                .ok_or(format!("That needs a {}", what))
+        };
+        let (rows, columns) = (self.row_count(), self.column_count());
+        let too_far = || format!("The table has {} rows and {} columns", rows, columns);
+        if parts[0] == "set" {
+            let (row, column) = (number(1, "row")?, number(2, "column")?);
+            let text = parts.get(3).unwrap_or(&"").to_string();
+            return self.set(row, column, text).map(Some).ok_or_else(too_far);
+        }
+        if !["i", "d", "m"].contains(&parts[0]) {
+            return Err("Start with set, i, d or m".to_string());
+        }
+        let is_row = match parts.get(1) {
+            Some(&"row") => true,
+            Some(&"column") => false,
+            _ => return Err("Say whether that's a row or a column".to_string()),
+        };
+        let index = number(2, "index")?;
+        let desc = match parts[0] {
+            "i" if is_row => self.insert_row(index),
+            "i" => self.insert_column(index),
+            "d" if is_row => self.delete_row(index),
+            "d" => self.delete_column(index),
+            "m" if is_row => self.move_row(index, number(3, "index to move to")?),
+            _ => self.move_column(index, number(3, "index to move to")?),
+        };
+        desc.map(Some).ok_or_else(too_far)
+    }
+}
+
+impl Interactive for EwFlag {
+    const PROMPT: &'static str = "`on` or `off`, leave empty to quit";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        match input {
+            "" => Ok(None),
+            "on" => Ok(Some(self.enable())),
+            "off" => Ok(Some(self.disable())),
+            _ => Err("It can only be on or off".to_string()),
+        }
+    }
+}
+
+impl Interactive for DwFlag {
+    const PROMPT: &'static str = "`on` or `off`, leave empty to quit";
+
+    fn parse(&self, input: &str) -> Result<Option<Self::Description>, String> {
+        match input {
+            "" => Ok(None),
+            "on" => Ok(Some(self.enable())),
+            "off" => Ok(Some(self.disable())),
+            _ => Err("It can only be on or off".to_string()),
+        }
+    }
+}
+
+// Repeatedly ask the user for a new operation. We'll apply it to the crdt. Once the user exits we'll save
+// all their operations to disk
+fn run<T>(mut crdt: CRDT<T>, mut account: Account, project_basedir: &Path)
+where
+    T: Interactive,
+    T: Serialize,
     T::Description: Serialize,
+    T::Description: Ord,
+
+    T: std::fmt::Display,
+    T: std::fmt::Debug,
+    T::Description: std::fmt::Debug,
 {
-    for (counter, operation) in operations.drain() {
-        let to_write_dir = {
-            let relative_dir = format!(
-                "operations/{}",
-                base64::encode_config(
-                    bincode::serialize(&operation.user_pub_key).unwrap(),
-                    base64_config()
-                )
-            );
-            project_basedir.join(std::path::Path::new(&relative_dir))
-        };
-        fs::create_dir_all(&to_write_dir).expect("Failed to create directory to store operations");
-        let to_write_file_path =
-            to_write_dir.join(std::path::Path::new(&format!("{}.pennyop", counter)));
-        if to_write_file_path.exists() {
-            panic!("Something is messed up... I want to write to {} but it already exists. That's bad! Aborting", to_write_file_path.to_string_lossy());
+    loop {
+        println!("Current value: {}", Red.paint(format!("{}", crdt.value)));
+        print!("{}: ", T::PROMPT);
+        io::stdout().flush().unwrap();
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+        match crdt.value.parse(input.trim()) {
+            Ok(Some(desc)) => {
+                crdt = crdt.apply_desc(&mut account, desc);
+            }
+            Ok(None) => break,
+            Err(e) => println!("{}", e),
         }
-        let mut file = OpenOptions::new()
-            .read(false)
-            .write(true)
-            .create(true)
-            .open(to_write_file_path)
-            .unwrap();
-        file.write_all(
-            &bincode::serialize(&operation.data).expect("somehow there was a serialization error"),
-        )
-        .expect("Failed to write operation");
     }
+    save_operations::<T>(crdt.flush(), project_basedir);
 }
 
 // This contains the information needed to create new operations on the CRDT.
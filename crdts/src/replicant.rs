@@ -6,6 +6,9 @@ use std::collections::HashMap;
 use std::fmt;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+/// Derives `Applyable` for a struct or enum made of other CRDTs. See the `crdts-derive` crate for how it works.
+pub use crdts_derive::Applyable;
+
 pub type Time = Duration;
 pub type UserPubKey = sign::ed25519::PublicKey;
 pub type UserSecKey = sign::ed25519::SecretKey;
@@ -13,6 +16,10 @@ pub type Signature = sign::ed25519::Signature;
 pub type Pun = u32;
 pub type Id = uuid::Uuid;
 
+/// A `StateVector` stores, for every user, the counter of the next operation we expect from them.
+/// Two peers can compare state vectors to figure out which operations the other one is missing.
+pub type StateVector = HashMap<UserPubKey, Counter>;
+
 /// The `Operation` contains all the information needed to apply an operation to a CRDT.
 /// This includes a bunch of useful metadata like when it was created, proof of who created it,
 /// etc.
@@ -30,6 +37,18 @@ pub struct OperationSigned<T> {
     payload: OperationCounted<T>,
 }
 
+impl<T> OperationSigned<T> {
+    /// The counter of this operation. It's unique among all the operations made by the same user.
+    pub fn counter(&self) -> Counter {
+        self.payload.counter
+    }
+
+    /// When this operation was made, according to the clock of the user who made it.
+    pub fn time(&self) -> Time {
+        self.payload.time
+    }
+}
+
 #[derive(Debug, Hash, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
 struct OperationCounted<T> {
     counter: Counter,
@@ -41,15 +60,39 @@ struct OperationCounted<T> {
 enum OperationData<T> {
     Initial,
     Desc(T),
+    /// Undoes the operation of the same user whose counter has the pun `undoes`. `desc` is what undoes it, or
+    /// `None` if there was nothing left to undo (say, because someone else had already changed it again).
+    Undo {
+        undoes: Pun,
+        desc: Option<T>,
+    },
 }
 
 impl<T> OperationData<T> {
     fn is_initial(&self) -> bool {
         match self {
             OperationData::Initial => true,
-            OperationData::Desc(_) => false,
+            OperationData::Desc(_) | OperationData::Undo { .. } => false,
         }
     }
+
+    /// The description to apply, if there is one.
+    fn into_desc(self) -> Option<T> {
+        match self {
+            OperationData::Desc(desc) => Some(desc),
+            OperationData::Undo { desc, .. } => desc,
+            OperationData::Initial => None,
+        }
+    }
+}
+
+impl<T: Serialize> Operation<T> {
+    /// Checks that this operation was really signed by the user it claims to be from.
+    pub fn verify(&self) -> bool {
+        self.data
+            .payload
+            .verify_sig(&self.data.signature, &self.user_pub_key)
+    }
 }
 
 // Convenience functions for signing and verifying operations
@@ -67,6 +110,33 @@ impl<T: Serialize> OperationCounted<T> {
     }
 }
 
+/// Checks the signature of an operation that's been encoded with bincode (the way it's stored on disk),
+/// without needing to know what type its description is. Returns the operation's counter if it really was
+/// signed by `user_pub_key`.
+///
+/// This works because an `OperationSigned` is encoded as its signature followed by its payload, and the
+/// signature is made over exactly those payload bytes.
+pub fn verify_encoded_operation(encoded: &[u8], user_pub_key: &UserPubKey) -> Option<Counter> {
+    let mut payload = encoded;
+    let signature: Signature = bincode::deserialize_from(&mut payload).ok()?;
+    if sign::verify_detached(&signature, payload, user_pub_key) {
+        // The counter is the first thing in the payload, so we can read it without decoding the rest.
+        bincode::deserialize_from(payload).ok()
+    } else {
+        None
+    }
+}
+
+/// Reads the counter and time of an operation that's been encoded with bincode, without needing to know what
+/// type its description is or checking its signature. They're the first two things in the payload.
+pub fn decode_operation_header(encoded: &[u8]) -> Option<(Counter, Time)> {
+    let mut encoded = encoded;
+    let _: Signature = bincode::deserialize_from(&mut encoded).ok()?;
+    let counter = bincode::deserialize_from(&mut encoded).ok()?;
+    let time = bincode::deserialize_from(&mut encoded).ok()?;
+    Some((counter, time))
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct Account {
     user_pub_key: UserPubKey,
@@ -140,6 +210,15 @@ impl Counter {
             Counter::Operation(_, _) => false,
         }
     }
+
+    /// The number of the operation this counter belongs to (the user's first operation is 0). `None` for an
+    /// `Initial` counter, since that doesn't belong to an operation.
+    pub fn pun(&self) -> Option<Pun> {
+        match self {
+            Counter::Initial(_) => None,
+            Counter::Operation(pun, _) => Some(*pun),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
@@ -152,13 +231,28 @@ pub struct CRDT<T: Applyable> {
     // counter is incremented. If it's greater than
     // ours, that means we somehow missed an operation. We'll put it in `notYetAppliedOperations` to
     // apply later in case turns up.
-    state_vector: HashMap<UserPubKey, Counter>,
+    state_vector: StateVector,
     #[serde(bound(
         serialize = "T::Description: Serialize",
         deserialize = "T::Description: Deserialize<'de>"
     ))]
     not_yet_applied_operations:
         HashMap<UserPubKey, HashMap<Counter, OperationSigned<T::Description>>>,
+    // Every operation we've applied, stored per user in the order they were applied. Because a user's
+    // operations are always applied in order, the operation at index `n` is the `n`th operation that user made.
+    // This is what lets us answer "what am I missing?" for a peer that sends us their state vector.
+    #[serde(bound(
+        serialize = "T::Description: Serialize",
+        deserialize = "T::Description: Deserialize<'de>"
+    ))]
+    applied_operations: HashMap<UserPubKey, Vec<OperationSigned<T::Description>>>,
+    // What would undo each operation we've applied, worked out with `Applyable::inverse` just before we applied it.
+    // It's stored per user, by the pun of the operation's counter.
+    #[serde(bound(
+        serialize = "T::Description: Serialize",
+        deserialize = "T::Description: Deserialize<'de>"
+    ))]
+    inverses: HashMap<UserPubKey, HashMap<Pun, T::Description>>,
     recently_created_and_applied_operations: HashMap<Counter, Operation<T::Description>>,
     pub value: T,
 }
@@ -175,14 +269,31 @@ where
 {
     /// Applies an operation description to the CRDT.
     /// This is the same as creating an operation from a description with `create_operation` then applying it with `apply`
-    pub fn apply_desc(mut self, account: &Account, desc: T::Description) -> Self {
+    pub fn apply_desc(self, account: &Account, desc: T::Description) -> Self {
+        self.apply_data(account, OperationData::Desc(desc), now())
+    }
+
+    /// Like `apply_desc`, but the operation says it was made at `time` rather than right now. This is for when you
+    /// need to control the clock, like in a simulation.
+    pub fn apply_desc_at(self, account: &Account, desc: T::Description, time: Time) -> Self {
+        self.apply_data(account, OperationData::Desc(desc), time)
+    }
+
+    // Makes an operation out of `op_data`, then applies it. If this is the user's first operation, we make their
+    // initial operation first.
+    fn apply_data(
+        mut self,
+        account: &Account,
+        op_data: OperationData<T::Description>,
+        time: Time,
+    ) -> Self {
         let counter = self
             .state_vector
             .entry(account.user_pub_key)
             .or_insert(Counter::Initial(self.info.id))
             .clone();
         let (new_crdt, counter) = if counter.is_initial() {
-            let (op, new_counter) = self.create_initial_operation(account);
+            let (op, new_counter) = self.create_initial_operation(account, time);
             let mut new_crdt = self.apply(op.clone());
             new_crdt
                 .recently_created_and_applied_operations
@@ -192,7 +303,7 @@ where
             (self, counter)
         };
 
-        let (op, _) = new_crdt.create_operation_from_description(account, desc, counter);
+        let (op, _) = new_crdt.create_operation(account, op_data, counter, time);
         let mut new_crdt = new_crdt.apply(op.clone());
         new_crdt
             .recently_created_and_applied_operations
@@ -201,117 +312,145 @@ where
     }
 
     /// Applies an operation to the CRDT, verifying the signature and checking to make sure it hasn't already been applied
-    pub fn apply(mut self, op: Operation<T::Description>) -> Self {
+    pub fn apply(self, op: Operation<T::Description>) -> Self {
+        if !op.verify() {
+            panic!(
+                "I couldn't verify that: {:#?}\nwas actually signed by {:?}",
+                &op, &op.user_pub_key
+            )
+        }
+        let (crdt, rejected) = self.apply_checked(op);
+        if let Some(op) = rejected.first() {
+            panic!(
+                "The operation:\n{:?}\nconflicts with one I've already applied. It's possible that someone has tried to rewrite history.",
+                op
+            )
+        }
+        crdt
+    }
+
+    /// Like `apply`, but instead of panicking on an operation we can't accept, we leave it out and hand it back.
+    /// That's an operation that isn't signed by the user it claims to be from, or one that conflicts with an
+    /// operation we've already applied (two different operations with the same counter, which means someone has
+    /// tried to rewrite history). Use this for operations from somewhere you don't trust, like a peer.
+    ///
+    /// The rejected operations might not include `op` itself, since applying it can let us get to operations we'd
+    /// been holding on to until their turn came.
+    pub fn apply_checked(
+        mut self,
+        op: Operation<T::Description>,
+    ) -> (Self, Vec<Operation<T::Description>>) {
         let user_pub_key = op.user_pub_key;
 
         // verify that the message is signed by the person who sent it
         // (to make sure nobody is trying to impersonate them)
-        if op
-            .data
-            .payload
-            .verify_sig(&op.data.signature, &user_pub_key)
-        {
-            // The state vector stores the counter of the next operation we expect from every user.
-            // Let's see what counter we expect for this user.
-            let state_vector_counter = self
-                .state_vector
-                .entry(user_pub_key)
-                .or_insert(Counter::Initial(self.info.id));
-
-            // Let's get the `not_yet_applied_operations` for this user.
-            let not_yet_applied_operations = self
-                .not_yet_applied_operations
-                .entry(user_pub_key)
-                .or_default();
-            // Now, we insert the operation we're currently working on.
-            // This is safe to do because at this point we've already checked the signature
-            not_yet_applied_operations.insert(op.data.payload.counter, op.data);
-
-            // `not_yet_applied_operations` is a hashmap to prevent us from adding two operations
-            // with the same counter. But now it would be convenient if it were a vector, so we
-            // could iterate over it in order.
-            let mut not_yet_applied_operations_ordered = not_yet_applied_operations
-                .drain()
-                .collect::<Vec<(Counter, OperationSigned<T::Description>)>>();
-            not_yet_applied_operations_ordered.sort();
-
-            // Any of the operations we can't do right now, we'll store in the hashmap `operations_cant_do_yet`
-            let mut operations_cant_do_yet: HashMap<Counter, OperationSigned<T::Description>> =
-                HashMap::new();
-
-            // As we iterate over `not_yet_applied_operations`, we are going to be applying the operations to our CRDT's
-            // value. It will "accumulate" the changes from all the operations we do, so let's call the current value the
-            // accumulator.
-            let mut accumulator = self.value;
-
-            // Finally - We iterate over all the operations we still want to do!
-            for (counter, op) in not_yet_applied_operations_ordered {
-                match (counter).partial_cmp(state_vector_counter) {
-                    // If we get an operation who's counter is lower than the one in our state counter, we want to
-                    // ignore it (it is a duplicate)
-                    Some(Less) => {}
-                    // If the operation's counter is greater, that means we're receiving that user's operations
-                    // out of order, and need to store the operation to be applied in the future. We store this in
-                    // `operations_cant_do_yet` to be merged back into `not_yet_applied_operations` later.
-                    Some(Greater) => {
-                        operations_cant_do_yet.insert(counter, op);
-                    }
-                    // If the operation's counter is the same, we want to apply it (and increment that user's
-                    // counter in the state vector)
-                    Some(Equal) => {
-                        state_vector_counter.increment(op.signature);
-                        match op.payload.contents {
-                            OperationData::Initial => {}
-                            OperationData::Desc(desc) => {
-                                accumulator = accumulator.apply_without_idempotency_check(
-                                    desc,
-                                    user_pub_key,
-                                    *state_vector_counter,
-                                );
-                            }
-                        };
+        if !op.verify() {
+            return (self, vec![op]);
+        }
+        let mut rejected = vec![];
+        // The state vector stores the counter of the next operation we expect from every user.
+        // Let's see what counter we expect for this user.
+        let state_vector_counter = self
+            .state_vector
+            .entry(user_pub_key)
+            .or_insert(Counter::Initial(self.info.id));
+
+        // Let's get the `not_yet_applied_operations` for this user.
+        let not_yet_applied_operations = self
+            .not_yet_applied_operations
+            .entry(user_pub_key)
+            .or_default();
+        // Now, we insert the operation we're currently working on.
+        // This is safe to do because at this point we've already checked the signature
+        not_yet_applied_operations.insert(op.data.payload.counter, op.data);
+
+        // `not_yet_applied_operations` is a hashmap to prevent us from adding two operations
+        // with the same counter. But now it would be convenient if it were a vector, so we
+        // could iterate over it in order.
+        let mut not_yet_applied_operations_ordered = not_yet_applied_operations
+            .drain()
+            .collect::<Vec<(Counter, OperationSigned<T::Description>)>>();
+        not_yet_applied_operations_ordered.sort();
+
+        // Any of the operations we can't do right now, we'll store in the hashmap `operations_cant_do_yet`
+        let mut operations_cant_do_yet: HashMap<Counter, OperationSigned<T::Description>> =
+            HashMap::new();
+
+        // As we iterate over `not_yet_applied_operations`, we are going to be applying the operations to our CRDT's
+        // value. It will "accumulate" the changes from all the operations we do, so let's call the current value the
+        // accumulator.
+        let mut accumulator = self.value;
+
+        // Finally - We iterate over all the operations we still want to do!
+        for (counter, op) in not_yet_applied_operations_ordered {
+            match (counter).partial_cmp(state_vector_counter) {
+                // If we get an operation who's counter is lower than the one in our state counter, we want to
+                // ignore it (it is a duplicate)
+                Some(Less) => {}
+                // If the operation's counter is greater, that means we're receiving that user's operations
+                // out of order, and need to store the operation to be applied in the future. We store this in
+                // `operations_cant_do_yet` to be merged back into `not_yet_applied_operations` later.
+                Some(Greater) => {
+                    operations_cant_do_yet.insert(counter, op);
+                }
+                // If the operation's counter is the same, we want to apply it (and increment that user's
+                // counter in the state vector)
+                Some(Equal) => {
+                    state_vector_counter.increment(op.signature);
+                    self.applied_operations
+                        .entry(user_pub_key)
+                        .or_default()
+                        .push(op.clone());
+                    let pun = op.payload.counter.pun();
+                    if let Some(desc) = op.payload.contents.into_desc() {
+                        let inverse =
+                            accumulator.inverse(&desc, user_pub_key, *state_vector_counter);
+                        if let (Some(pun), Some(inverse)) = (pun, inverse) {
+                            self.inverses
+                                .entry(user_pub_key)
+                                .or_default()
+                                .insert(pun, inverse);
+                        }
+                        accumulator = accumulator.apply_without_idempotency_check(
+                            desc,
+                            user_pub_key,
+                            *state_vector_counter,
+                            op.payload.time,
+                        );
                     }
-                    // It's possible that the counter isn't the same, greater, or lesser, because the signature is 
-                    // different. This is probably because someone is trying to rewrite history. I want to have a more
-                    // robust solution here in the future but for now I'm just going to fail.
-                    None => panic!(
-                        "I expected a signature like:\n{:?}\nBut I got:\n{:?}.\nIt's possible that someone has tried to rewrite history.",
-                        counter, state_vector_counter
-                    ),
                 }
+                // It's possible that the counter isn't the same, greater, or lesser, because the signature is
+                // different (or it's the initial operation of a different CRDT). This is probably because someone
+                // is trying to rewrite history, so we leave it out.
+                None => rejected.push(Operation {
+                    user_pub_key,
+                    data: op,
+                }),
             }
-            // Now we set `not_yet_applied_operations` to the `operations_cant_do_yet` list we've been building
-            *not_yet_applied_operations = operations_cant_do_yet;
-            // ...but if it's empty let's just delete the entry from the hashmap to reduce clutter
-            if *not_yet_applied_operations == HashMap::new() {
-                self.not_yet_applied_operations.remove(&user_pub_key);
-            }
-            // Finally, we can return the accumulated CRDT!
+        }
+        // Now we set `not_yet_applied_operations` to the `operations_cant_do_yet` list we've been building
+        *not_yet_applied_operations = operations_cant_do_yet;
+        // ...but if it's empty let's just delete the entry from the hashmap to reduce clutter
+        if *not_yet_applied_operations == HashMap::new() {
+            self.not_yet_applied_operations.remove(&user_pub_key);
+        }
+        // Finally, we can return the accumulated CRDT!
+        (
             CRDT {
                 value: accumulator,
                 ..self
-            }
-        } else {
-            panic!(
-                "I couldn't verify that: {:#?}\nwas actually signed by {:?}",
-                &op, &user_pub_key
-            )
-        }
-    }
-
-    fn create_initial_operation(&self, account: &Account) -> (Operation<T::Description>, Counter) {
-        let id = self.info.id;
-        self.create_operation(account, OperationData::Initial, Counter::Initial(id))
+            },
+            rejected,
+        )
     }
 
-    /// Takes a description and creates an operation
-    fn create_operation_from_description(
+    fn create_initial_operation(
         &self,
         account: &Account,
-        desc: T::Description,
-        counter: Counter,
+        time: Time,
     ) -> (Operation<T::Description>, Counter) {
-        self.create_operation(account, OperationData::Desc(desc), counter)
+        let id = self.info.id;
+        self.create_operation(account, OperationData::Initial, Counter::Initial(id), time)
     }
 
     /// Takes a description and creates an operation
@@ -320,6 +459,7 @@ where
         account: &Account,
         op_data: OperationData<T::Description>,
         mut counter: Counter,
+        time: Time,
     ) -> (Operation<T::Description>, Counter) {
         assert!(
             op_data.is_initial() == counter.is_initial(),
@@ -330,9 +470,7 @@ where
 
         let payload = OperationCounted {
             counter,
-            time: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards"),
+            time,
             contents: op_data,
         };
 
@@ -349,6 +487,61 @@ where
         (op, counter)
     }
 
+    /// Returns the id of the CRDT. Every replica of the same CRDT shares the same id.
+    pub fn id(&self) -> Id {
+        self.info.id
+    }
+
+    /// Returns the state vector, which stores the counter of the next operation we expect from every user.
+    /// Send this to a peer so they can work out which operations you're missing with `ops_since`.
+    pub fn state_vector(&self) -> &StateVector {
+        &self.state_vector
+    }
+
+    /// Returns every operation we've applied that isn't covered by `state_vector` (usually a peer's).
+    /// Applying all of them to the peer's CRDT will bring it up to date with ours.
+    pub fn ops_since<'a>(
+        &'a self,
+        state_vector: &'a StateVector,
+    ) -> impl Iterator<Item = Operation<T::Description>> + 'a {
+        self.applied_operations
+            .iter()
+            .flat_map(move |(user_pub_key, operations)| {
+                let user_pub_key = *user_pub_key;
+                // If the peer has never heard of this user, they're missing all of their operations.
+                // Otherwise they're missing everything from the counter they expect next onwards.
+                let already_seen = match state_vector.get(&user_pub_key) {
+                    None | Some(Counter::Initial(_)) => 0,
+                    Some(Counter::Operation(pun, _)) => *pun as usize + 1,
+                };
+                operations
+                    .iter()
+                    .skip(already_seen)
+                    .map(move |op| Operation {
+                        user_pub_key,
+                        data: op.clone(),
+                    })
+            })
+    }
+
+    /// Every operation that still counts towards `value`, as its user and pun: that's every operation we've applied,
+    /// except the ones that have been undone and the undos (and redos) that cancelled them. We don't keep track of
+    /// which part of the value an operation changed, so one whose effect was overwritten later (like an earlier
+    /// write to an `LwwRegister`) still counts.
+    pub fn contributing_operations(&self) -> impl Iterator<Item = (UserPubKey, Pun)> + '_ {
+        self.applied_operations
+            .iter()
+            .flat_map(|(user_pub_key, operations)| {
+                let undone = undone(operations);
+                let depths = undo_depths(operations);
+                operations
+                    .iter()
+                    .enumerate()
+                    .filter(move |(i, _)| !undone[*i] && depths[*i] == 0)
+                    .filter_map(move |(_, op)| Some((*user_pub_key, op.counter().pun()?)))
+            })
+    }
+
     pub fn flush(&mut self) -> HashMap<Counter, Operation<T::Description>> {
         let mut output = HashMap::new();
         std::mem::swap(
@@ -360,6 +553,115 @@ where
     }
 }
 
+// What time it is, for stamping new operations with.
+fn now() -> Time {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+}
+
+// The counter an operation gets applied with. It's the operation's own counter, incremented by its signature.
+fn applied_counter<D>(op: &OperationSigned<D>) -> Counter {
+    let mut counter = op.payload.counter;
+    counter.increment(op.signature);
+    counter
+}
+
+// Which of a user's operations have been undone. An operation is undone if an undo that hasn't itself been undone
+// points at it. Undos always point backwards, so going from the newest operation to the oldest we always know
+// whether an undo still counts by the time we get to it.
+fn undone<D>(operations: &[OperationSigned<D>]) -> Vec<bool> {
+    let mut undone = vec![false; operations.len()];
+    for i in (0..operations.len()).rev() {
+        if let OperationData::Undo { undoes, .. } = operations[i].payload.contents {
+            // The user's first operation is their initial one, so the operation with pun `n` is at `n + 1`
+            let target = undoes as usize + 1;
+            if !undone[i] && target < i {
+                undone[target] = true;
+            }
+        }
+    }
+    undone
+}
+
+// How far each of a user's operations is from an edit: 0 for an edit, 1 for an undo of one, 2 for a redo, and so on.
+// Operations an even distance away put something back, and the odd ones take something away.
+fn undo_depths<D>(operations: &[OperationSigned<D>]) -> Vec<usize> {
+    let mut depths: Vec<usize> = Vec::with_capacity(operations.len());
+    for op in operations {
+        let depth = match op.payload.contents {
+            OperationData::Undo { undoes, .. } => {
+                depths.get(undoes as usize + 1).map_or(1, |depth| depth + 1)
+            }
+            _ => 0,
+        };
+        depths.push(depth);
+    }
+    depths
+}
+
+/// Undo and redo. You can only undo your own operations, and undoing one only takes back what that operation did:
+/// everyone else's edits, even ones made after it, stay put. Undoing an undo redoes it.
+impl<T> CRDT<T>
+where
+    T: Invertible,
+    T: Serialize,
+    T::Description: Serialize,
+    T::Description: Ord,
+
+    T: std::fmt::Debug,
+    T::Description: std::fmt::Debug,
+{
+    /// Undoes the operation `account` made whose counter has the pun `pun`, by making a new operation that
+    /// points back at it. If there's no such operation, or it's already been undone, nothing happens.
+    pub fn undo(self, account: &Account, pun: Pun) -> Self {
+        let index = pun as usize + 1;
+        let desc = match self.applied_operations.get(&account.user_pub_key) {
+            Some(operations) if index < operations.len() && !undone(operations)[index] => self
+                .inverses
+                .get(&account.user_pub_key)
+                .and_then(|inverses| inverses.get(&pun))
+                .and_then(|inverse| {
+                    self.value.invert(
+                        inverse,
+                        account.user_pub_key,
+                        applied_counter(&operations[index]),
+                    )
+                }),
+            _ => return self,
+        };
+        self.apply_data(account, OperationData::Undo { undoes: pun, desc }, now())
+    }
+
+    /// The pun of the newest edit (or redo) `user_pub_key` made that hasn't been undone. Pass it to `undo` to undo
+    /// their last edit.
+    pub fn last_undoable(&self, user_pub_key: &UserPubKey) -> Option<Pun> {
+        let operations = self.applied_operations.get(user_pub_key)?;
+        let undone = undone(operations);
+        let depths = undo_depths(operations);
+        (1..operations.len())
+            .rev()
+            .find(|&i| !undone[i] && depths[i] % 2 == 0)
+            .and_then(|i| operations[i].counter().pun())
+    }
+
+    /// The pun of the newest undo `user_pub_key` made that hasn't been redone. Pass it to `undo` to redo what it
+    /// undid. Like in any editor, once you make a new edit there's nothing left to redo.
+    pub fn last_redoable(&self, user_pub_key: &UserPubKey) -> Option<Pun> {
+        let operations = self.applied_operations.get(user_pub_key)?;
+        let undone = undone(operations);
+        let depths = undo_depths(operations);
+        for i in (1..operations.len()).rev() {
+            match depths[i] {
+                0 => return None,
+                depth if depth % 2 == 1 && !undone[i] => return operations[i].counter().pun(),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
 pub fn get_random_id() -> Id {
     uuid::Uuid::new_v4()
 }
@@ -382,6 +684,8 @@ pub fn create_crdt<T: Applyable>(info: CRDTInfo<T>) -> CRDT<T> {
     CRDT {
         state_vector: HashMap::new(),
         not_yet_applied_operations: HashMap::new(),
+        applied_operations: HashMap::new(),
+        inverses: HashMap::new(),
         recently_created_and_applied_operations: HashMap::new(),
         value: info.initial_value.clone(),
         info,
@@ -421,12 +725,48 @@ pub trait Applyable: Clone {
     /// You can depend on a user's action never getting applied to this function twice.
     /// Also, if a user does an action, then another action, they will always be applied in that order
     /// (for all peers). But if I do an action and you do an action, the order of application isn't specified.
+    ///
+    /// `time` is when the operation was made, according to the clock of whoever made it. Clocks can be wrong,
+    /// so don't depend on it for anything but breaking ties (like deciding which of two writes wins).
     fn apply_without_idempotency_check(
         self,
         desc: Self::Description,
         user_pub_key: UserPubKey,
         counter: Counter,
+        time: Time,
     ) -> Self;
+
+    /// A description that would undo `desc`, worked out just before `desc` is applied to `self` with `user_pub_key`
+    /// and `counter`. `CRDT` keeps it alongside the operation, so undoing something later doesn't mean going back
+    /// through the whole history. Only types that implement `Invertible` need to write this.
+    fn inverse(
+        &self,
+        _desc: &Self::Description,
+        _user_pub_key: UserPubKey,
+        _counter: Counter,
+    ) -> Option<Self::Description> {
+        None
+    }
+}
+
+/// An `Applyable` whose operations can be undone. `CRDT::undo` uses this, along with the inverse that
+/// `Applyable::inverse` worked out when the operation was applied, to make an operation that takes back what an
+/// earlier one did.
+pub trait Invertible: Applyable {
+    /// What undoing the operation applied with `user_pub_key` and `counter` should do now, given the `inverse` we
+    /// kept for it. `self` is the current value.
+    ///
+    /// The undo has to leave everyone else's edits alone, even ones made after the operation. For example, undoing a
+    /// write to a register that someone has written over since shouldn't change anything. Return `None` if there's
+    /// nothing left to undo. By default the inverse is always used as it is.
+    fn invert(
+        &self,
+        inverse: &Self::Description,
+        _user_pub_key: UserPubKey,
+        _counter: Counter,
+    ) -> Option<Self::Description> {
+        Some(inverse.clone())
+    }
 }
 
 /// Nat is a very simple CRDT. It is just a number that can only go up. If I increment it and you increment it,
@@ -459,6 +799,7 @@ impl Applyable for Nat {
         desc: Self::Description,
         _: UserPubKey,
         _: Counter,
+        _: Time,
     ) -> Self {
         Nat {
             value: self.value.saturating_add(desc),
@@ -523,6 +864,188 @@ mod tests {
         assert_eq!(try1.value.value, vs1.iter().sum::<u32>());
     }
 
+    #[test]
+    fn ops_since_catches_up_a_peer() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(Nat::from(0), get_random_id()));
+
+        let ahead = initial
+            .clone()
+            .apply_desc(&account1, 1)
+            .apply_desc(&account2, 2);
+        let behind = ahead
+            .ops_since(&HashMap::new())
+            .filter(|op| op.user_pub_key == pk1)
+            .fold(initial, CRDT::apply);
+        let ahead = ahead.apply_desc(&account1, 3);
+
+        let missing = ahead.ops_since(behind.state_vector()).collect::<Vec<_>>();
+        // account1's latest operation, plus account2's initial operation and their increment
+        assert_eq!(missing.len(), 3);
+
+        let caught_up = missing.into_iter().fold(behind, CRDT::apply);
+        assert_eq!(caught_up.value, ahead.value);
+        assert_eq!(caught_up.state_vector(), ahead.state_vector());
+        assert_eq!(caught_up.ops_since(ahead.state_vector()).count(), 0);
+    }
+
+    #[test]
+    fn encoded_operations_can_be_verified_without_their_type() {
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+        let mut crdt = create_crdt(create_crdt_info(Nat::from(0), get_random_id()))
+            .apply_desc(&account, 1)
+            .apply_desc(&account, 2);
+
+        for (counter, op) in crdt.flush() {
+            let mut encoded = bincode::serialize(&op.data).unwrap();
+            assert_eq!(verify_encoded_operation(&encoded, &pk), Some(counter));
+            let (other_pk, _) = sign::gen_keypair();
+            assert_eq!(verify_encoded_operation(&encoded, &other_pk), None);
+            *encoded.last_mut().unwrap() ^= 1;
+            assert_eq!(verify_encoded_operation(&encoded, &pk), None);
+        }
+    }
+
+    #[test]
+    fn nat_converges() {
+        crate::testkit::check_applyable(Nat::from(0), any::<u32>());
+    }
+
+    fn merge<T>(into: CRDT<T>, from: &CRDT<T>) -> CRDT<T>
+    where
+        T: Applyable + Serialize + fmt::Debug,
+        T::Description: Serialize + Ord + fmt::Debug,
+    {
+        let missing = from.ops_since(into.state_vector()).collect::<Vec<_>>();
+        missing.into_iter().fold(into, CRDT::apply)
+    }
+
+    #[test]
+    fn apply_checked_leaves_out_rewritten_history() {
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+        let initial = create_crdt(create_crdt_info(Nat::from(0), get_random_id()));
+        let all_ops = |crdt: &CRDT<Nat>| crdt.ops_since(&HashMap::new()).collect::<Vec<_>>();
+
+        // The same user makes two different histories, say by copying the project along with their keys
+        let crdt1 = initial
+            .clone()
+            .apply_desc(&account, 1)
+            .apply_desc(&account, 2);
+        // (which we only notice once it gets further than the history we have)
+        let crdt2 = initial
+            .apply_desc(&account, 10)
+            .apply_desc(&account, 20)
+            .apply_desc(&account, 30);
+        let (crdt1, rejected) =
+            all_ops(&crdt2)
+                .into_iter()
+                .fold((crdt1, vec![]), |(crdt, mut rejected), op| {
+                    let (crdt, mut conflicts) = crdt.apply_checked(op);
+                    rejected.append(&mut conflicts);
+                    (crdt, rejected)
+                });
+        assert_eq!(crdt1.value.value, 3);
+        assert!(!rejected.is_empty());
+
+        // Operations from a different CRDT are left out too
+        let other = create_crdt(create_crdt_info(Nat::from(0), get_random_id()));
+        let (other, rejected) =
+            all_ops(&crdt2)
+                .into_iter()
+                .fold((other, 0), |(crdt, rejected), op| {
+                    let (crdt, conflicts) = crdt.apply_checked(op);
+                    (crdt, rejected + conflicts.len())
+                });
+        assert_eq!(other.value.value, 0);
+        assert!(rejected > 0);
+    }
+
+    #[test]
+    fn undo_and_redo_like_an_editor() {
+        use crate::types::PNCounter;
+
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+        let crdt = create_crdt(create_crdt_info(PNCounter::default(), get_random_id()))
+            .apply_desc(&account, 1)
+            .apply_desc(&account, 10);
+        assert_eq!(crdt.last_redoable(&pk), None);
+
+        let undo = |crdt: CRDT<PNCounter>| {
+            let pun = crdt.last_undoable(&pk).unwrap();
+            crdt.undo(&account, pun)
+        };
+        let redo = |crdt: CRDT<PNCounter>| {
+            let pun = crdt.last_redoable(&pk).unwrap();
+            crdt.undo(&account, pun)
+        };
+
+        let crdt = undo(crdt);
+        assert_eq!(crdt.value.value(), 1);
+        let crdt = undo(crdt);
+        assert_eq!(crdt.value.value(), 0);
+        assert_eq!(crdt.last_undoable(&pk), None);
+        let crdt = redo(crdt);
+        assert_eq!(crdt.value.value(), 1);
+        let crdt = undo(crdt);
+        assert_eq!(crdt.value.value(), 0);
+        let crdt = redo(redo(crdt));
+        assert_eq!(crdt.value.value(), 11);
+        assert_eq!(crdt.last_redoable(&pk), None);
+
+        // Undoing something twice only undoes it once
+        let crdt = undo(crdt);
+        let crdt = crdt.clone().undo(&account, 1).undo(&account, 1);
+        assert_eq!(crdt.value.value(), 1);
+
+        // A new edit means there's nothing to redo any more
+        let crdt = crdt.apply_desc(&account, 100);
+        assert_eq!(crdt.last_redoable(&pk), None);
+        assert_eq!(crdt.value.value(), 101);
+    }
+
+    #[test]
+    fn undo_leaves_other_users_edits_alone() {
+        use crate::types::{LwwRegister, PNCounter};
+
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+
+        // Undoing a counter change takes away just that change
+        let crdt1 = create_crdt(create_crdt_info(PNCounter::default(), get_random_id()))
+            .apply_desc(&account1, 5);
+        let crdt2 = crdt1.clone().apply_desc(&account2, 3);
+        let crdt1 = merge(crdt1, &crdt2);
+        let pun = crdt1.last_undoable(&pk1).unwrap();
+        let crdt1 = crdt1.undo(&account1, pun);
+        assert_eq!(crdt1.value.value(), 3);
+        assert_eq!(crdt1.last_undoable(&pk1), None);
+
+        // Undoing a register write puts back what was there before it
+        let crdt1 = create_crdt(create_crdt_info(
+            LwwRegister::new("a".to_string()),
+            get_random_id(),
+        ))
+        .apply_desc(&account1, "b".to_string());
+        let pun = crdt1.last_undoable(&pk1).unwrap();
+        let undone = crdt1.clone().undo(&account1, pun);
+        assert_eq!(undone.value.value(), "a");
+
+        // ...but not if someone else has written to it since
+        let crdt2 = crdt1.clone().apply_desc(&account2, "c".to_string());
+        let crdt1 = merge(crdt1, &crdt2).undo(&account1, pun);
+        assert_eq!(crdt1.value.value(), "c");
+        let crdt2 = merge(crdt2, &crdt1);
+        assert_eq!(crdt1.value, crdt2.value);
+    }
+
     proptest! {
 
 
@@ -531,16 +1054,16 @@ mod tests {
             if vs1.len() > 0 {
                 let (initial, operations) = {
                     let (pk, sk): (sign::ed25519::PublicKey, sign::ed25519::SecretKey) = sign::gen_keypair();
-                    let mut account = create_account(pk, sk);
+                    let account = create_account(pk, sk);
                     let initial = create_crdt(create_crdt_info(Nat::from(0), get_random_id()));
 
 
                     let mut operations = vec![];
-                    let (op, counter) = initial.create_initial_operation(&mut account);
+                    let (op, counter) = initial.create_initial_operation(&account, now());
                     operations.push(op);
                     let mut counter = counter;
                     for desc in vs1 {
-                        let (op, new_counter) = initial.create_operation_from_description(&mut account, desc, counter);
+                        let (op, new_counter) = initial.create_operation(&account, OperationData::Desc(desc), counter, now());
                         operations.push(op);
                         counter = new_counter;
                     }
@@ -574,15 +1097,15 @@ mod tests {
             if vs1.len() > 0 {
                 let (initial, operations) = {
                     let (pk, sk): (sign::ed25519::PublicKey, sign::ed25519::SecretKey) = sign::gen_keypair();
-                    let mut account = create_account(pk, sk);
+                    let account = create_account(pk, sk);
                     let initial = create_crdt(create_crdt_info(Nat::from(0), get_random_id()));
 
                     let mut operations = vec![];
-                    let (op, counter) = initial.create_initial_operation(&mut account);
+                    let (op, counter) = initial.create_initial_operation(&account, now());
                     operations.push(op);
                     let mut counter = counter;
                     for desc in vs1 {
-                        let (op, new_counter) = initial.create_operation_from_description(&mut account, desc, counter);
+                        let (op, new_counter) = initial.create_operation(&account, OperationData::Desc(desc), counter, now());
                         operations.push(op);
                         counter = new_counter;
                     }
@@ -620,15 +1143,15 @@ mod tests {
             if vs1.len() > 0 {
                 let (initial, operations) = {
                     let (pk, sk): (sign::ed25519::PublicKey, sign::ed25519::SecretKey) = sign::gen_keypair();
-                    let mut account = create_account(pk, sk);
+                    let account = create_account(pk, sk);
                     let initial = create_crdt(create_crdt_info(Nat::from(0), get_random_id()));
 
                     let mut operations = vec![];
-                    let (op, counter) = initial.create_initial_operation(&mut account);
+                    let (op, counter) = initial.create_initial_operation(&account, now());
                     operations.push(op);
                     let mut counter = counter;
                     for desc in vs1 {
-                        let (op, new_counter) = initial.create_operation_from_description(&mut account, desc, counter);
+                        let (op, new_counter) = initial.create_operation(&account, OperationData::Desc(desc), counter, now());
                         operations.push(op);
                         counter = new_counter;
                     }
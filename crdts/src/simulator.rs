@@ -0,0 +1,337 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+use serde::Serialize;
+use sodiumoxide::crypto::sign;
+use std::fmt;
+use std::time::Duration;
+
+use crate::replicant::{
+    create_account, create_crdt, create_crdt_info, Account, Applyable, CRDTInfo, Id, Operation,
+    StateVector, CRDT,
+};
+
+/// How badly behaved the simulated network is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetworkConfig {
+    /// The probability that any given message gets lost.
+    pub loss: f64,
+    /// The probability that any given message gets delivered twice.
+    pub duplication: f64,
+    /// Every message takes somewhere between 1 and `max_delay` steps to arrive. Messages sent at the same time can
+    /// arrive in any order, so this also controls how much reordering there is.
+    pub max_delay: u64,
+}
+
+impl NetworkConfig {
+    /// A network that delivers every message exactly once, in the order they were sent.
+    pub fn reliable() -> Self {
+        NetworkConfig {
+            loss: 0.0,
+            duplication: 0.0,
+            max_delay: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Payload<D> {
+    // "Here's an operation you might not have seen"
+    Operation(Operation<D>),
+    // "This is what I have, please send me anything I'm missing"
+    StateVector(StateVector),
+}
+
+#[derive(Debug, Clone)]
+struct Message<D> {
+    from: usize,
+    to: usize,
+    arrives_at: u64,
+    payload: Payload<D>,
+}
+
+/// A deterministic, in-process network of replicas of the same CRDT, for testing that they converge.
+///
+/// Replicas gossip the way the docs for `Applyable` describe: when a replica makes an operation, or receives one it
+/// hasn't seen before, it sends it to every peer it can reach. The network can lose, duplicate, delay and reorder
+/// those messages, and can be split into partitions that can't talk to each other. Gossip alone can't recover from
+/// a lost message, so replicas can also do a round of `anti_entropy`, where they each send their state vector to a
+/// random peer and get back whatever they're missing.
+///
+/// Everything random (including the users' keys) comes from `seed`, and operations are stamped with the network's
+/// own clock rather than the real time, so a failing run can be replayed exactly.
+pub struct Network<T: Applyable> {
+    info: CRDTInfo<T>,
+    replicas: Vec<CRDT<T>>,
+    accounts: Vec<Account>,
+    // Replicas can only send each other messages if they're in the same partition.
+    partitions: Vec<usize>,
+    in_flight: Vec<Message<T::Description>>,
+    config: NetworkConfig,
+    rng: StdRng,
+    now: u64,
+    edits: u64,
+}
+
+impl<T> Network<T>
+where
+    T: Applyable + Serialize + PartialEq,
+    T::Description: Serialize + Ord,
+
+    T: fmt::Debug,
+    T::Description: fmt::Debug,
+{
+    /// Create a network of `replicas` replicas, all starting from `initial_value`, each with its own user.
+    pub fn new(initial_value: T, replicas: usize, config: NetworkConfig, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let info = create_crdt_info(initial_value, Id::from_bytes(rng.gen()));
+        let accounts = (0..replicas)
+            .map(|_| {
+                let seed = sign::Seed(rng.gen());
+                let (pk, sk) = sign::keypair_from_seed(&seed);
+                create_account(pk, sk)
+            })
+            .collect();
+        Network {
+            replicas: (0..replicas).map(|_| create_crdt(info.clone())).collect(),
+            info,
+            accounts,
+            partitions: vec![0; replicas],
+            in_flight: vec![],
+            config,
+            rng,
+            now: 0,
+            edits: 0,
+        }
+    }
+
+    pub fn replicas(&self) -> &[CRDT<T>] {
+        &self.replicas
+    }
+
+    pub fn accounts(&self) -> &[Account] {
+        &self.accounts
+    }
+
+    /// The number of messages that have been sent but haven't arrived (or been lost) yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Have the user of `replica` make an operation, and gossip it to their peers.
+    pub fn edit(&mut self, replica: usize, desc: T::Description) {
+        let account = self.accounts[replica].clone();
+        // The clock reads a second for every step, plus a nanosecond for every edit so far, so that no two edits
+        // happen at exactly the same time.
+        self.edits += 1;
+        let time = Duration::from_secs(self.now) + Duration::from_nanos(self.edits);
+        let new_operations = self.update(replica, |crdt| crdt.apply_desc_at(&account, desc, time));
+        self.gossip(replica, new_operations);
+    }
+
+    /// Split the network up. Each group can talk amongst itself but not to the others. Any replica not mentioned
+    /// ends up on its own.
+    pub fn partition(&mut self, groups: &[&[usize]]) {
+        for (replica, partition) in self.partitions.iter_mut().enumerate() {
+            *partition = groups
+                .iter()
+                .position(|group| group.contains(&replica))
+                .unwrap_or(groups.len() + replica);
+        }
+    }
+
+    /// Let every replica talk to every other again.
+    pub fn heal(&mut self) {
+        self.partitions = vec![0; self.replicas.len()];
+    }
+
+    /// Move time forward by one step, delivering every message that arrives now.
+    pub fn step(&mut self) {
+        self.now += 1;
+        let now = self.now;
+        let (arriving, in_flight) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition::<Vec<_>, _>(|message| message.arrives_at <= now);
+        self.in_flight = in_flight;
+        for message in arriving {
+            // The network might have been partitioned while the message was on its way
+            if self.partitions[message.from] != self.partitions[message.to] {
+                continue;
+            }
+            match message.payload {
+                Payload::Operation(operation) => {
+                    let new_operations =
+                        self.update(message.to, |crdt| crdt.apply(operation.clone()));
+                    self.gossip(message.to, new_operations);
+                }
+                Payload::StateVector(state_vector) => {
+                    let mut missing = self.replicas[message.to]
+                        .ops_since(&state_vector)
+                        .collect::<Vec<_>>();
+                    missing.sort();
+                    for operation in missing {
+                        self.send(message.to, message.from, Payload::Operation(operation));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Keep stepping until there are no messages left in flight.
+    pub fn run_until_idle(&mut self) {
+        while !self.in_flight.is_empty() {
+            self.step();
+        }
+    }
+
+    /// Every replica sends its state vector to a random peer in its partition, which answers with whatever
+    /// operations it's missing. This is how replicas recover from lost messages.
+    pub fn anti_entropy(&mut self) {
+        for replica in 0..self.replicas.len() {
+            let peers = self.peers(replica);
+            if !peers.is_empty() {
+                let peer = peers[self.rng.gen_range(0, peers.len())];
+                let state_vector = self.replicas[replica].state_vector().clone();
+                self.send(replica, peer, Payload::StateVector(state_vector));
+            }
+        }
+    }
+
+    /// Heal the network and do rounds of gossip and anti-entropy until every replica has converged, giving up
+    /// after `max_rounds`. Returns whether they converged.
+    pub fn settle(&mut self, max_rounds: usize) -> bool {
+        self.heal();
+        for _ in 0..max_rounds {
+            self.run_until_idle();
+            if self.converged() {
+                return true;
+            }
+            self.anti_entropy();
+        }
+        self.run_until_idle();
+        self.converged()
+    }
+
+    /// Whether every replica has applied the same operations and ended up with the same value.
+    pub fn converged(&self) -> bool {
+        self.replicas.windows(2).all(|pair| {
+            pair[0].value == pair[1].value && pair[0].state_vector() == pair[1].state_vector()
+        })
+    }
+
+    // Change a replica, and return the operations it hadn't seen before.
+    fn update(
+        &mut self,
+        replica: usize,
+        f: impl FnOnce(CRDT<T>) -> CRDT<T>,
+    ) -> Vec<Operation<T::Description>> {
+        let placeholder = create_crdt(self.info.clone());
+        let crdt = std::mem::replace(&mut self.replicas[replica], placeholder);
+        let before = crdt.state_vector().clone();
+        self.replicas[replica] = f(crdt);
+        // Sorting keeps the simulation deterministic, since `ops_since` comes out in whatever order a HashMap likes.
+        let mut new_operations = self.replicas[replica]
+            .ops_since(&before)
+            .collect::<Vec<_>>();
+        new_operations.sort();
+        new_operations
+    }
+
+    fn gossip(&mut self, from: usize, operations: Vec<Operation<T::Description>>) {
+        for operation in operations {
+            for peer in self.peers(from) {
+                self.send(from, peer, Payload::Operation(operation.clone()));
+            }
+        }
+    }
+
+    fn peers(&self, replica: usize) -> Vec<usize> {
+        (0..self.replicas.len())
+            .filter(|peer| *peer != replica && self.partitions[*peer] == self.partitions[replica])
+            .collect()
+    }
+
+    fn send(&mut self, from: usize, to: usize, payload: Payload<T::Description>) {
+        if self.rng.gen_bool(self.config.loss) {
+            return;
+        }
+        let copies = if self.rng.gen_bool(self.config.duplication) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let delay = self.rng.gen_range(1, self.config.max_delay.max(1) + 1);
+            self.in_flight.push(Message {
+                from,
+                to,
+                arrives_at: self.now + delay,
+                payload: payload.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::Nat;
+
+    #[test]
+    fn replicas_converge_on_a_lossy_network() {
+        let config = NetworkConfig {
+            loss: 0.3,
+            duplication: 0.3,
+            max_delay: 10,
+        };
+        let mut network = Network::new(Nat::from(0), 5, config, 0);
+        let mut expected = 0;
+        for round in 0..20 {
+            network.edit(round % 5, round as u32);
+            expected += round as u32;
+            network.step();
+        }
+        assert!(network.settle(100));
+        assert!(network
+            .replicas()
+            .iter()
+            .all(|replica| replica.value == Nat::from(expected)));
+    }
+
+    #[test]
+    fn runs_replay_exactly() {
+        use crate::types::LwwRegister;
+
+        let run = || {
+            let config = NetworkConfig {
+                loss: 0.2,
+                duplication: 0.2,
+                max_delay: 5,
+            };
+            let mut network = Network::new(LwwRegister::new(0), 3, config, 7);
+            for round in 0..10 {
+                network.edit(round % 3, round as u32);
+                network.edit((round + 1) % 3, round as u32 + 100);
+                network.step();
+            }
+            assert!(network.settle(50));
+            network.replicas().to_vec()
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn partitions_converge_once_healed() {
+        let mut network = Network::new(Nat::from(0), 4, NetworkConfig::reliable(), 1);
+        network.partition(&[&[0, 1], &[2, 3]]);
+        network.edit(0, 1);
+        network.edit(3, 2);
+        network.run_until_idle();
+        assert_eq!(network.replicas()[1].value, Nat::from(1));
+        assert_eq!(network.replicas()[2].value, Nat::from(2));
+        assert!(!network.converged());
+
+        assert!(network.settle(20));
+        assert_eq!(network.replicas()[0].value, Nat::from(3));
+    }
+}
@@ -0,0 +1,658 @@
+use base64::{CharacterSet, Config};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::replicant::{
+    decode_operation_header, get_random_id, Applyable, CRDTInfo, Counter, Nat, Operation,
+    OperationSigned, Pun, Time, UserPubKey, CRDT,
+};
+
+// We're going to be serializing the operations with bincode, converting them to text with base64,
+// then writing them to disk. This is the base64 config we're going to be using.
+pub fn base64_config() -> Config {
+    Config::new(CharacterSet::UrlSafe, false)
+}
+
+// Create a new project directory containing a `project.penny` file, and record what type of CRDT it holds.
+pub fn create_project<T>(project_basedir: &Path, info: &CRDTInfo<T>)
+where
+    T: Applyable + Serialize,
+{
+    let info = bincode::serialize(info).expect("somehow there was a serialization error");
+    fs::create_dir_all(project_basedir).unwrap();
+    {
+        let mut project_file = File::create(project_basedir.join("project.penny")).unwrap();
+        project_file.write_all(&info).unwrap();
+    }
+    fs::write(project_basedir.join("project.type"), T::NAME).unwrap();
+}
+
+// The type of CRDT a project holds, as its `Applyable::NAME`. We didn't always record this, but back then
+// every project was a `Nat`.
+pub fn read_project_type(project_basedir: &Path) -> String {
+    match fs::read_to_string(project_basedir.join("project.type")) {
+        Ok(project_type) => project_type.trim().to_string(),
+        Err(_) => Nat::NAME.to_string(),
+    }
+}
+
+// Read the `project.penny` file in a project directory, which holds the id and initial value of the CRDT.
+pub fn read_project_info<T>(project_basedir: &Path) -> CRDTInfo<T>
+where
+    T: Applyable + DeserializeOwned,
+{
+    let pennyfile_dir = project_basedir.join("project.penny");
+    let mut file = File::open(&pennyfile_dir).unwrap_or_else(|_| {
+        panic!(
+            "Couldn't open the project file at {}",
+            pennyfile_dir.to_string_lossy()
+        )
+    });
+    let mut contents = vec![];
+    file.read_to_end(&mut contents).unwrap();
+    bincode::deserialize(&contents).unwrap_or_else(|_| {
+        panic!(
+            "The file at {} couldn't be decoded into a valid project!",
+            pennyfile_dir.to_string_lossy()
+        )
+    })
+}
+
+// Crawl through the `operations` folder to find all the user operations folders (the folder name is the user's
+// public key). Then read and apply all the operations within.
+pub fn restore_operations<T>(crdt: CRDT<T>, project_basedir: &Path) -> CRDT<T>
+where
+    T: Applyable + Serialize + DeserializeOwned,
+    T::Description: Serialize + DeserializeOwned + Ord,
+
+    T: std::fmt::Debug,
+    T::Description: std::fmt::Debug,
+{
+    let operation_dir = project_basedir.join("operations");
+    let mut all_operations: Vec<Operation<T::Description>> = vec![];
+    if operation_dir.exists() {
+        for user_entry in fs::read_dir(&operation_dir).expect(&format!(
+            "Trying to read the '{}' folder, but couldn't open it for whatever reason",
+            operation_dir.to_string_lossy()
+        )) {
+            let user_entry = user_entry.expect(&format!(
+                "ran into an error when reading an entry in the '{}' folder",
+                operation_dir.to_string_lossy()
+            ));
+
+            let path = user_entry.path();
+
+            if path.is_dir() {
+                all_operations.extend(get_operations_in_path::<T>(&path));
+            } else {
+                panic!(
+                    "I only expected directories in {}, but I came across {}, which is a file!",
+                    operation_dir.to_string_lossy(),
+                    path.to_string_lossy()
+                );
+            }
+        }
+        all_operations.into_iter().fold(crdt, CRDT::apply)
+    } else {
+        crdt
+    }
+}
+
+// Apply the operations that have been written to a project directory since `crdt` was last brought up to date with
+// it. Unlike `restore_operations`, this only opens files we haven't applied yet: for every user, we look for the
+// operation after the last one of theirs we applied, and stop at the first one that isn't there. Operations that
+// conflict with ones we've already applied are left out and returned.
+pub fn restore_new_operations<T>(
+    mut crdt: CRDT<T>,
+    project_basedir: &Path,
+) -> (CRDT<T>, Vec<Operation<T::Description>>)
+where
+    T: Applyable + Serialize + DeserializeOwned,
+    T::Description: Serialize + DeserializeOwned + Ord,
+
+    T: std::fmt::Debug,
+    T::Description: std::fmt::Debug,
+{
+    let mut rejected = vec![];
+    for user_pub_key in project_users(project_basedir) {
+        let user_dir = user_operations_dir(project_basedir, &user_pub_key);
+        let mut next = match crdt.state_vector().get(&user_pub_key) {
+            None | Some(Counter::Initial(_)) => None,
+            Some(Counter::Operation(pun, _)) => Some(*pun),
+        };
+        loop {
+            let operation_path = user_dir.join(operation_file_name(next));
+            if !operation_path.exists() {
+                break;
+            }
+            let (applied, mut conflicts) =
+                crdt.apply_checked(read_operation_file::<T>(&operation_path, user_pub_key));
+            crdt = applied;
+            rejected.append(&mut conflicts);
+            next = Some(next.map_or(0, |pun| pun + 1));
+        }
+    }
+    (crdt, rejected)
+}
+
+// Read through a user operations directory and return a vector of all the operations within.
+fn get_operations_in_path<T>(base_path: &PathBuf) -> Vec<Operation<T::Description>>
+where
+    T: Applyable + DeserializeOwned,
+    T::Description: DeserializeOwned,
+{
+    let user_pub_key: UserPubKey = {
+        let user_pub_key = base_path.components().into_iter().last().unwrap();
+        let user_pub_key = match user_pub_key {
+            std::path::Component::Normal(osstr) => osstr.to_string_lossy(),
+            _ => panic!(
+                "The last element of {} wasn't a normal part of a path",
+                base_path.to_string_lossy()
+            ),
+        };
+        decode_user_pub_key(&user_pub_key).unwrap_or_else(|e| panic!("{}", e))
+    };
+
+    fs::read_dir(&base_path)
+        .expect(&format!(
+            "Trying to read the '{}' folder, but couldn't open it for whatever reason",
+            base_path.to_string_lossy()
+        ))
+        .map(|operation| operation.unwrap().path())
+        // Anything else is a temporary file that `save_new_operation` didn't get to clean up
+        .filter(|path| path.extension() == Some(OsStr::new("pennyop")))
+        .map(|path| read_operation_file::<T>(&path, user_pub_key))
+        .collect()
+}
+
+// Read the operation in a single file from `user_pub_key`'s operations folder.
+fn read_operation_file<T>(
+    operation_path: &Path,
+    user_pub_key: UserPubKey,
+) -> Operation<T::Description>
+where
+    T: Applyable + DeserializeOwned,
+    T::Description: DeserializeOwned,
+{
+    let operation_signed: OperationSigned<T::Description> = {
+        let mut operation_bytes = vec![];
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(false)
+            .create(false)
+            .open(operation_path)
+            .unwrap();
+        file.read_to_end(&mut operation_bytes).unwrap();
+        bincode::deserialize(&operation_bytes).unwrap_or_else(|_| {
+            panic!(
+                "The file at {} couldn't be decoded into a valid operation!",
+                operation_path.to_string_lossy()
+            )
+        })
+    };
+    Operation {
+        user_pub_key,
+        data: operation_signed,
+    }
+}
+
+// Every user's operations are stored in a folder named after their public key. This gives the name of the folder.
+pub fn encode_user_pub_key(user_pub_key: &UserPubKey) -> String {
+    base64::encode_config(bincode::serialize(user_pub_key).unwrap(), base64_config())
+}
+
+// This turns the name of one of those folders back into the public key.
+pub fn decode_user_pub_key(dir_name: &str) -> Result<UserPubKey, String> {
+    let user_pub_key_decoded = base64::decode_config(dir_name.as_bytes(), base64_config())
+        .map_err(|_| format!("{} couldn't be decoded as base64!", dir_name))?;
+
+    bincode::deserialize(&user_pub_key_decoded)
+        .map_err(|_| format!("{} couldn't be converted to a valid public key!", dir_name))
+}
+
+// Record some operations to a user's operation folder.
+pub fn save_operations<T>(
+    mut operations: HashMap<Counter, Operation<T::Description>>,
+    project_basedir: &Path,
+) where
+    T: Applyable + Serialize,
+    T::Description: Serialize,
+{
+    for (_, operation) in operations.drain() {
+        save_operation::<T>(&operation, project_basedir);
+    }
+}
+
+// Record a single operation to its user's operation folder.
+pub fn save_operation<T>(operation: &Operation<T::Description>, project_basedir: &Path)
+where
+    T: Applyable + Serialize,
+    T::Description: Serialize,
+{
+    if !save_new_operation::<T>(operation, project_basedir) {
+        panic!(
+            "Something is messed up... I want to write to {} but it already exists. That's bad! Aborting",
+            operation_path::<T>(operation, project_basedir).to_string_lossy()
+        );
+    }
+}
+
+// Record an operation unless it's already on disk, and return whether we wrote it. Checking and writing happen in
+// one step, so it's fine for someone else to be saving the same operation at the same time.
+pub fn save_new_operation<T>(operation: &Operation<T::Description>, project_basedir: &Path) -> bool
+where
+    T: Applyable + Serialize,
+    T::Description: Serialize,
+{
+    let to_write_file_path = operation_path::<T>(operation, project_basedir);
+    fs::create_dir_all(to_write_file_path.parent().unwrap())
+        .expect("Failed to create directory to store operations");
+    let bytes =
+        bincode::serialize(&operation.data).expect("somehow there was a serialization error");
+    // Someone else (like the sync daemon) might be reading the operations folder while we write to it, so we
+    // write the operation somewhere else first and then link it into place. That way nobody ever sees half an
+    // operation, and unlike a rename, linking fails if the file is already there. The temporary file goes next to
+    // the operation, so it's on the same filesystem, and it ends in `.tmp` so that readers know to skip it if we
+    // crash before removing it.
+    let temporary_file_path =
+        to_write_file_path.with_file_name(format!(".{}.tmp", get_random_id()));
+    let mut file = OpenOptions::new()
+        .read(false)
+        .write(true)
+        .create_new(true)
+        .open(&temporary_file_path)
+        .unwrap();
+    file.write_all(&bytes).expect("Failed to write operation");
+    let linked = fs::hard_link(&temporary_file_path, &to_write_file_path);
+    let _ = fs::remove_file(&temporary_file_path);
+    match linked {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return false,
+        // Some filesystems (like the FAT ones on USB sticks) can't do hard links. There we write the operation in
+        // place instead, which still never overwrites one that's already there.
+        Err(_) => match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&to_write_file_path)
+        {
+            Ok(mut file) => file.write_all(&bytes).expect("Failed to write operation"),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return false,
+            Err(e) => panic!("Failed to write operation: {}", e),
+        },
+    }
+
+    // If this fails, `operation_index` will notice the index is missing the operation and rebuild it, so there's no
+    // need to give up on saving.
+    if let Some(pun) = operation.data.counter().pun() {
+        let _ = append_to_index(
+            project_basedir,
+            &operation.user_pub_key,
+            pun,
+            operation.data.time(),
+        );
+    }
+    true
+}
+
+// This is where an operation gets stored: `operations/<user public key>/<counter>.pennyop`.
+pub fn operation_path<T>(operation: &Operation<T::Description>, project_basedir: &Path) -> PathBuf
+where
+    T: Applyable,
+{
+    user_operations_dir(project_basedir, &operation.user_pub_key)
+        .join(operation_file_name(operation.data.counter().pun()))
+}
+
+// The name of the file that holds the operation whose counter has the pun `pun`, or the user's initial operation
+// if it's `None`.
+pub fn operation_file_name(pun: Option<Pun>) -> String {
+    match pun {
+        Some(pun) => format!("{:0>6}.pennyop", pun),
+        None => "_initial.pennyop".to_string(),
+    }
+}
+
+fn user_operations_dir(project_basedir: &Path, user_pub_key: &UserPubKey) -> PathBuf {
+    project_basedir
+        .join("operations")
+        .join(encode_user_pub_key(user_pub_key))
+}
+
+/// One of a user's operations, as recorded in the operation index: which operation it is, and when they made it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct IndexedOperation {
+    pub user_pub_key: UserPubKey,
+    /// The pun of the operation's counter (the user's first operation is 0).
+    pub pun: Pun,
+    pub time: Time,
+}
+
+// Every user has an index of their operations in `index/<user public key>.pennyidx`, so we can answer questions
+// about who did what and when without decoding every operation in the project. Each line is the pun of one
+// operation and the time it was made. Initial operations aren't in it, since they don't change anything.
+fn index_path(project_basedir: &Path, user_pub_key: &UserPubKey) -> PathBuf {
+    project_basedir
+        .join("index")
+        .join(format!("{}.pennyidx", encode_user_pub_key(user_pub_key)))
+}
+
+fn index_line(pun: Pun, time: Time) -> String {
+    format!("{} {}.{:09}\n", pun, time.as_secs(), time.subsec_nanos())
+}
+
+fn parse_index_line(line: &str) -> Option<(Pun, Time)> {
+    let mut fields = line.split(' ');
+    let pun = fields.next()?.parse().ok()?;
+    let mut time = fields.next()?.split('.');
+    let secs = time.next()?.parse().ok()?;
+    let nanos = time.next()?.parse().ok()?;
+    if fields.next().is_some() || time.next().is_some() {
+        return None;
+    }
+    Some((pun, Time::new(secs, nanos)))
+}
+
+// Add an operation to the end of its user's index. Appends are small enough that two processes saving operations
+// at once won't mix up each other's lines.
+fn append_to_index(
+    project_basedir: &Path,
+    user_pub_key: &UserPubKey,
+    pun: Pun,
+    time: Time,
+) -> io::Result<()> {
+    let path = index_path(project_basedir, user_pub_key);
+    fs::create_dir_all(path.parent().unwrap())?;
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)?
+        .write_all(index_line(pun, time).as_bytes())
+}
+
+// Read a user's index. `None` if it doesn't exist or has been mangled somehow. If an operation is in there twice
+// (which can happen if the index is rebuilt while someone's appending to it) we only keep it once.
+fn read_index(path: &Path) -> Option<BTreeMap<Pun, Time>> {
+    fs::read_to_string(path)
+        .ok()?
+        .lines()
+        .map(parse_index_line)
+        .collect()
+}
+
+// Make a user's index from scratch by reading the counter and time of each of their operations. Like
+// `restore_new_operations`, we go through them in order and stop at the first one that isn't there.
+fn rebuild_index(project_basedir: &Path, user_pub_key: &UserPubKey) -> BTreeMap<Pun, Time> {
+    let user_dir = user_operations_dir(project_basedir, user_pub_key);
+    let mut index = BTreeMap::new();
+    for pun in 0.. {
+        let path = user_dir.join(operation_file_name(Some(pun)));
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(_) => panic!("Couldn't read the operation at {}", path.to_string_lossy()),
+        };
+        let (_, time) = decode_operation_header(&bytes).unwrap_or_else(|| {
+            panic!(
+                "The file at {} couldn't be decoded into a valid operation!",
+                path.to_string_lossy()
+            )
+        });
+        index.insert(pun, time);
+    }
+
+    // Like with operations, we write the new index somewhere else and move it into place so nobody reads half of it
+    let contents = index
+        .iter()
+        .map(|(pun, time)| index_line(*pun, *time))
+        .collect::<String>();
+    let path = index_path(project_basedir, user_pub_key);
+    let temporary_file_path = path.with_file_name(format!(".{}.tmp", get_random_id()));
+    let written = fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| fs::write(&temporary_file_path, contents))
+        .and_then(|_| fs::rename(&temporary_file_path, &path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary_file_path);
+    }
+    index
+}
+
+/// Every user who has made an operation in the project.
+pub fn project_users(project_basedir: &Path) -> Vec<UserPubKey> {
+    match fs::read_dir(project_basedir.join("operations")) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                decode_user_pub_key(&entry.file_name().to_string_lossy())
+                    .unwrap_or_else(|e| panic!("{}", e))
+            })
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// Every operation `user_pub_key` has made in the project, oldest first.
+///
+/// This comes from the user's index. Operations that arrive some other way than through penny (like through git or
+/// dropbox) don't get added to it. A user's operations are numbered from 0, so the index is up to date as long as it
+/// has every number up to its last one, and there's no operation file for the number after that. If it isn't, we
+/// rebuild it.
+pub fn operation_index(project_basedir: &Path, user_pub_key: &UserPubKey) -> Vec<IndexedOperation> {
+    let is_up_to_date = |index: &BTreeMap<Pun, Time>| {
+        let next = index.len() as Pun;
+        index
+            .keys()
+            .next_back()
+            .is_none_or(|last| *last + 1 == next)
+            && !user_operations_dir(project_basedir, user_pub_key)
+                .join(operation_file_name(Some(next)))
+                .exists()
+    };
+    let index = match read_index(&index_path(project_basedir, user_pub_key)) {
+        Some(index) if is_up_to_date(&index) => index,
+        _ => rebuild_index(project_basedir, user_pub_key),
+    };
+    index
+        .into_iter()
+        .map(|(pun, time)| IndexedOperation {
+            user_pub_key: *user_pub_key,
+            pun,
+            time,
+        })
+        .collect()
+}
+
+/// Read an operation found through the index.
+pub fn read_indexed_operation<T>(
+    project_basedir: &Path,
+    operation: &IndexedOperation,
+) -> Operation<T::Description>
+where
+    T: Applyable + DeserializeOwned,
+    T::Description: DeserializeOwned,
+{
+    read_operation_file::<T>(
+        &user_operations_dir(project_basedir, &operation.user_pub_key)
+            .join(operation_file_name(Some(operation.pun))),
+        operation.user_pub_key,
+    )
+}
+
+/// The operations `user_pub_key` made from `times.start` up to (but not including) `times.end`, oldest first. Only
+/// those operations get read from disk.
+pub fn operations_between<T>(
+    project_basedir: &Path,
+    user_pub_key: &UserPubKey,
+    times: Range<Time>,
+) -> Vec<Operation<T::Description>>
+where
+    T: Applyable + DeserializeOwned,
+    T::Description: DeserializeOwned,
+{
+    operation_index(project_basedir, user_pub_key)
+        .iter()
+        .filter(|operation| times.contains(&operation.time))
+        .map(|operation| read_indexed_operation::<T>(project_basedir, operation))
+        .collect()
+}
+
+/// The operations that went into `crdt.value`, in the order they were made, so you can find out who edited it and
+/// when. Operations that were undone are left out, along with the undos that cancelled them (see
+/// `CRDT::contributing_operations`).
+pub fn contributing_operations<T>(project_basedir: &Path, crdt: &CRDT<T>) -> Vec<IndexedOperation>
+where
+    T: Applyable + Serialize,
+    T::Description: Serialize + Ord,
+
+    T: std::fmt::Debug,
+    T::Description: std::fmt::Debug,
+{
+    let mut contributing: HashMap<UserPubKey, HashSet<Pun>> = HashMap::new();
+    for (user_pub_key, pun) in crdt.contributing_operations() {
+        contributing.entry(user_pub_key).or_default().insert(pun);
+    }
+    let mut operations = contributing
+        .iter()
+        .flat_map(|(user_pub_key, puns)| {
+            operation_index(project_basedir, user_pub_key)
+                .into_iter()
+                .filter(move |operation| puns.contains(&operation.pun))
+        })
+        .collect::<Vec<_>>();
+    operations.sort_by_key(|operation| (operation.time, operation.user_pub_key, operation.pun));
+    operations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{create_account, create_crdt, create_crdt_info};
+    use crate::types::PNCounter;
+    use sodiumoxide::crypto::sign;
+
+    #[test]
+    fn the_index_answers_who_did_what_and_when() {
+        let info = create_crdt_info(Nat::from(0), get_random_id());
+        let basedir = std::env::temp_dir().join(format!("penny-storage-{}", get_random_id()));
+        create_project(&basedir, &info);
+
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+
+        let mut crdt = create_crdt(info)
+            .apply_desc(&account1, 1)
+            .apply_desc(&account2, 2);
+        save_operations::<Nat>(crdt.flush(), &basedir);
+        let earlier = crdt.clone();
+        let mut crdt = crdt.apply_desc(&account1, 3).apply_desc(&account1, 4);
+        save_operations::<Nat>(crdt.flush(), &basedir);
+
+        let mut users = project_users(&basedir);
+        users.sort();
+        let mut expected = vec![pk1, pk2];
+        expected.sort();
+        assert_eq!(users, expected);
+
+        let index1 = operation_index(&basedir, &pk1);
+        assert_eq!(
+            index1.iter().map(|op| op.pun).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        assert_eq!(operation_index(&basedir, &pk2).len(), 1);
+
+        // Only what had happened by then went into the earlier value
+        let contributing = |crdt: &CRDT<Nat>| {
+            contributing_operations(&basedir, crdt)
+                .iter()
+                .map(|op| (op.user_pub_key, op.pun))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(contributing(&earlier), [(pk1, 0), (pk2, 0)]);
+        assert_eq!(contributing(&crdt).len(), 4);
+
+        let between = operations_between::<Nat>(&basedir, &pk1, index1[1].time..index1[2].time);
+        assert_eq!(between.len(), 1);
+        assert_eq!(between[0].data.counter().pun(), Some(1));
+
+        // If the index goes missing or gets mangled, it's rebuilt from the operations
+        fs::remove_dir_all(basedir.join("index")).unwrap();
+        assert_eq!(operation_index(&basedir, &pk1), index1);
+        fs::write(index_path(&basedir, &pk1), "nonsense").unwrap();
+        assert_eq!(operation_index(&basedir, &pk1), index1);
+        // ...and if operations turn up without going through penny (say through git), it's brought up to date
+        fs::write(index_path(&basedir, &pk1), index_line(0, index1[0].time)).unwrap();
+        assert_eq!(operation_index(&basedir, &pk1), index1);
+
+        fs::remove_dir_all(basedir).unwrap();
+    }
+
+    #[test]
+    fn leftover_temporary_files_are_skipped() {
+        let info = create_crdt_info(Nat::from(0), get_random_id());
+        let basedir = std::env::temp_dir().join(format!("penny-storage-{}", get_random_id()));
+        create_project(&basedir, &info);
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+
+        let mut crdt = create_crdt(info).apply_desc(&account, 1);
+        let operations = crdt.flush();
+        for operation in operations.values() {
+            assert!(save_new_operation::<Nat>(operation, &basedir));
+            assert!(!save_new_operation::<Nat>(operation, &basedir));
+        }
+
+        // This is what's left if we crash between writing an operation and linking it into place
+        let user_dir = user_operations_dir(&basedir, &pk);
+        fs::write(user_dir.join(".leftover.tmp"), "half an operation").unwrap();
+        assert_eq!(
+            restore_operations(create_crdt(info), &basedir).value,
+            Nat::from(1)
+        );
+        assert_eq!(
+            restore_new_operations(create_crdt(info), &basedir).0.value,
+            Nat::from(1)
+        );
+        assert_eq!(operation_index(&basedir, &pk).len(), 1);
+
+        fs::remove_dir_all(basedir).unwrap();
+    }
+
+    #[test]
+    fn undone_operations_dont_contribute() {
+        let info = create_crdt_info(PNCounter::default(), get_random_id());
+        let basedir = std::env::temp_dir().join(format!("penny-storage-{}", get_random_id()));
+        create_project(&basedir, &info);
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+
+        // Make three edits, undo the first two, then redo the second
+        let crdt = create_crdt(info)
+            .apply_desc(&account, 1)
+            .apply_desc(&account, 2)
+            .apply_desc(&account, 3)
+            .undo(&account, 0)
+            .undo(&account, 1);
+        let mut crdt = crdt.undo(&account, 4);
+        save_operations::<PNCounter>(crdt.flush(), &basedir);
+        assert_eq!(crdt.value.value(), 5);
+
+        let contributing = contributing_operations(&basedir, &crdt)
+            .iter()
+            .map(|op| op.pun)
+            .collect::<Vec<_>>();
+        assert_eq!(contributing, [1, 2]);
+
+        fs::remove_dir_all(basedir).unwrap();
+    }
+}
@@ -0,0 +1,627 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::io::{BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::replicant::{create_crdt, Applyable, CRDTInfo, Id, Operation, StateVector, CRDT};
+use crate::storage::{read_project_info, restore_new_operations, save_new_operation};
+
+// Nobody should ever need to send us more than this in one message. It stops a misbehaving peer from
+// making us allocate an enormous buffer.
+const MESSAGE_SIZE_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// These are the messages that peers send each other to keep a project in sync.
+///
+/// The protocol is tiny. Whoever opened the connection says which project they want with `Hello`. Then both
+/// sides send their `StateVector`, and each side answers the other's state vector with the `Operations` they're
+/// missing. After that, whenever a peer learns about new operations (because the user wrote some, or because
+/// another peer sent them), it pushes them to everyone it's connected to.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Message<D> {
+    Hello(Id),
+    StateVector(StateVector),
+    Operations(Vec<Operation<D>>),
+}
+
+/// Somewhere a daemon can listen or connect to: either a TCP socket address like `127.0.0.1:7878`, or the path of
+/// a Unix domain socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<SocketAddr>() {
+            Ok(socket_address) => Ok(Address::Tcp(socket_address)),
+            #[cfg(unix)]
+            Err(_) => Ok(Address::Unix(PathBuf::from(s))),
+            #[cfg(not(unix))]
+            Err(_) => Err(format!("{} isn't a valid socket address", s)),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Tcp(socket_address) => write!(f, "{}", socket_address),
+            #[cfg(unix)]
+            Address::Unix(path) => write!(f, "{}", path.to_string_lossy()),
+        }
+    }
+}
+
+// TCP and Unix domain sockets work exactly the same way as far as we're concerned, so we wrap them up
+// to avoid writing everything twice.
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn connect(address: &Address) -> io::Result<Self> {
+        match address {
+            Address::Tcp(socket_address) => {
+                Ok(Connection::Tcp(TcpStream::connect(socket_address)?))
+            }
+            #[cfg(unix)]
+            Address::Unix(path) => Ok(Connection::Unix(UnixStream::connect(path)?)),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Connection::Tcp(stream) => Ok(Connection::Tcp(stream.try_clone()?)),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Ok(Connection::Unix(stream.try_clone()?)),
+        }
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    fn bind(address: &Address) -> io::Result<Self> {
+        match address {
+            Address::Tcp(socket_address) => Ok(Listener::Tcp(TcpListener::bind(socket_address)?)),
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                // A socket left over from a daemon that didn't shut down cleanly would stop us from binding.
+                // We only ever remove sockets though, never regular files!
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if metadata.file_type().is_socket() {
+                        std::fs::remove_file(path)?;
+                    }
+                }
+                Ok(Listener::Unix(UnixListener::bind(path)?, path.clone()))
+            }
+        }
+    }
+
+    fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => Ok(Connection::Tcp(listener.accept()?.0)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => Ok(Connection::Unix(listener.accept()?.0)),
+        }
+    }
+
+    fn local_address(&self) -> io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => Ok(Address::Tcp(listener.local_addr()?)),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(Address::Unix(path.clone())),
+        }
+    }
+}
+
+fn send<D: Serialize>(connection: &mut Connection, message: &Message<D>) -> io::Result<()> {
+    let bytes = bincode::serialize(message).expect("somehow there was a serialization error");
+    connection.write_all(&bytes)?;
+    connection.flush()
+}
+
+fn receive<D: DeserializeOwned>(connection: &mut BufReader<Connection>) -> io::Result<Message<D>> {
+    bincode::config()
+        .limit(MESSAGE_SIZE_LIMIT)
+        .deserialize_from(connection)
+        .map_err(|e| match *e {
+            bincode::ErrorKind::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        })
+}
+
+// Split some operations into `Operations` messages that are each small enough for a peer to accept. An operation
+// that's too big to send on its own gets left out, since nobody would accept it anyway.
+fn operations_messages<D: Serialize>(operations: Vec<Operation<D>>) -> Vec<Message<D>> {
+    batch_operations(operations, MESSAGE_SIZE_LIMIT)
+}
+
+fn batch_operations<D: Serialize>(operations: Vec<Operation<D>>, limit: u64) -> Vec<Message<D>> {
+    let size = |message: &Message<D>| {
+        bincode::serialized_size(message).expect("somehow there was a serialization error")
+    };
+    let header_size = size(&Message::Operations(vec![]));
+    let mut messages = vec![];
+    let mut batch = vec![];
+    let mut batch_size = header_size;
+    for operation in operations {
+        let operation_size =
+            bincode::serialized_size(&operation).expect("somehow there was a serialization error");
+        if header_size + operation_size > limit {
+            continue;
+        }
+        if batch_size + operation_size > limit {
+            messages.push(Message::Operations(std::mem::take(&mut batch)));
+            batch_size = header_size;
+        }
+        batch_size += operation_size;
+        batch.push(operation);
+    }
+    if !batch.is_empty() {
+        messages.push(Message::Operations(batch));
+    }
+    messages
+}
+
+// Where to put messages for a peer. Every peer has its own thread that takes messages from here and writes them to
+// the connection, so nobody ever waits for a peer to read what we sent. Otherwise two peers with lots to send each
+// other could both be stuck writing, with neither of them reading.
+type Peer<D> = Sender<Message<D>>;
+
+// Start the thread that writes a peer's messages to `connection`. If the connection breaks, we shut it down, so the
+// thread reading from it stops too.
+fn start_writing<D>(mut connection: Connection) -> Peer<D>
+where
+    D: Serialize + Send + 'static,
+{
+    let (outbox, messages) = channel::<Message<D>>();
+    thread::spawn(move || {
+        for message in messages {
+            if send(&mut connection, &message).is_err() {
+                let _ = connection.shutdown();
+                return;
+            }
+        }
+    });
+    outbox
+}
+
+// Everything the daemon knows about one of the projects it's serving.
+struct Project<T: Applyable> {
+    basedir: PathBuf,
+    info: CRDTInfo<T>,
+    crdt: CRDT<T>,
+    // Every peer we're syncing this project with.
+    peers: HashMap<usize, Peer<T::Description>>,
+}
+
+impl<T> Project<T>
+where
+    T: Applyable + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    T::Description: Serialize + DeserializeOwned + Ord + fmt::Debug + Send + 'static,
+{
+    // Apply some operations to the CRDT and return the ones we hadn't seen before.
+    fn apply(
+        &mut self,
+        operations: Vec<Operation<T::Description>>,
+    ) -> Vec<Operation<T::Description>> {
+        let before = self.crdt.state_vector().clone();
+        let mut crdt = std::mem::replace(&mut self.crdt, create_crdt(self.info.clone()));
+        // A peer could send us anything, so we use `apply_checked`, which leaves out operations that aren't
+        // properly signed or that try to rewrite history rather than panicking on them.
+        for operation in operations {
+            crdt = crdt.apply_checked(operation).0;
+        }
+        self.crdt = crdt;
+        self.crdt.ops_since(&before).collect()
+    }
+
+    // Operations sent by a peer need to be written to disk, so the user sees them next time they open the project.
+    fn receive(
+        &mut self,
+        operations: Vec<Operation<T::Description>>,
+    ) -> Vec<Operation<T::Description>> {
+        let new_operations = self.apply(operations);
+        for operation in &new_operations {
+            // The user might have written this very operation to disk themselves since we last looked, in which
+            // case there's nothing to do.
+            save_new_operation::<T>(operation, &self.basedir);
+        }
+        new_operations
+    }
+
+    // Look for operations that were written to disk since we last looked (probably by the user running `penny`).
+    fn rescan(&mut self) -> Vec<Operation<T::Description>> {
+        let before = self.crdt.state_vector().clone();
+        let crdt = std::mem::replace(&mut self.crdt, create_crdt(self.info.clone()));
+        self.crdt = restore_new_operations(crdt, &self.basedir).0;
+        self.crdt.ops_since(&before).collect()
+    }
+
+    // Queue up some messages for every peer except `except`, forgetting about any whose connection has broken.
+    fn broadcast(&mut self, except: Option<usize>, messages: Vec<Message<T::Description>>) {
+        self.peers.retain(|peer, outbox| {
+            Some(*peer) == except
+                || messages
+                    .iter()
+                    .all(|message| outbox.send(message.clone()).is_ok())
+        });
+    }
+}
+
+/// The daemon serves a set of project directories to its peers, and keeps them in sync with the peers' copies.
+/// Every project gets synced independently over its own connection.
+pub struct Daemon<T: Applyable> {
+    projects: Arc<HashMap<Id, Arc<Mutex<Project<T>>>>>,
+    next_peer: Arc<AtomicUsize>,
+}
+
+impl<T: Applyable> Clone for Daemon<T> {
+    fn clone(&self) -> Self {
+        Daemon {
+            projects: self.projects.clone(),
+            next_peer: self.next_peer.clone(),
+        }
+    }
+}
+
+impl<T> Daemon<T>
+where
+    T: Applyable + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    T::Description: Serialize + DeserializeOwned + Ord + fmt::Debug + Send + 'static,
+{
+    /// Load every project (the directories containing a `project.penny`) that the daemon is going to serve.
+    pub fn open(project_basedirs: &[PathBuf]) -> Self {
+        let projects = project_basedirs
+            .iter()
+            .map(|basedir| {
+                let info = read_project_info::<T>(basedir);
+                // This reads every user's operations in order, so we never have to hold on to one until the
+                // operations before it turn up.
+                let crdt = restore_new_operations(create_crdt(info.clone()), basedir).0;
+                let project = Project {
+                    basedir: basedir.clone(),
+                    info,
+                    crdt,
+                    peers: HashMap::new(),
+                };
+                (project.crdt.id(), Arc::new(Mutex::new(project)))
+            })
+            .collect();
+        Daemon {
+            projects: Arc::new(projects),
+            next_peer: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Start accepting connections from other daemons in the background.
+    /// Returns the address we're actually listening on, which is handy if you asked for port 0.
+    pub fn listen(&self, address: &Address) -> io::Result<Address> {
+        let listener = Listener::bind(address)?;
+        let local_address = listener.local_address()?;
+        let daemon = self.clone();
+        thread::spawn(move || loop {
+            if let Ok(connection) = listener.accept() {
+                let daemon = daemon.clone();
+                thread::spawn(move || daemon.handle(connection, None));
+            }
+        });
+        Ok(local_address)
+    }
+
+    /// Replicate every project with the daemon at `address` in the background. If we can't reach it, or the
+    /// connection drops, we try again after `retry_interval`.
+    pub fn connect(&self, address: Address, retry_interval: Duration) {
+        for id in self.projects.keys().copied() {
+            let daemon = self.clone();
+            let address = address.clone();
+            thread::spawn(move || loop {
+                if let Ok(connection) = Connection::connect(&address) {
+                    let _ = daemon.handle(connection, Some(id));
+                }
+                thread::sleep(retry_interval);
+            });
+        }
+    }
+
+    /// Check the project directories for new operations every `interval` in the background, and push any we
+    /// find to our peers.
+    pub fn watch(&self, interval: Duration) {
+        let daemon = self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            for project in daemon.projects.values() {
+                let mut project = project.lock().unwrap();
+                let new_operations = project.rescan();
+                project.broadcast(None, operations_messages(new_operations));
+            }
+        });
+    }
+
+    // Talk to a peer until the connection drops. If we opened the connection, `project` is the project we want to
+    // sync. Otherwise the peer will tell us with a `Hello`.
+    fn handle(&self, connection: Connection, project: Option<Id>) -> io::Result<()> {
+        let mut reader = BufReader::new(connection.try_clone()?);
+        let mut writer = connection;
+
+        let id = match project {
+            Some(id) => {
+                send(&mut writer, &Message::<T::Description>::Hello(id))?;
+                id
+            }
+            None => match receive::<T::Description>(&mut reader)? {
+                Message::Hello(id) => id,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "The peer didn't say which project it wanted",
+                    ))
+                }
+            },
+        };
+        let project = self.projects.get(&id).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("The peer asked for project {}, which we don't have", id),
+            )
+        })?;
+
+        let peer = self.next_peer.fetch_add(1, Ordering::SeqCst);
+        let outbox = start_writing(writer);
+        {
+            let mut project = project.lock().unwrap();
+            project.peers.insert(peer, outbox.clone());
+            let state_vector = project.crdt.state_vector().clone();
+            // If this fails the connection has broken, and we'll find out when we try to read from it
+            let _ = outbox.send(Message::StateVector(state_vector));
+        }
+
+        let result = loop {
+            let message = match receive::<T::Description>(&mut reader) {
+                Ok(message) => message,
+                Err(e) => break Err(e),
+            };
+            let mut project = project.lock().unwrap();
+            match message {
+                Message::Hello(_) => {}
+                Message::StateVector(state_vector) => {
+                    let missing = project.crdt.ops_since(&state_vector).collect();
+                    for message in operations_messages(missing) {
+                        let _ = outbox.send(message);
+                    }
+                }
+                Message::Operations(operations) => {
+                    let new_operations = project.receive(operations);
+                    project.broadcast(Some(peer), operations_messages(new_operations));
+                }
+            }
+        };
+        project.lock().unwrap().peers.remove(&peer);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{create_account, create_crdt_info, get_random_id, Nat};
+    use crate::storage::{restore_operations, save_operations};
+    use sodiumoxide::crypto::sign;
+    use std::fs;
+    use std::path::Path;
+
+    fn temporary_project(info: &CRDTInfo<Nat>) -> PathBuf {
+        let basedir = std::env::temp_dir().join(format!("penny-sync-{}", get_random_id()));
+        fs::create_dir_all(&basedir).unwrap();
+        fs::write(
+            basedir.join("project.penny"),
+            bincode::serialize(info).unwrap(),
+        )
+        .unwrap();
+        basedir
+    }
+
+    #[test]
+    fn a_peer_cant_crash_us_by_rewriting_history() {
+        let info = create_crdt_info(Nat::from(0), get_random_id());
+        let basedir = temporary_project(&info);
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+
+        let mut crdt = create_crdt(info)
+            .apply_desc(&account, 1)
+            .apply_desc(&account, 2);
+        save_operations::<Nat>(crdt.flush(), &basedir);
+        let daemon = Daemon::<Nat>::open(std::slice::from_ref(&basedir));
+        let project = daemon.projects.values().next().unwrap().clone();
+
+        // The same user, with a different history
+        let forked = create_crdt(info)
+            .apply_desc(&account, 10)
+            .apply_desc(&account, 20)
+            .apply_desc(&account, 30);
+        let new_operations = project
+            .lock()
+            .unwrap()
+            .receive(forked.ops_since(&HashMap::new()).collect());
+        assert!(new_operations.is_empty());
+        assert_eq!(project.lock().unwrap().crdt.value, Nat::from(3));
+        assert_eq!(
+            restore_operations(create_crdt(info), &basedir).value,
+            Nat::from(3)
+        );
+
+        fs::remove_dir_all(basedir).unwrap();
+    }
+
+    #[test]
+    fn big_replies_are_split_up() {
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+        let crdt = (0..100).fold(
+            create_crdt(create_crdt_info(Nat::from(0), get_random_id())),
+            |crdt, i| crdt.apply_desc(&account, i),
+        );
+        let operations = crdt.ops_since(&HashMap::new()).collect::<Vec<_>>();
+        let messages = operations_messages(operations.clone());
+        assert_eq!(messages, vec![Message::Operations(operations.clone())]);
+
+        // With a smaller limit, they have to be split up
+        let limit = bincode::serialized_size(&messages[0]).unwrap() / 3;
+        let messages = batch_operations(operations.clone(), limit);
+        assert!(messages.len() >= 3);
+        for message in &messages {
+            assert!(bincode::serialized_size(message).unwrap() <= limit);
+        }
+        let rejoined = messages
+            .into_iter()
+            .flat_map(|message| match message {
+                Message::Operations(operations) => operations,
+                _ => vec![],
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rejoined, operations);
+    }
+
+    fn eventually(mut condition: impl FnMut() -> bool) -> bool {
+        (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(50));
+            condition()
+        })
+    }
+
+    #[test]
+    fn two_daemons_replicate_over_loopback() {
+        let interval = Duration::from_millis(20);
+        let info = create_crdt_info(Nat::from(0), get_random_id());
+        let basedir1 = temporary_project(&info);
+        let basedir2 = temporary_project(&info);
+        let value = |basedir: &Path| restore_operations(create_crdt(info), basedir).value;
+
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+
+        // The first user makes a change before either daemon is running...
+        let mut crdt1 = create_crdt(info).apply_desc(&account1, 5);
+        save_operations::<Nat>(crdt1.flush(), &basedir1);
+
+        let daemon1 = Daemon::<Nat>::open(std::slice::from_ref(&basedir1));
+        let address = daemon1.listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        daemon1.watch(interval);
+        let daemon2 = Daemon::<Nat>::open(std::slice::from_ref(&basedir2));
+        daemon2.connect(address, interval);
+        daemon2.watch(interval);
+
+        // ...which the second daemon should pick up as soon as it connects
+        assert!(eventually(|| value(&basedir2) == Nat::from(5)));
+
+        // Then the second user makes a change, which should get pushed to the first daemon
+        let mut crdt2 = restore_operations(create_crdt(info), &basedir2).apply_desc(&account2, 7);
+        save_operations::<Nat>(crdt2.flush(), &basedir2);
+        assert!(eventually(|| value(&basedir1) == Nat::from(12)));
+
+        fs::remove_dir_all(basedir1).unwrap();
+        fs::remove_dir_all(basedir2).unwrap();
+    }
+
+    // Over TCP on loopback the socket buffers can grow to tens of megabytes, so we use a Unix socket, whose buffers
+    // are a few hundred kilobytes. That way a few thousand operations are more than fit in them.
+    #[cfg(unix)]
+    #[test]
+    fn daemons_with_lots_to_send_each_other_dont_get_stuck() {
+        let info = create_crdt_info(Nat::from(0), get_random_id());
+        let operations = 5000;
+        let project_with_lots_of_operations = || {
+            let basedir = temporary_project(&info);
+            let (pk, sk) = sign::gen_keypair();
+            let account = create_account(pk, sk);
+            let mut crdt =
+                (0..operations).fold(create_crdt(info), |crdt, _| crdt.apply_desc(&account, 1));
+            save_operations::<Nat>(crdt.flush(), &basedir);
+            basedir
+        };
+        let basedir1 = project_with_lots_of_operations();
+        let basedir2 = project_with_lots_of_operations();
+        let value = |daemon: &Daemon<Nat>| {
+            let project = daemon.projects.values().next().unwrap();
+            project.lock().unwrap().crdt.value
+        };
+
+        let daemon1 = Daemon::<Nat>::open(std::slice::from_ref(&basedir1));
+        let socket = basedir1.join("daemon.sock");
+        let address = daemon1.listen(&Address::Unix(socket)).unwrap();
+        let daemon2 = Daemon::<Nat>::open(std::slice::from_ref(&basedir2));
+        daemon2.connect(address, Duration::from_secs(60));
+
+        let everything = Nat::from(2 * operations);
+        assert!(eventually(
+            || value(&daemon1) == everything && value(&daemon2) == everything
+        ));
+
+        fs::remove_dir_all(basedir1).unwrap();
+        fs::remove_dir_all(basedir2).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+//! A reusable test harness for anything that implements `Applyable`.
+//!
+//! Writing a CRDT by hand is easy to get subtly wrong, so this checks the properties every `Applyable` needs to
+//! have. Give it an initial value and a proptest `Strategy` that generates descriptions, and it will have several
+//! users make random operations, then check that:
+//!  - every replica ends up with the same value no matter what order the users' operations are interleaved in,
+//!    even when users saw some of each other's operations before making their own
+//!  - operations can arrive out of order, even from the same user
+//!  - applying the same operation more than once doesn't change anything
+//!  - replicas gossiping over a lossy, duplicating, reordering network all converge
+//!
+//! It's behind the `testkit` feature, so add `crdts = { ..., features = ["testkit"] }` to your dev-dependencies.
+//!
+//! Then in a test:
+//!
+//! ```
+//! use crdts::replicant::Nat;
+//! use crdts::testkit;
+//! use proptest::prelude::*;
+//!
+//! testkit::check_applyable(Nat::from(0), any::<u32>());
+//! ```
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestRunner};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rand::SeedableRng;
+use serde::Serialize;
+use sodiumoxide::crypto::sign;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::replicant::{
+    create_account, create_crdt, create_crdt_info, Applyable, Id, Operation, CRDT,
+};
+use crate::simulator::{Network, NetworkConfig};
+
+/// How hard `check_applyable_with` should try.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestKitConfig {
+    /// How many different users make operations.
+    pub users: usize,
+    /// The most operations made in a single test case (by all the users put together).
+    pub max_operations: usize,
+    /// How many random test cases to try.
+    pub cases: u32,
+}
+
+impl Default for TestKitConfig {
+    fn default() -> Self {
+        TestKitConfig {
+            users: 3,
+            max_operations: 20,
+            cases: 64,
+        }
+    }
+}
+
+/// Check that an `Applyable` converges, using the default `TestKitConfig`. Panics with a (shrunk) counterexample
+/// if it doesn't.
+pub fn check_applyable<T, S>(initial_value: T, descriptions: S)
+where
+    T: Applyable + Serialize + PartialEq + fmt::Debug,
+    T::Description: Serialize + Ord + fmt::Debug,
+    S: Strategy<Value = T::Description>,
+{
+    check_applyable_with(TestKitConfig::default(), initial_value, descriptions)
+}
+
+/// Check that an `Applyable` converges. Panics with a (shrunk) counterexample if it doesn't.
+pub fn check_applyable_with<T, S>(config: TestKitConfig, initial_value: T, descriptions: S)
+where
+    T: Applyable + Serialize + PartialEq + fmt::Debug,
+    T::Description: Serialize + Ord + fmt::Debug,
+    S: Strategy<Value = T::Description>,
+{
+    let mut runner = TestRunner::new(Config {
+        cases: config.cases,
+        ..Config::default()
+    });
+    // Each test case is a list of (which user, whose operations they caught up with first, what they did), and a
+    // seed for the users' keys and for shuffling things around. Everything random comes from the test case, so a
+    // failure can be reproduced from the case proptest prints.
+    let strategy = (
+        vec(
+            (
+                0..config.users,
+                prop::option::of(0..config.users),
+                descriptions,
+            ),
+            1..=config.max_operations,
+        ),
+        any::<u64>(),
+    );
+    let result = runner.run(&strategy, |(edits, seed)| {
+        check_case(&initial_value, config.users, edits, seed)
+    });
+    if let Err(e) = result {
+        panic!("{} is not a valid CRDT: {}", T::NAME, e);
+    }
+}
+
+fn check_case<T>(
+    initial_value: &T,
+    users: usize,
+    edits: Vec<(usize, Option<usize>, T::Description)>,
+    seed: u64,
+) -> Result<(), TestCaseError>
+where
+    T: Applyable + Serialize + PartialEq + fmt::Debug,
+    T::Description: Serialize + Ord + fmt::Debug,
+{
+    let mut rng = StdRng::seed_from_u64(seed);
+    let initial = create_crdt(create_crdt_info(
+        initial_value.clone(),
+        Id::from_bytes(rng.gen()),
+    ));
+    let keys = (0..users)
+        .map(|_| sign::keypair_from_seed(&sign::Seed(rng.gen())))
+        .collect::<Vec<_>>();
+    let accounts = keys
+        .iter()
+        .map(|(pk, sk)| create_account(*pk, sk.clone()))
+        .collect::<Vec<_>>();
+
+    // Every user makes their operations on their own replica, sometimes catching up with another user's replica
+    // first. That way some operations are made on top of other users' operations, not just their own. Operations
+    // are stamped with a made-up clock, so anything that depends on time comes out the same every run.
+    let mut replicas = vec![initial.clone(); users];
+    for (time, (user, sync_from, desc)) in edits.iter().enumerate() {
+        if let Some(from) = sync_from {
+            let missing = replicas[*from]
+                .ops_since(replicas[*user].state_vector())
+                .collect::<Vec<_>>();
+            replicas[*user] = missing
+                .into_iter()
+                .fold(replicas[*user].clone(), CRDT::apply);
+        }
+        replicas[*user] = replicas[*user].clone().apply_desc_at(
+            &accounts[*user],
+            desc.clone(),
+            Duration::from_secs(time as u64),
+        );
+    }
+
+    // Afterwards, `ops_since` gives us each user's operations in the order they made them.
+    let mut operations_by_user: HashMap<usize, Vec<Operation<T::Description>>> = HashMap::new();
+    for user in 0..users {
+        let operations = replicas[user]
+            .ops_since(&HashMap::new())
+            .filter(|operation| operation.user_pub_key == keys[user].0)
+            .collect();
+        operations_by_user.insert(user, operations);
+    }
+
+    // We compare the value and the state vector (which tells us every operation got applied), rather than the
+    // whole CRDT, to keep the failure messages readable.
+    let do_all = |operations: Vec<Operation<T::Description>>| {
+        let crdt = operations.into_iter().fold(initial.clone(), CRDT::apply);
+        (crdt.value.clone(), crdt.state_vector().clone())
+    };
+
+    // Every user's operations one after the other, each in the order they were made
+    let in_order = (0..users)
+        .flat_map(|user| operations_by_user[&user].clone())
+        .collect::<Vec<_>>();
+    let expected = do_all(in_order.clone());
+
+    // Interleaved between users, but still in order for each user
+    let interleaved = {
+        let mut queues = (0..users)
+            .map(|user| operations_by_user[&user].clone().into_iter())
+            .collect::<Vec<_>>();
+        let mut interleaved = vec![];
+        while interleaved.len() < in_order.len() {
+            let user = rng.gen_range(0, users);
+            interleaved.extend(queues[user].next());
+        }
+        interleaved
+    };
+    prop_assert_eq!(
+        &expected,
+        &do_all(interleaved),
+        "interleaving users changed the result"
+    );
+
+    // Completely out of order
+    let shuffled = {
+        let mut shuffled = in_order.clone();
+        shuffled.shuffle(&mut rng);
+        shuffled
+    };
+    prop_assert_eq!(
+        &expected,
+        &do_all(shuffled.clone()),
+        "delivering out of order changed the result"
+    );
+
+    // Out of order, with some operations delivered more than once
+    let duplicated = {
+        let amt_to_repeat: usize = rng.gen_range(0, in_order.len() + 1);
+        let mut duplicated = shuffled.clone();
+        duplicated.extend_from_slice(&shuffled[..amt_to_repeat]);
+        duplicated.shuffle(&mut rng);
+        duplicated
+    };
+    prop_assert_eq!(
+        &expected,
+        &do_all(duplicated),
+        "applying operations twice changed the result"
+    );
+
+    // Finally, over a badly behaved network
+    let config = NetworkConfig {
+        loss: 0.2,
+        duplication: 0.2,
+        max_delay: 5,
+    };
+    let mut network = Network::new(initial_value.clone(), users, config, seed);
+    for (user, _, desc) in edits {
+        network.edit(user, desc);
+        network.step();
+    }
+    prop_assert!(network.settle(100), "replicas didn't converge over gossip");
+
+    Ok(())
+}
@@ -0,0 +1,88 @@
+//! The CRDTs that come built in. `Nat` lives in `replicant` since it's the example the rest of the docs use.
+
+pub mod bounded_counter;
+pub mod counter;
+pub mod crdt_map;
+pub mod flag;
+pub mod g_set;
+pub mod graph;
+pub mod json_document;
+pub mod lattice;
+pub mod lww_register;
+pub mod mv_register;
+pub mod or_set;
+pub mod product;
+pub mod rich_text;
+pub mod sequence;
+pub mod table;
+pub mod text;
+pub mod tree;
+pub mod two_phase_set;
+
+pub use bounded_counter::BoundedCounter;
+pub use counter::PNCounter;
+pub use crdt_map::CrdtMap;
+pub use flag::{DwFlag, EwFlag};
+pub use g_set::GSet;
+pub use graph::Graph;
+pub use json_document::JsonDocument;
+pub use lattice::{BoundedLattice, Lattice, MaxRegister, MinRegister, StateBased};
+pub use lww_register::LwwRegister;
+pub use mv_register::MvRegister;
+pub use or_set::OrSet;
+pub use product::{Product2, Product3, Product4, Product5, Product6};
+pub use rich_text::RichText;
+pub use sequence::Sequence;
+pub use table::Table;
+pub use text::Text;
+pub use tree::Tree;
+pub use two_phase_set::TwoPhaseSet;
+
+use std::fmt;
+
+/// Every type a penny project can hold, each listed once as `"name" => Type = initial value,`. The name is what you
+/// pass to `penny init --type`.
+///
+/// Pass it the name of a macro, and it calls that macro with the whole list. That's how `penny` and `penny-daemon`
+/// generate the code that picks a type, so adding a type here is all it takes for both of them to support it.
+#[macro_export]
+macro_rules! project_types {
+    ($callback:ident) => {
+        $callback! {
+            "nat" => $crate::replicant::Nat = $crate::replicant::Nat::from(0),
+            "counter" => $crate::types::PNCounter = $crate::types::PNCounter::default(),
+            "register" => $crate::types::LwwRegister<String> =
+                $crate::types::LwwRegister::new(String::new()),
+            "multi-register" => $crate::types::MvRegister<String> =
+                $crate::types::MvRegister::new(String::new()),
+            "set" => $crate::types::OrSet<String> = $crate::types::OrSet::new(),
+            "grow-only-set" => $crate::types::GSet<String> = $crate::types::GSet::new(),
+            "two-phase-set" => $crate::types::TwoPhaseSet<String> =
+                $crate::types::TwoPhaseSet::new(),
+            "text" => $crate::types::Text = $crate::types::Text::new(),
+            "rich-text" => $crate::types::RichText = $crate::types::RichText::new(),
+            "list" => $crate::types::Sequence<String> = $crate::types::Sequence::new(),
+            "document" => $crate::types::JsonDocument = $crate::types::JsonDocument::new(),
+            "folders" => $crate::types::Tree = $crate::types::Tree::new(),
+            "graph" => $crate::types::Graph<String> = $crate::types::Graph::new(),
+            "table" => $crate::types::Table = $crate::types::Table::new(),
+            "enable-wins-flag" => $crate::types::EwFlag = $crate::types::EwFlag::default(),
+            "disable-wins-flag" => $crate::types::DwFlag = $crate::types::DwFlag::default(),
+        }
+    };
+}
+
+// Writes a set's elements the way you'd write them in maths, like `{a, b, c}`.
+pub(crate) fn write_set<'a, E: fmt::Display + 'a>(
+    f: &mut fmt::Formatter,
+    elements: impl Iterator<Item = &'a E>,
+) -> fmt::Result {
+    write!(f, "{{")?;
+    for (i, element) in elements.enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", element)?;
+    }
+    write!(f, "}}")
+}
@@ -0,0 +1,355 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Time, UserPubKey};
+
+/// BoundedCounter is a counter that can never go below zero, even when users decrement it concurrently while offline.
+///
+/// It works by escrow. Every unit of the counter belongs to one user, and a user can only decrement the counter by
+/// as much as they own (their "rights"). Incrementing the counter gives you rights to what you added, and you can
+/// transfer rights to other users. Since nobody can spend more than they own, the total can't go negative.
+///
+/// The engine only guarantees that each user's operations are applied in the order they were made. So we might see
+/// a user spend rights before we've seen the transfer that gave them those rights. To handle that, every operation
+/// that spends rights records how many rights its user had been given when they made it. We hold on to the operation
+/// (and anything that user did after it) until we've seen that many transfers to them. An operation that spends
+/// more than its user could have had when they made it does nothing, so it never holds up the user's later ones.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct BoundedCounter {
+    increments: BTreeMap<UserPubKey, u128>,
+    decrements: BTreeMap<UserPubKey, u128>,
+    // transfers[from][to] is how many rights `from` has given to `to`
+    transfers: BTreeMap<UserPubKey, BTreeMap<UserPubKey, u128>>,
+    // Operations we can't apply yet because we haven't seen the transfers they rely on, in the order they were made.
+    pending: BTreeMap<UserPubKey, Vec<BoundedCounterDescription>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum BoundedCounterDescription {
+    Increment(u64),
+    // `received` is how many rights had been transferred to the user when they made the operation
+    Decrement {
+        amount: u64,
+        received: u128,
+    },
+    Transfer {
+        to: UserPubKey,
+        amount: u64,
+        received: u128,
+    },
+}
+
+/// The user tried to spend more rights than they have.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct InsufficientRights {
+    pub available: u128,
+    pub requested: u64,
+}
+
+impl fmt::Display for InsufficientRights {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "tried to use {} but only had the rights to {}",
+            self.requested, self.available
+        )
+    }
+}
+
+impl BoundedCounter {
+    pub fn value(&self) -> u128 {
+        self.increments.values().sum::<u128>() - self.decrements.values().sum::<u128>()
+    }
+
+    /// How much `user_pub_key` is allowed to decrement the counter by (or transfer to other users).
+    pub fn rights(&self, user_pub_key: &UserPubKey) -> u128 {
+        self.rights_given(user_pub_key, self.received(user_pub_key))
+    }
+
+    // How many rights other users have transferred to `user_pub_key`
+    fn received(&self, user_pub_key: &UserPubKey) -> u128 {
+        self.transfers
+            .values()
+            .map(|to| to.get(user_pub_key).copied().unwrap_or(0))
+            .sum()
+    }
+
+    // The rights `user_pub_key` would have if they had been given `received` rights by other users. This only
+    // looks at what that user did themself, so every replica agrees on it once it has seen the same operations
+    // from them.
+    fn rights_given(&self, user_pub_key: &UserPubKey, received: u128) -> u128 {
+        let get = |map: &BTreeMap<UserPubKey, u128>| map.get(user_pub_key).copied().unwrap_or(0);
+        let sent: u128 = self
+            .transfers
+            .get(user_pub_key)
+            .map_or(0, |to| to.values().sum());
+        (get(&self.increments) + received).saturating_sub(sent + get(&self.decrements))
+    }
+
+    pub fn increment(amount: u64) -> BoundedCounterDescription {
+        BoundedCounterDescription::Increment(amount)
+    }
+
+    /// Decrement the counter, as long as `user_pub_key` has the rights to.
+    pub fn decrement(
+        &self,
+        user_pub_key: &UserPubKey,
+        amount: u64,
+    ) -> Result<BoundedCounterDescription, InsufficientRights> {
+        self.check_rights(user_pub_key, amount)?;
+        Ok(BoundedCounterDescription::Decrement {
+            amount,
+            received: self.received(user_pub_key),
+        })
+    }
+
+    /// Give some of `from`'s rights to `to`, as long as `from` has them.
+    pub fn transfer(
+        &self,
+        from: &UserPubKey,
+        to: UserPubKey,
+        amount: u64,
+    ) -> Result<BoundedCounterDescription, InsufficientRights> {
+        self.check_rights(from, amount)?;
+        Ok(BoundedCounterDescription::Transfer {
+            to,
+            amount,
+            received: self.received(from),
+        })
+    }
+
+    fn check_rights(
+        &self,
+        user_pub_key: &UserPubKey,
+        amount: u64,
+    ) -> Result<(), InsufficientRights> {
+        let available = self.rights(user_pub_key);
+        if u128::from(amount) <= available {
+            Ok(())
+        } else {
+            Err(InsufficientRights {
+                available,
+                requested: amount,
+            })
+        }
+    }
+
+    // Whether we've seen all the transfers the operation relies on
+    fn is_ready(&self, desc: BoundedCounterDescription, user_pub_key: &UserPubKey) -> bool {
+        match desc {
+            BoundedCounterDescription::Increment(_) => true,
+            BoundedCounterDescription::Decrement { received, .. }
+            | BoundedCounterDescription::Transfer { received, .. } => {
+                self.received(user_pub_key) >= received
+            }
+        }
+    }
+
+    // Applies an operation we're ready for. If the user couldn't have afforded it when they made it, it does
+    // nothing. We decide that from the rights they had then rather than the rights they have now, since the
+    // rights they have now depend on which transfers this replica happens to have seen.
+    fn apply_ready(&mut self, desc: BoundedCounterDescription, user_pub_key: UserPubKey) {
+        match desc {
+            BoundedCounterDescription::Increment(amount) => {
+                *self.increments.entry(user_pub_key).or_insert(0) += u128::from(amount);
+            }
+            BoundedCounterDescription::Decrement { amount, received } => {
+                if u128::from(amount) <= self.rights_given(&user_pub_key, received) {
+                    *self.decrements.entry(user_pub_key).or_insert(0) += u128::from(amount);
+                }
+            }
+            BoundedCounterDescription::Transfer {
+                to,
+                amount,
+                received,
+            } => {
+                if u128::from(amount) <= self.rights_given(&user_pub_key, received) {
+                    *self
+                        .transfers
+                        .entry(user_pub_key)
+                        .or_default()
+                        .entry(to)
+                        .or_insert(0) += u128::from(amount);
+                }
+            }
+        }
+    }
+
+    // Apply as many pending operations as we can. Transfers only ever add to what a user has received, so an
+    // operation that is ready stays that way until we apply it. That means the order we go through the users in
+    // doesn't change where we end up.
+    fn apply_pending(&mut self) {
+        let mut progress = true;
+        while progress {
+            progress = false;
+            let users = self.pending.keys().copied().collect::<Vec<_>>();
+            for user_pub_key in users {
+                let mut queue = self.pending.remove(&user_pub_key).unwrap_or_default();
+                let ready = queue
+                    .iter()
+                    .take_while(|desc| self.is_ready(**desc, &user_pub_key))
+                    .count();
+                for desc in queue.drain(..ready) {
+                    self.apply_ready(desc, user_pub_key);
+                }
+                progress |= ready > 0;
+                if !queue.is_empty() {
+                    self.pending.insert(user_pub_key, queue);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for BoundedCounter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value())
+    }
+}
+
+impl Applyable for BoundedCounter {
+    const NAME: &'static str = "BoundedCounter";
+
+    type Description = BoundedCounterDescription;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        _: Counter,
+        _: Time,
+    ) -> Self {
+        // If this user is already waiting on something, this has to wait too so their operations stay in order
+        self.pending.entry(user_pub_key).or_default().push(desc);
+        self.apply_pending();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{create_account, create_crdt, create_crdt_info, get_random_id, CRDT};
+    use crate::testkit;
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+    use std::collections::HashMap;
+
+    #[test]
+    fn never_goes_below_zero_under_concurrent_edits() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(BoundedCounter::default(), get_random_id()));
+
+        // The first user stocks 10 items and gives the rights to 4 of them to the second user
+        let crdt1 = initial
+            .clone()
+            .apply_desc(&account1, BoundedCounter::increment(10));
+        let transfer = crdt1.value.transfer(&pk1, pk2, 4).unwrap();
+        let mut crdt1 = crdt1.apply_desc(&account1, transfer);
+        assert_eq!(crdt1.value.rights(&pk1), 6);
+        assert!(crdt1.value.decrement(&pk1, 7).is_err());
+
+        // Both users go offline. The second one (who has seen the transfer) uses their 4, the first uses their 6.
+        let mut crdt2 = crdt1
+            .ops_since(&HashMap::new())
+            .fold(initial.clone(), CRDT::apply);
+        let decrement = crdt2.value.decrement(&pk2, 4).unwrap();
+        crdt2 = crdt2.apply_desc(&account2, decrement);
+        let decrement = crdt1.value.decrement(&pk1, 6).unwrap();
+        crdt1 = crdt1.apply_desc(&account1, decrement);
+
+        // A third replica hears from the second user first. It can't apply their decrement until it knows about
+        // the transfer, so the value never goes negative.
+        let only_user2 = crdt2
+            .ops_since(&HashMap::new())
+            .filter(|op| op.user_pub_key == pk2)
+            .fold(initial, CRDT::apply);
+        assert_eq!(only_user2.value.value(), 0);
+        assert_eq!(only_user2.value.rights(&pk2), 0);
+
+        let missing = crdt1
+            .ops_since(only_user2.state_vector())
+            .collect::<Vec<_>>();
+        let everything = missing.into_iter().fold(only_user2, CRDT::apply);
+        assert_eq!(everything.value.value(), 0);
+
+        let missing = crdt2.ops_since(crdt1.state_vector()).collect::<Vec<_>>();
+        let crdt1 = missing.into_iter().fold(crdt1, CRDT::apply);
+        assert_eq!(everything.value, crdt1.value);
+    }
+
+    #[test]
+    fn spending_rights_you_never_had_does_nothing() {
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+        let crdt = create_crdt(create_crdt_info(BoundedCounter::default(), get_random_id()))
+            .apply_desc(
+                &account,
+                BoundedCounterDescription::Decrement {
+                    amount: 1,
+                    received: 0,
+                },
+            )
+            .apply_desc(&account, BoundedCounter::increment(5));
+        // The decrement is dropped, and doesn't hold up the increment that came after it
+        assert_eq!(crdt.value.value(), 5);
+        assert_eq!(crdt.value.rights(&pk), 5);
+    }
+
+    #[test]
+    fn spending_more_than_you_were_given_does_nothing_even_once_you_have_it() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(BoundedCounter::default(), get_random_id()));
+
+        // The second user tries to spend 3 after being given 2, then stocks 1 more
+        let crdt2 = initial
+            .clone()
+            .apply_desc(
+                &account2,
+                BoundedCounterDescription::Decrement {
+                    amount: 3,
+                    received: 2,
+                },
+            )
+            .apply_desc(&account2, BoundedCounter::increment(1));
+        // Meanwhile the first user gives them 5
+        let crdt1 = initial.apply_desc(&account1, BoundedCounter::increment(5));
+        let transfer = crdt1.value.transfer(&pk1, pk2, 5).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, transfer);
+
+        // Whichever order we hear about it in, the decrement is dropped once the transfer it relies on arrives
+        let missing = crdt2.ops_since(crdt1.state_vector()).collect::<Vec<_>>();
+        let merged1 = missing.into_iter().fold(crdt1.clone(), CRDT::apply);
+        let missing = crdt1.ops_since(crdt2.state_vector()).collect::<Vec<_>>();
+        let merged2 = missing.into_iter().fold(crdt2, CRDT::apply);
+        assert_eq!(merged1.value, merged2.value);
+        assert_eq!(merged1.value.value(), 6);
+        assert_eq!(merged1.value.rights(&pk2), 6);
+    }
+
+    #[test]
+    fn bounded_counter_converges() {
+        let (someone_else, _) = sign::gen_keypair();
+        let descriptions = prop_oneof![
+            (0..10u64).prop_map(BoundedCounterDescription::Increment),
+            (0..10u64, 0..10u128).prop_map(|(amount, received)| {
+                BoundedCounterDescription::Decrement { amount, received }
+            }),
+            (0..10u64, 0..10u128).prop_map(move |(amount, received)| {
+                BoundedCounterDescription::Transfer {
+                    to: someone_else,
+                    amount,
+                    received,
+                }
+            }),
+        ];
+        testkit::check_applyable(BoundedCounter::default(), descriptions);
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Invertible, Time, UserPubKey};
+
+/// PNCounter is a counter that can go up and down. If I add 3 and you subtract 5, when we merge
+/// the result will be 2 lower than where we started.
+///
+/// Every user's changes are tallied separately. A single operation can change the counter by anything that
+/// fits in an `i64`, but the tallies are `i128`s, so the value is always exact (you'd need billions of billions
+/// of operations to overflow it).
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct PNCounter {
+    tallies: BTreeMap<UserPubKey, i128>,
+}
+
+impl PNCounter {
+    pub fn value(&self) -> i128 {
+        self.tallies.values().sum()
+    }
+
+    /// How much one particular user has changed the counter by.
+    pub fn tally(&self, user_pub_key: &UserPubKey) -> i128 {
+        self.tallies.get(user_pub_key).copied().unwrap_or(0)
+    }
+}
+
+impl fmt::Display for PNCounter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value())
+    }
+}
+
+impl Applyable for PNCounter {
+    const NAME: &'static str = "PNCounter";
+
+    /// How much to change the counter by. Negative numbers decrement it.
+    type Description = i64;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        _: Counter,
+        _: Time,
+    ) -> Self {
+        *self.tallies.entry(user_pub_key).or_insert(0) += i128::from(desc);
+        self
+    }
+
+    fn inverse(&self, desc: &i64, _: UserPubKey, _: Counter) -> Option<i64> {
+        desc.checked_neg().filter(|by| *by != 0)
+    }
+}
+
+/// Undoing a change changes the counter back by the same amount, so it doesn't matter what anyone else did since.
+impl Invertible for PNCounter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{create_account, create_crdt, create_crdt_info, get_random_id};
+    use crate::testkit;
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+
+    #[test]
+    fn counts_up_and_down_without_overflowing() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+
+        let crdt = create_crdt(create_crdt_info(PNCounter::default(), get_random_id()))
+            .apply_desc(&account1, 3)
+            .apply_desc(&account2, -5);
+        assert_eq!(crdt.value.value(), -2);
+        assert_eq!(crdt.value.tally(&pk2), -5);
+
+        let crdt = crdt
+            .apply_desc(&account1, i64::MAX)
+            .apply_desc(&account2, i64::MAX);
+        assert_eq!(crdt.value.value(), 2 * i128::from(i64::MAX) - 2);
+    }
+
+    #[test]
+    fn pn_counter_converges() {
+        testkit::check_applyable(PNCounter::default(), any::<i64>());
+    }
+}
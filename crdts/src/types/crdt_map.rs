@@ -0,0 +1,370 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Pun, Time, UserPubKey};
+
+/// Identifies one add or update of a key. A user's operations are numbered in the order they made them, so
+/// sorting these puts each user's operations back in order.
+pub type CrdtMapTag = (UserPubKey, Pun);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum CrdtMapDescription<K, D> {
+    /// Add a key holding the map's initial value, if it isn't there already.
+    Add(K),
+    /// Apply a description to the value at a key, adding the key first if it isn't there.
+    Update(K, D),
+    /// Remove a key, undoing the adds and updates with these tags. Make these with `CrdtMap::remove`.
+    Remove { key: K, tags: BTreeSet<CrdtMapTag> },
+}
+
+// Everything we need to apply an update again.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+struct Update<D> {
+    desc: Option<D>,
+    counter: Counter,
+    time: Time,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+struct Entry<V, D> {
+    value: V,
+    updates: BTreeMap<CrdtMapTag, Update<D>>,
+}
+
+/// CrdtMap maps keys to other CRDTs, so you can build bigger types out of smaller ones (a map of counters, a map
+/// of registers, even a map of maps) instead of writing a whole new `Applyable`.
+///
+/// Every key starts out holding the same initial value, and you change it with the descriptions of whatever type
+/// it holds. Removing a key works like an `OrSet`: it only undoes the adds and updates the remover could see. If
+/// someone updates a key at the same time as someone else removes it, the key stays, holding just the updates the
+/// remover didn't know about.
+///
+/// To undo some updates but not others, we keep every update to every key and apply the ones that are left again
+/// whenever something gets removed. So this takes as much space as all the updates put together, not just the
+/// values.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[serde(bound(
+    serialize = "K: Serialize, V: Serialize, V::Description: Serialize",
+    deserialize = "K: Deserialize<'de>, V: Deserialize<'de>, V::Description: Deserialize<'de>"
+))]
+pub struct CrdtMap<K: Ord, V: Applyable> {
+    initial: V,
+    // This says `V::Description` rather than leaving it to `Entry`, so the std derives know they need it too
+    entries: BTreeMap<K, Entry<V, V::Description>>,
+    // A remove can arrive before the update it removes, so we remember the tags of every update that's been removed
+    removed: BTreeSet<CrdtMapTag>,
+}
+
+impl<K: Ord, V: Applyable> CrdtMap<K, V> {
+    /// A map where every key starts out holding `initial`.
+    pub fn new(initial: V) -> Self {
+        CrdtMap {
+            initial,
+            entries: BTreeMap::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+
+    /// The value every key starts out with.
+    pub fn initial(&self) -> &V {
+        &self.initial
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|entry| &entry.value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, entry)| (key, &entry.value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn add(key: K) -> CrdtMapDescription<K, V::Description> {
+        CrdtMapDescription::Add(key)
+    }
+
+    pub fn update(key: K, desc: V::Description) -> CrdtMapDescription<K, V::Description> {
+        CrdtMapDescription::Update(key, desc)
+    }
+
+    /// Update the value at `key`, with a description made from its current value (or the initial value, if the
+    /// key isn't there yet). Handy for types whose descriptions you make from their value, like `Text`.
+    pub fn update_with(
+        &self,
+        key: K,
+        make_desc: impl FnOnce(&V) -> V::Description,
+    ) -> CrdtMapDescription<K, V::Description> {
+        let desc = make_desc(self.get(&key).unwrap_or(&self.initial));
+        CrdtMapDescription::Update(key, desc)
+    }
+
+    /// Remove a key, along with every add and update of it we've seen. `None` if it isn't in the map.
+    pub fn remove(&self, key: K) -> Option<CrdtMapDescription<K, V::Description>> {
+        let tags = self.entries.get(&key)?.updates.keys().copied().collect();
+        Some(CrdtMapDescription::Remove { key, tags })
+    }
+}
+
+impl<K: Ord, V: Applyable + Default> Default for CrdtMap<K, V> {
+    fn default() -> Self {
+        CrdtMap::new(V::default())
+    }
+}
+
+impl<K: Ord + fmt::Display, V: Applyable + fmt::Display> fmt::Display for CrdtMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", key, value)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl<K: Ord + Clone, V: Applyable> Applyable for CrdtMap<K, V> {
+    const NAME: &'static str = "CrdtMap";
+
+    type Description = CrdtMapDescription<K, V::Description>;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        time: Time,
+    ) -> Self {
+        // The counter we get is always an `Operation`, since it's the counter of the operation we're applying
+        let tag = (user_pub_key, counter.pun().unwrap_or_default());
+        let (key, desc) = match desc {
+            CrdtMapDescription::Add(key) => (key, None),
+            CrdtMapDescription::Update(key, desc) => (key, Some(desc)),
+            CrdtMapDescription::Remove { key, tags } => {
+                if let Some(entry) = self.entries.get_mut(&key) {
+                    let before = entry.updates.len();
+                    entry.updates.retain(|tag, _| !tags.contains(tag));
+                    if entry.updates.is_empty() {
+                        self.entries.remove(&key);
+                    } else if entry.updates.len() < before {
+                        // Start over, and apply everything that's left. Sorting by tag keeps each user's updates in
+                        // the order they were made, which is all `apply_without_idempotency_check` asks for.
+                        entry.value = entry
+                            .updates
+                            .iter()
+                            .filter_map(|((user_pub_key, _), update)| {
+                                Some((*user_pub_key, update.desc.clone()?, update))
+                            })
+                            .fold(
+                                self.initial.clone(),
+                                |value, (user_pub_key, desc, update)| {
+                                    value.apply_without_idempotency_check(
+                                        desc,
+                                        user_pub_key,
+                                        update.counter,
+                                        update.time,
+                                    )
+                                },
+                            );
+                    }
+                }
+                self.removed.extend(tags);
+                return self;
+            }
+        };
+        if self.removed.contains(&tag) {
+            return self;
+        }
+        let initial = &self.initial;
+        let entry = self.entries.entry(key).or_insert_with(|| Entry {
+            value: initial.clone(),
+            updates: BTreeMap::new(),
+        });
+        entry.updates.insert(
+            tag,
+            Update {
+                desc: desc.clone(),
+                counter,
+                time,
+            },
+        );
+        if let Some(desc) = desc {
+            entry.value = entry.value.clone().apply_without_idempotency_check(
+                desc,
+                user_pub_key,
+                counter,
+                time,
+            );
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{
+        create_account, create_crdt, create_crdt_info, get_random_id, Account, CRDT,
+    };
+    use crate::testkit;
+    use crate::types::{LwwRegister, PNCounter};
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+
+    type Counters = CrdtMap<u8, PNCounter>;
+
+    fn merge(into: CRDT<Counters>, from: &CRDT<Counters>) -> CRDT<Counters> {
+        let missing = from.ops_since(into.state_vector()).collect::<Vec<_>>();
+        missing.into_iter().fold(into, CRDT::apply)
+    }
+
+    #[test]
+    fn removing_only_undoes_what_the_remover_saw() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(Counters::default(), get_random_id()));
+        let crdt1 = initial
+            .clone()
+            .apply_desc(&account1, Counters::update(1, 5))
+            .apply_desc(&account1, Counters::update(2, 1))
+            .apply_desc(&account1, Counters::add(3));
+        let crdt2 = merge(initial, &crdt1);
+
+        // The first user removes 1 while the second adds 2 to it. Both remove 2, and the second user removes 3.
+        let remove = crdt1.value.remove(1).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, remove);
+        let remove = crdt1.value.remove(2).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, remove);
+        let crdt2 = crdt2.apply_desc(&account2, Counters::update(1, 2));
+        let remove = crdt2.value.remove(2).unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, remove);
+        let remove = crdt2.value.remove(3).unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, remove);
+        assert_eq!(crdt1.value.get(&1), None);
+        assert_eq!(crdt2.value.get(&1).map(PNCounter::value), Some(7));
+
+        let merged1 = merge(crdt1.clone(), &crdt2);
+        let merged2 = merge(crdt2, &crdt1);
+        assert_eq!(merged1.value, merged2.value);
+        // Key 1 only has the update the first user didn't know about
+        assert_eq!(
+            merged1
+                .value
+                .iter()
+                .map(|(key, counter)| (*key, counter.value()))
+                .collect::<Vec<_>>(),
+            vec![(1, 2)]
+        );
+        assert_eq!(format!("{}", merged1.value), "{1: 2}");
+    }
+
+    #[test]
+    fn values_can_be_any_applyable() {
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+        type Owners = CrdtMap<&'static str, LwwRegister<&'static str>>;
+        let crdt = create_crdt(create_crdt_info(
+            Owners::new(LwwRegister::new("nobody")),
+            get_random_id(),
+        ));
+        let crdt = crdt.apply_desc(&account, Owners::update("owner", "alice"));
+        assert_eq!(
+            crdt.value.get(&"owner").map(LwwRegister::value),
+            Some(&"alice")
+        );
+        assert_eq!(crdt.value.get(&"reviewer"), None);
+        let desc = crdt.value.update_with("reviewer", |_| "bob");
+        let crdt = crdt.apply_desc(&account, desc);
+        assert_eq!(
+            crdt.value.get(&"reviewer").map(LwwRegister::value),
+            Some(&"bob")
+        );
+    }
+
+    #[derive(Debug, Clone)]
+    enum Action {
+        Update(u8, i64),
+        Remove(u8),
+        // Catch up on everything another user has
+        SyncFrom(usize),
+    }
+
+    const USERS: usize = 4;
+
+    fn action() -> impl Strategy<Value = (usize, Action)> {
+        let action = prop_oneof![
+            (0..4u8, -10..10i64).prop_map(|(key, by)| Action::Update(key, by)),
+            (0..4u8).prop_map(Action::Remove),
+            (0..USERS).prop_map(Action::SyncFrom),
+        ];
+        (0..USERS, action)
+    }
+
+    proptest! {
+        // Removes have to refer to updates that really happened, so we drive each user's replica ourselves.
+        #[test]
+        fn replicas_converge(actions in prop::collection::vec(action(), 1..40)) {
+            let initial = create_crdt(create_crdt_info(Counters::default(), get_random_id()));
+            let accounts: Vec<Account> = (0..USERS)
+                .map(|_| {
+                    let (pk, sk) = sign::gen_keypair();
+                    create_account(pk, sk)
+                })
+                .collect();
+            let mut replicas = vec![initial; USERS];
+            for (user, action) in actions {
+                let replica = replicas[user].clone();
+                replicas[user] = match action {
+                    Action::Update(key, by) => replica.apply_desc(&accounts[user], Counters::update(key, by)),
+                    Action::Remove(key) => match replica.value.remove(key) {
+                        Some(remove) => {
+                            let replica = replica.apply_desc(&accounts[user], remove);
+                            prop_assert!(!replica.value.contains_key(&key));
+                            replica
+                        }
+                        None => replica,
+                    },
+                    Action::SyncFrom(other) => merge(replica, &replicas[other]),
+                };
+            }
+
+            let forwards = replicas.iter().fold(replicas[0].clone(), merge);
+            let backwards = replicas.iter().rev().fold(replicas[USERS - 1].clone(), merge);
+            prop_assert_eq!(&forwards.value, &backwards.value);
+        }
+    }
+
+    #[test]
+    fn crdt_map_converges() {
+        // Like `OrSet`, these removes mostly refer to updates that never happen
+        let (pk, _) = sign::gen_keypair();
+        let descriptions = prop_oneof![
+            (0..4u8).prop_map(Counters::add),
+            (0..4u8, any::<i64>()).prop_map(|(key, by)| Counters::update(key, by)),
+            (0..4u8, 0..4u32).prop_map(move |(key, pun)| CrdtMapDescription::Remove {
+                key,
+                tags: vec![(pk, pun)].into_iter().collect(),
+            }),
+        ];
+        testkit::check_applyable(Counters::default(), descriptions);
+    }
+}
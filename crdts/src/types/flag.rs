@@ -0,0 +1,218 @@
+//! Flags are booleans that several people can flip at once, like feature toggles or checkboxes in a form. They
+//! differ in what happens when someone turns a flag on at the same time as someone else turns it off: an `EwFlag`
+//! ends up on, and a `DwFlag` ends up off.
+//!
+//! Both are an `MvRegister<bool>` underneath. Every change is tagged by the operation that made it, and replaces
+//! the changes its maker had seen. Any changes left over were made at the same time, and the flag decides which
+//! wins by looking at all of them.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Time, UserPubKey};
+use crate::types::mv_register::MvRegisterWrite;
+use crate::types::MvRegister;
+
+/// Turning a flag on or off. Make these with `enable` and `disable`.
+pub type FlagDescription = MvRegisterWrite<bool>;
+
+/// A flag where turning it on wins over turning it off at the same time.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct EwFlag {
+    register: MvRegister<bool>,
+}
+
+impl EwFlag {
+    pub fn new(value: bool) -> Self {
+        EwFlag {
+            register: MvRegister::new(value),
+        }
+    }
+
+    /// On if anyone turned it on without knowing about every time it was turned off.
+    pub fn value(&self) -> bool {
+        self.register.values().any(|value| *value)
+    }
+
+    pub fn enable(&self) -> FlagDescription {
+        self.register.write(true)
+    }
+
+    pub fn disable(&self) -> FlagDescription {
+        self.register.write(false)
+    }
+}
+
+impl fmt::Display for EwFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", if self.value() { "on" } else { "off" })
+    }
+}
+
+impl Applyable for EwFlag {
+    const NAME: &'static str = "EwFlag";
+
+    type Description = FlagDescription;
+
+    fn apply_without_idempotency_check(
+        self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        time: Time,
+    ) -> Self {
+        EwFlag {
+            register: self.register.apply_without_idempotency_check(
+                desc,
+                user_pub_key,
+                counter,
+                time,
+            ),
+        }
+    }
+}
+
+/// A flag where turning it off wins over turning it on at the same time.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct DwFlag {
+    register: MvRegister<bool>,
+}
+
+impl DwFlag {
+    pub fn new(value: bool) -> Self {
+        DwFlag {
+            register: MvRegister::new(value),
+        }
+    }
+
+    /// Off if anyone turned it off without knowing about every time it was turned on.
+    pub fn value(&self) -> bool {
+        self.register.values().all(|value| *value)
+    }
+
+    pub fn enable(&self) -> FlagDescription {
+        self.register.write(true)
+    }
+
+    pub fn disable(&self) -> FlagDescription {
+        self.register.write(false)
+    }
+}
+
+impl fmt::Display for DwFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", if self.value() { "on" } else { "off" })
+    }
+}
+
+impl Applyable for DwFlag {
+    const NAME: &'static str = "DwFlag";
+
+    type Description = FlagDescription;
+
+    fn apply_without_idempotency_check(
+        self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        time: Time,
+    ) -> Self {
+        DwFlag {
+            register: self.register.apply_without_idempotency_check(
+                desc,
+                user_pub_key,
+                counter,
+                time,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{create_account, create_crdt, create_crdt_info, get_random_id, CRDT};
+    use crate::testkit;
+    use crate::types::mv_register::Tag;
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+    use std::collections::BTreeSet;
+
+    // Starting from `initial`, one user turns the flag on while another turns it off. Returns what everyone ends up
+    // with, after checking they agree.
+    fn flip_concurrently<T>(
+        initial: T,
+        enable: fn(&T) -> FlagDescription,
+        disable: fn(&T) -> FlagDescription,
+    ) -> T
+    where
+        T: Applyable<Description = FlagDescription> + Serialize + PartialEq + fmt::Debug,
+    {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(initial, get_random_id()));
+        let desc = enable(&initial.value);
+        let crdt1 = initial.clone().apply_desc(&account1, desc);
+        let desc = disable(&initial.value);
+        let crdt2 = initial.apply_desc(&account2, desc);
+
+        let missing = crdt2.ops_since(crdt1.state_vector()).collect::<Vec<_>>();
+        let merged1 = missing.into_iter().fold(crdt1.clone(), CRDT::apply);
+        let missing = crdt1.ops_since(crdt2.state_vector()).collect::<Vec<_>>();
+        let merged2 = missing.into_iter().fold(crdt2, CRDT::apply);
+        assert_eq!(merged1.value, merged2.value);
+        merged1.value
+    }
+
+    #[test]
+    fn enable_wins() {
+        for initial in [false, true].iter() {
+            let flag = flip_concurrently(EwFlag::new(*initial), EwFlag::enable, EwFlag::disable);
+            assert!(flag.value());
+        }
+    }
+
+    #[test]
+    fn disable_wins() {
+        for initial in [false, true].iter() {
+            let flag = flip_concurrently(DwFlag::new(*initial), DwFlag::enable, DwFlag::disable);
+            assert!(!flag.value());
+        }
+    }
+
+    #[test]
+    fn a_later_change_wins_either_way() {
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+        let crdt = create_crdt(create_crdt_info(DwFlag::new(true), get_random_id()));
+        let desc = crdt.value.disable();
+        let crdt = crdt.apply_desc(&account, desc);
+        let desc = crdt.value.enable();
+        let crdt = crdt.apply_desc(&account, desc);
+        assert!(crdt.value.value());
+        assert_eq!(format!("{}", crdt.value), "on");
+    }
+
+    fn flag_descriptions() -> impl Strategy<Value = FlagDescription> {
+        (any::<bool>(), any::<bool>()).prop_map(|(value, over_initial)| MvRegisterWrite {
+            value,
+            supersedes: if over_initial {
+                vec![Tag::Initial].into_iter().collect()
+            } else {
+                BTreeSet::new()
+            },
+        })
+    }
+
+    #[test]
+    fn ew_flag_converges() {
+        testkit::check_applyable(EwFlag::new(false), flag_descriptions());
+    }
+
+    #[test]
+    fn dw_flag_converges() {
+        testkit::check_applyable(DwFlag::new(true), flag_descriptions());
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Time, UserPubKey};
+use crate::types::write_set;
+
+/// GSet is a grow-only set. Elements can be added but never removed, which is what makes it so simple: adding the
+/// same things in any order gives the same set.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct GSet<E: Ord> {
+    elements: BTreeSet<E>,
+}
+
+impl<E: Ord> GSet<E> {
+    pub fn new() -> Self {
+        GSet {
+            elements: BTreeSet::new(),
+        }
+    }
+
+    pub fn contains(&self, element: &E) -> bool {
+        self.elements.contains(element)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &E> {
+        self.elements.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+}
+
+impl<E: Ord> Default for GSet<E> {
+    fn default() -> Self {
+        GSet::new()
+    }
+}
+
+impl<E: Ord + fmt::Display> fmt::Display for GSet<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_set(f, self.iter())
+    }
+}
+
+impl<E: Ord + Clone> Applyable for GSet<E> {
+    const NAME: &'static str = "GSet";
+
+    /// The element to add.
+    type Description = E;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        _: UserPubKey,
+        _: Counter,
+        _: Time,
+    ) -> Self {
+        self.elements.insert(desc);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit;
+    use proptest::prelude::*;
+
+    #[test]
+    fn g_set_converges() {
+        testkit::check_applyable(GSet::new(), any::<u8>());
+    }
+}
@@ -0,0 +1,400 @@
+//! A directed graph, for things like which tasks depend on which. Vertices and edges work like an `OrSet`: every add
+//! is tagged with the operation that made it, a remove only removes the adds the remover could see, and if someone
+//! adds something at the same time as someone else removes it, the add wins.
+//!
+//! The tricky part is removing a vertex while someone else adds an edge to it. Removing a vertex removes every edge
+//! to or from it that the remover could see, but they couldn't see the new one. So an edge only counts as being in
+//! the graph while both of its ends are. Everyone agrees on which edges those are, whatever order operations arrive
+//! in, and nobody ever sees an edge pointing at a vertex that isn't there. If the vertex gets added again later, the
+//! edge comes back with it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Time, UserPubKey};
+use crate::types::write_set;
+
+/// Identifies one add of a vertex or an edge, the same way `OrSetTag` does.
+pub type GraphTag = (UserPubKey, Counter);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum GraphDescription<V> {
+    AddVertex(V),
+    /// Remove the adds of `vertex`, and of the edges to and from it, with these tags. Make these with
+    /// `Graph::remove_vertex`.
+    RemoveVertex {
+        vertex: V,
+        tags: BTreeSet<GraphTag>,
+    },
+    /// Make these with `Graph::add_edge`.
+    AddEdge {
+        from: V,
+        to: V,
+    },
+    /// Remove the adds of the edge with these tags. Make these with `Graph::remove_edge`.
+    RemoveEdge {
+        from: V,
+        to: V,
+        tags: BTreeSet<GraphTag>,
+    },
+}
+
+/// Graph is an add-wins directed graph. See the module docs for what happens when a vertex is removed at the same
+/// time as an edge is added to it.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Graph<V: Ord> {
+    vertices: BTreeMap<V, BTreeSet<GraphTag>>,
+    // Includes edges whose ends have been removed, in case they come back
+    edges: BTreeMap<(V, V), BTreeSet<GraphTag>>,
+    removed: BTreeSet<GraphTag>,
+}
+
+impl<V: Ord + Clone> Graph<V> {
+    pub fn new() -> Self {
+        Graph {
+            vertices: BTreeMap::new(),
+            edges: BTreeMap::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+
+    pub fn contains_vertex(&self, vertex: &V) -> bool {
+        self.vertices.contains_key(vertex)
+    }
+
+    pub fn contains_edge(&self, from: &V, to: &V) -> bool {
+        self.contains_vertex(from)
+            && self.contains_vertex(to)
+            && self.edges.contains_key(&(from.clone(), to.clone()))
+    }
+
+    pub fn vertices(&self) -> impl Iterator<Item = &V> {
+        self.vertices.keys()
+    }
+
+    /// Every edge, as `(from, to)`.
+    pub fn edges(&self) -> impl Iterator<Item = (&V, &V)> {
+        self.edges
+            .keys()
+            .filter(move |(from, to)| self.contains_vertex(from) && self.contains_vertex(to))
+            .map(|(from, to)| (from, to))
+    }
+
+    /// The vertices `vertex` has an edge to.
+    pub fn successors<'a>(&'a self, vertex: &'a V) -> impl Iterator<Item = &'a V> {
+        self.edges()
+            .filter(move |(from, _)| *from == vertex)
+            .map(|(_, to)| to)
+    }
+
+    /// The vertices that have an edge to `vertex`.
+    pub fn predecessors<'a>(&'a self, vertex: &'a V) -> impl Iterator<Item = &'a V> {
+        self.edges()
+            .filter(move |(_, to)| *to == vertex)
+            .map(|(from, _)| from)
+    }
+
+    /// The number of vertices.
+    pub fn len(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn add_vertex(vertex: V) -> GraphDescription<V> {
+        GraphDescription::AddVertex(vertex)
+    }
+
+    /// Add an edge from `from` to `to`. `None` if either of them isn't in the graph.
+    pub fn add_edge(&self, from: V, to: V) -> Option<GraphDescription<V>> {
+        if self.contains_vertex(&from) && self.contains_vertex(&to) {
+            Some(GraphDescription::AddEdge { from, to })
+        } else {
+            None
+        }
+    }
+
+    /// Remove a vertex along with every edge to or from it. `None` if it isn't in the graph.
+    pub fn remove_vertex(&self, vertex: V) -> Option<GraphDescription<V>> {
+        let mut tags = self.vertices.get(&vertex)?.clone();
+        for ((from, to), edge_tags) in &self.edges {
+            if *from == vertex || *to == vertex {
+                tags.extend(edge_tags.iter().cloned());
+            }
+        }
+        Some(GraphDescription::RemoveVertex { vertex, tags })
+    }
+
+    /// `None` if the edge isn't in the graph.
+    pub fn remove_edge(&self, from: V, to: V) -> Option<GraphDescription<V>> {
+        if !self.contains_edge(&from, &to) {
+            return None;
+        }
+        let tags = self.edges[&(from.clone(), to.clone())].clone();
+        Some(GraphDescription::RemoveEdge { from, to, tags })
+    }
+}
+
+impl<V: Ord + Clone> Default for Graph<V> {
+    fn default() -> Self {
+        Graph::new()
+    }
+}
+
+// Removes `tags` from the adds of `key`, and forgets it once it has none left.
+fn remove_tags<K: Ord>(
+    entries: &mut BTreeMap<K, BTreeSet<GraphTag>>,
+    key: &K,
+    tags: &BTreeSet<GraphTag>,
+) {
+    if let Some(remaining) = entries.get_mut(key) {
+        remaining.retain(|tag| !tags.contains(tag));
+        if remaining.is_empty() {
+            entries.remove(key);
+        }
+    }
+}
+
+/// Written like `{a, b, c} a -> b, b -> c`.
+impl<V: Ord + Clone + fmt::Display> fmt::Display for Graph<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_set(f, self.vertices())?;
+        for (i, (from, to)) in self.edges().enumerate() {
+            write!(f, "{}{} -> {}", if i == 0 { " " } else { ", " }, from, to)?;
+        }
+        Ok(())
+    }
+}
+
+impl<V: Ord + Clone> Applyable for Graph<V> {
+    const NAME: &'static str = "Graph";
+
+    type Description = GraphDescription<V>;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        _: Time,
+    ) -> Self {
+        let tag = (user_pub_key, counter);
+        match desc {
+            GraphDescription::AddVertex(vertex) => {
+                if !self.removed.contains(&tag) {
+                    self.vertices.entry(vertex).or_default().insert(tag);
+                }
+            }
+            GraphDescription::AddEdge { from, to } => {
+                if !self.removed.contains(&tag) {
+                    self.edges.entry((from, to)).or_default().insert(tag);
+                }
+            }
+            GraphDescription::RemoveVertex { vertex, tags } => {
+                remove_tags(&mut self.vertices, &vertex, &tags);
+                let incident = self
+                    .edges
+                    .keys()
+                    .filter(|(from, to)| *from == vertex || *to == vertex)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for edge in incident {
+                    remove_tags(&mut self.edges, &edge, &tags);
+                }
+                self.removed.extend(tags);
+            }
+            GraphDescription::RemoveEdge { from, to, tags } => {
+                remove_tags(&mut self.edges, &(from, to), &tags);
+                self.removed.extend(tags);
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{
+        create_account, create_crdt, create_crdt_info, get_random_id, Account, CRDT,
+    };
+    use crate::testkit::{self, TestKitConfig};
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+
+    fn merge(into: CRDT<Graph<u8>>, from: &CRDT<Graph<u8>>) -> CRDT<Graph<u8>> {
+        let missing = from.ops_since(into.state_vector()).collect::<Vec<_>>();
+        missing.into_iter().fold(into, CRDT::apply)
+    }
+
+    #[test]
+    fn edges_to_a_concurrently_removed_vertex_are_hidden() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(Graph::new(), get_random_id()));
+        let crdt1 = initial
+            .clone()
+            .apply_desc(&account1, Graph::add_vertex(1))
+            .apply_desc(&account1, Graph::add_vertex(2))
+            .apply_desc(&account1, Graph::add_vertex(3));
+        let desc = crdt1.value.add_edge(1, 2).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, desc);
+        let crdt2 = merge(initial, &crdt1);
+        assert_eq!(crdt2.value.successors(&1).collect::<Vec<_>>(), vec![&2]);
+
+        // The first user removes 2 while the second makes 3 depend on it
+        let desc = crdt1.value.remove_vertex(2).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, desc);
+        assert!(!crdt1.value.contains_edge(&1, &2));
+        let desc = crdt2.value.add_edge(3, 2).unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, desc);
+        assert_eq!(crdt1.value.add_edge(1, 2), None);
+
+        let merged1 = merge(crdt1.clone(), &crdt2);
+        let merged2 = merge(crdt2, &crdt1);
+        assert_eq!(merged1.value, merged2.value);
+        assert_eq!(merged1.value.vertices().collect::<Vec<_>>(), vec![&1, &3]);
+        assert_eq!(merged1.value.edges().count(), 0);
+        assert_eq!(merged1.value.predecessors(&2).count(), 0);
+        assert_eq!(format!("{}", merged1.value), "{1, 3}");
+
+        // Adding 2 back brings back the edge nobody removed, but not the one that was
+        let merged1 = merged1.apply_desc(&account1, Graph::add_vertex(2));
+        assert_eq!(format!("{}", merged1.value), "{1, 2, 3} 3 -> 2");
+        assert_eq!(merged1.value.predecessors(&2).collect::<Vec<_>>(), vec![&3]);
+    }
+
+    #[test]
+    fn concurrent_adds_win_over_removes() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(Graph::new(), get_random_id()));
+        let crdt1 = initial
+            .clone()
+            .apply_desc(&account1, Graph::add_vertex(1))
+            .apply_desc(&account1, Graph::add_vertex(2));
+        let desc = crdt1.value.add_edge(1, 2).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, desc);
+        let crdt2 = merge(initial, &crdt1);
+
+        // The first user removes the edge and vertex 2, while the second adds them both again
+        let desc = crdt1.value.remove_edge(1, 2).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, desc);
+        let desc = crdt1.value.remove_vertex(2).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, desc);
+        let crdt2 = crdt2.apply_desc(&account2, Graph::add_vertex(2));
+        let desc = crdt2.value.add_edge(1, 2).unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, desc);
+
+        let merged1 = merge(crdt1.clone(), &crdt2);
+        let merged2 = merge(crdt2, &crdt1);
+        assert_eq!(merged1.value, merged2.value);
+        assert!(merged1.value.contains_edge(&1, &2));
+        assert_eq!(format!("{}", merged1.value), "{1, 2} 1 -> 2");
+    }
+
+    #[derive(Debug, Clone)]
+    enum Action {
+        AddVertex(u8),
+        RemoveVertex(u8),
+        AddEdge(u8, u8),
+        RemoveEdge(u8, u8),
+        // Catch up on everything another user has
+        SyncFrom(usize),
+    }
+
+    const USERS: usize = 5;
+
+    fn action() -> impl Strategy<Value = (usize, Action)> {
+        let action = prop_oneof![
+            (0..5u8).prop_map(Action::AddVertex),
+            (0..5u8).prop_map(Action::RemoveVertex),
+            (0..5u8, 0..5u8).prop_map(|(from, to)| Action::AddEdge(from, to)),
+            (0..5u8, 0..5u8).prop_map(|(from, to)| Action::RemoveEdge(from, to)),
+            (0..USERS).prop_map(Action::SyncFrom),
+        ];
+        (0..USERS, action)
+    }
+
+    proptest! {
+        // Like the `OrSet` test, removes have to refer to adds that really happened, so each user removes whatever
+        // they can see.
+        #[test]
+        fn replicas_with_many_users_converge(actions in prop::collection::vec(action(), 1..60)) {
+            let initial = create_crdt(create_crdt_info(Graph::new(), get_random_id()));
+            let accounts: Vec<Account> = (0..USERS)
+                .map(|_| {
+                    let (pk, sk) = sign::gen_keypair();
+                    create_account(pk, sk)
+                })
+                .collect();
+            let mut replicas = vec![initial; USERS];
+            for (user, action) in actions {
+                let replica = replicas[user].clone();
+                let desc = match action {
+                    Action::AddVertex(vertex) => Some(Graph::add_vertex(vertex)),
+                    Action::RemoveVertex(vertex) => replica.value.remove_vertex(vertex),
+                    Action::AddEdge(from, to) => replica.value.add_edge(from, to),
+                    Action::RemoveEdge(from, to) => replica.value.remove_edge(from, to),
+                    Action::SyncFrom(other) => {
+                        replicas[user] = merge(replica, &replicas[other]);
+                        continue;
+                    }
+                };
+                replicas[user] = match desc {
+                    Some(desc) => replica.apply_desc(&accounts[user], desc),
+                    None => replica,
+                };
+                // Every edge goes between vertices that are there
+                let graph = &replicas[user].value;
+                for (from, to) in graph.edges() {
+                    prop_assert!(graph.contains_vertex(from) && graph.contains_vertex(to));
+                }
+            }
+
+            // Everyone catches up with everyone, in two different orders
+            let forwards = replicas.iter().fold(replicas[0].clone(), merge);
+            let backwards = replicas.iter().rev().fold(replicas[USERS - 1].clone(), merge);
+            prop_assert_eq!(&forwards.value, &backwards.value);
+            prop_assert_eq!(forwards.state_vector(), backwards.state_vector());
+        }
+    }
+
+    #[test]
+    fn graph_converges() {
+        // As with `OrSet`, these removes mostly refer to adds that never happen, but the order still mustn't matter
+        let (pk, _) = sign::gen_keypair();
+        let tags = move |pun: u32| {
+            let mut tags = BTreeSet::new();
+            tags.insert((pk, Counter::Operation(pun, sign::Signature([0; 64]))));
+            tags
+        };
+        let descriptions = prop_oneof![
+            (0..4u8).prop_map(Graph::add_vertex),
+            (0..4u8, 0..4u8).prop_map(|(from, to)| GraphDescription::AddEdge { from, to }),
+            (0..4u8, any::<u32>()).prop_map(move |(vertex, pun)| GraphDescription::RemoveVertex {
+                vertex,
+                tags: tags(pun),
+            }),
+            (0..4u8, 0..4u8, any::<u32>()).prop_map(move |(from, to, pun)| {
+                GraphDescription::RemoveEdge {
+                    from,
+                    to,
+                    tags: tags(pun),
+                }
+            }),
+        ];
+        let config = TestKitConfig {
+            users: 8,
+            ..TestKitConfig::default()
+        };
+        testkit::check_applyable_with(config, Graph::new(), descriptions);
+    }
+}
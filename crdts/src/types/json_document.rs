@@ -0,0 +1,697 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::mem;
+
+use crate::replicant::{Applyable, Counter, Time, UserPubKey};
+use crate::types::sequence::{ItemId, SequenceDescription};
+use crate::types::text::TextDescription;
+use crate::types::{PNCounter, Sequence, Text};
+
+/// Identifies a map, list, text or counter inside a `JsonDocument`. Everything but the root map is identified by
+/// the operation that put it in the document.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum NodeId {
+    Root,
+    Created(ItemId),
+}
+
+/// Something you can put in a `JsonDocument`. Maps, lists and text start out empty, and you fill them in with more
+/// edits.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum JsonValue {
+    Map,
+    List,
+    Text,
+    /// A counter starting at this value.
+    Counter(i64),
+    /// Any JSON value (as a string, since `serde_json::Value` isn't `Ord`). It can only be replaced as a whole, so
+    /// if two people change it at the same time, one of them wins.
+    Register(String),
+}
+
+impl JsonValue {
+    pub fn register(value: &serde_json::Value) -> Self {
+        JsonValue::Register(value.to_string())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum JsonDocumentDescription {
+    /// Put a new value at `key` in a map, or remove the key if `value` is `None`.
+    Put {
+        map: NodeId,
+        key: String,
+        value: Option<JsonValue>,
+        lamport: u64,
+    },
+    /// Insert a new value into a list, right after the element `after` (or at the start, if it's `None`).
+    Insert {
+        list: NodeId,
+        after: Option<ItemId>,
+        value: JsonValue,
+        lamport: u64,
+    },
+    /// Replace an element of a list with a new value.
+    Set {
+        list: NodeId,
+        element: ItemId,
+        value: JsonValue,
+        lamport: u64,
+    },
+    Delete {
+        list: NodeId,
+        element: ItemId,
+    },
+    Increment {
+        counter: NodeId,
+        by: i64,
+    },
+    Edit {
+        text: NodeId,
+        edit: TextDescription,
+    },
+}
+
+/// What lives at a map key or list element, along with the (lamport, id) of the operation that put it there. The
+/// biggest wins. The value's `NodeId` is the id in the stamp.
+type Slot = ((u64, ItemId), Option<JsonValue>);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+struct List {
+    order: Sequence<()>,
+    elements: BTreeMap<ItemId, Slot>,
+}
+
+/// Something went wrong finding what a path points to.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PathError {
+    /// Nothing lives at this path.
+    NotFound(String),
+    /// Something lives at this path, but it isn't what the edit needs.
+    WrongType {
+        path: String,
+        expected: &'static str,
+    },
+    /// The index is past the end of the list or text at this path.
+    OutOfRange { path: String, len: usize },
+    /// The document itself can't be replaced or removed, only what's in it.
+    Root,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathError::NotFound(path) => write!(f, "there's nothing at {}", path),
+            PathError::WrongType { path, expected } => {
+                write!(f, "{} should be {}, but it isn't", path, expected)
+            }
+            PathError::OutOfRange { path, len } => {
+                write!(f, "{} is only {} long", path, len)
+            }
+            PathError::Root => write!(f, "the whole document can't be replaced or removed"),
+        }
+    }
+}
+
+/// JsonDocument holds a whole tree of maps, lists, text, counters and registers, so one project can hold all of an
+/// application's state. Its value is a `serde_json::Value`.
+///
+/// It's made out of the other types. Lists are `Sequence`s, text is `Text`, and counters are `PNCounter`s. Every map
+/// key and list element holds a value like an `LwwRegister`, so if two people put something at the same key at the
+/// same time, one of them wins (along with everything inside it).
+///
+/// Every map, list, text or counter is stored under the id of the operation that created it, rather than inside
+/// its parent. So an edit to something doesn't care if we've seen it get created yet, and everything converges no
+/// matter what order it arrives in. Things that get removed from the document are still stored, but you can't see
+/// them.
+///
+/// You normally edit it by path (a list of map keys and list indexes, like `["todos", "0", "title"]`) with `set`,
+/// `insert`, `remove`, `increment`, `insert_text` and `delete_text`, which turn the edit into a description.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct JsonDocument {
+    maps: BTreeMap<NodeId, BTreeMap<String, Slot>>,
+    lists: BTreeMap<NodeId, List>,
+    texts: BTreeMap<NodeId, Text>,
+    counters: BTreeMap<NodeId, PNCounter>,
+    // The biggest lamport timestamp we've seen, so new puts can beat everything we know about
+    lamport: u64,
+}
+
+fn join(path: &[&str]) -> String {
+    format!("/{}", path.join("/"))
+}
+
+fn resolve_slot(slot: &Slot) -> Option<(NodeId, JsonValue)> {
+    let ((_, id), value) = slot;
+    value.clone().map(|value| (NodeId::Created(*id), value))
+}
+
+fn put_slot<K: Ord>(slots: &mut BTreeMap<K, Slot>, key: K, slot: Slot) {
+    match slots.get(&key) {
+        Some(current) if current.0 >= slot.0 => {}
+        _ => {
+            slots.insert(key, slot);
+        }
+    }
+}
+
+impl JsonDocument {
+    pub fn new() -> Self {
+        JsonDocument::default()
+    }
+
+    /// The whole document as JSON.
+    pub fn to_json(&self) -> serde_json::Value {
+        self.render(NodeId::Root, &JsonValue::Map)
+    }
+
+    /// What's at `path`, as JSON.
+    pub fn get(&self, path: &[&str]) -> Option<serde_json::Value> {
+        let (node, value) = self.lookup(path).ok()?;
+        Some(self.render(node, &value))
+    }
+
+    fn render(&self, node: NodeId, value: &JsonValue) -> serde_json::Value {
+        match value {
+            JsonValue::Map => serde_json::Value::Object(
+                self.maps
+                    .get(&node)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|(key, slot)| {
+                        let (child, value) = resolve_slot(slot)?;
+                        Some((key.clone(), self.render(child, &value)))
+                    })
+                    .collect(),
+            ),
+            JsonValue::List => serde_json::Value::Array(match self.lists.get(&node) {
+                Some(list) => list
+                    .order
+                    .ids()
+                    .iter()
+                    .filter_map(|element| resolve_slot(list.elements.get(element)?))
+                    .map(|(child, value)| self.render(child, &value))
+                    .collect(),
+                None => Vec::new(),
+            }),
+            JsonValue::Text => serde_json::Value::String(
+                self.texts
+                    .get(&node)
+                    .map(Text::to_string)
+                    .unwrap_or_default(),
+            ),
+            JsonValue::Counter(initial) => {
+                let total =
+                    i128::from(*initial) + self.counters.get(&node).map_or(0, PNCounter::value);
+                // JSON numbers don't go past an i64 exactly, so really big counters lose some precision
+                i64::try_from(total)
+                    .map(serde_json::Value::from)
+                    .unwrap_or_else(|_| serde_json::Value::from(total as f64))
+            }
+            JsonValue::Register(json) => {
+                serde_json::from_str(json).unwrap_or(serde_json::Value::Null)
+            }
+        }
+    }
+
+    // What's inside `parent` at `segment`, if it's a map or list.
+    fn child(
+        &self,
+        (node, value): &(NodeId, JsonValue),
+        segment: &str,
+    ) -> Option<(NodeId, JsonValue)> {
+        match value {
+            JsonValue::Map => resolve_slot(self.maps.get(node)?.get(segment)?),
+            JsonValue::List => {
+                let list = self.lists.get(node)?;
+                let element = list
+                    .order
+                    .ids()
+                    .get(segment.parse::<usize>().ok()?)
+                    .copied()?;
+                resolve_slot(list.elements.get(&element)?)
+            }
+            _ => None,
+        }
+    }
+
+    fn lookup(&self, path: &[&str]) -> Result<(NodeId, JsonValue), PathError> {
+        let mut node = (NodeId::Root, JsonValue::Map);
+        for (depth, segment) in path.iter().enumerate() {
+            node = self
+                .child(&node, segment)
+                .ok_or_else(|| PathError::NotFound(join(&path[..=depth])))?;
+        }
+        Ok(node)
+    }
+
+    // The id of the element at `index` in a list. `path` is the path to the element.
+    fn element(&self, list: NodeId, path: &[&str], index: &str) -> Result<ItemId, PathError> {
+        let ids = self
+            .lists
+            .get(&list)
+            .map(|list| list.order.ids())
+            .unwrap_or_default();
+        let index = index.parse::<usize>().map_err(|_| PathError::WrongType {
+            path: join(path),
+            expected: "an index",
+        })?;
+        ids.get(index)
+            .copied()
+            .ok_or_else(|| PathError::OutOfRange {
+                path: join(&path[..path.len() - 1]),
+                len: ids.len(),
+            })
+    }
+
+    /// Put `value` at `path`, replacing whatever was there. The path has to end in a key of a map, or an index of a
+    /// list that's already there.
+    pub fn set(
+        &self,
+        path: &[&str],
+        value: JsonValue,
+    ) -> Result<JsonDocumentDescription, PathError> {
+        let (last, parent_path) = path.split_last().ok_or(PathError::Root)?;
+        let lamport = self.lamport + 1;
+        match self.lookup(parent_path)? {
+            (map, JsonValue::Map) => Ok(JsonDocumentDescription::Put {
+                map,
+                key: last.to_string(),
+                value: Some(value),
+                lamport,
+            }),
+            (list, JsonValue::List) => Ok(JsonDocumentDescription::Set {
+                list,
+                element: self.element(list, path, last)?,
+                value,
+                lamport,
+            }),
+            _ => Err(PathError::WrongType {
+                path: join(parent_path),
+                expected: "a map or list",
+            }),
+        }
+    }
+
+    /// Insert `value` into a list, so that it ends up at `path` (which has to end in an index).
+    pub fn insert(
+        &self,
+        path: &[&str],
+        value: JsonValue,
+    ) -> Result<JsonDocumentDescription, PathError> {
+        let (last, parent_path) = path.split_last().ok_or(PathError::Root)?;
+        let list = match self.lookup(parent_path)? {
+            (list, JsonValue::List) => list,
+            _ => {
+                return Err(PathError::WrongType {
+                    path: join(parent_path),
+                    expected: "a list",
+                })
+            }
+        };
+        let index = last.parse::<usize>().map_err(|_| PathError::WrongType {
+            path: join(path),
+            expected: "an index",
+        })?;
+        let empty = Sequence::new();
+        let order = self.lists.get(&list).map_or(&empty, |list| &list.order);
+        let after = match order.insert(index, ()) {
+            Some(SequenceDescription::Insert { after, .. }) => after,
+            _ => {
+                return Err(PathError::OutOfRange {
+                    path: join(parent_path),
+                    len: order.len(),
+                })
+            }
+        };
+        Ok(JsonDocumentDescription::Insert {
+            list,
+            after,
+            value,
+            lamport: self.lamport + 1,
+        })
+    }
+
+    /// Remove whatever's at `path`.
+    pub fn remove(&self, path: &[&str]) -> Result<JsonDocumentDescription, PathError> {
+        let (last, parent_path) = path.split_last().ok_or(PathError::Root)?;
+        // Make sure there's something there to remove
+        self.lookup(path)?;
+        match self.lookup(parent_path)? {
+            (map, JsonValue::Map) => Ok(JsonDocumentDescription::Put {
+                map,
+                key: last.to_string(),
+                value: None,
+                lamport: self.lamport + 1,
+            }),
+            (list, _) => Ok(JsonDocumentDescription::Delete {
+                list,
+                element: self.element(list, path, last)?,
+            }),
+        }
+    }
+
+    /// Change the counter at `path` by `by`.
+    pub fn increment(&self, path: &[&str], by: i64) -> Result<JsonDocumentDescription, PathError> {
+        match self.lookup(path)? {
+            (counter, JsonValue::Counter(_)) => {
+                Ok(JsonDocumentDescription::Increment { counter, by })
+            }
+            _ => Err(PathError::WrongType {
+                path: join(path),
+                expected: "a counter",
+            }),
+        }
+    }
+
+    fn text(&self, path: &[&str]) -> Result<(NodeId, Text), PathError> {
+        match self.lookup(path)? {
+            (node, JsonValue::Text) => {
+                Ok((node, self.texts.get(&node).cloned().unwrap_or_default()))
+            }
+            _ => Err(PathError::WrongType {
+                path: join(path),
+                expected: "text",
+            }),
+        }
+    }
+
+    /// Insert `text` into the text at `path`, so that it starts at character `index`.
+    pub fn insert_text(
+        &self,
+        path: &[&str],
+        index: usize,
+        text: &str,
+    ) -> Result<JsonDocumentDescription, PathError> {
+        let (node, current) = self.text(path)?;
+        let edit = current
+            .insert(index, text)
+            .ok_or_else(|| PathError::OutOfRange {
+                path: join(path),
+                len: current.len(),
+            })?;
+        Ok(JsonDocumentDescription::Edit { text: node, edit })
+    }
+
+    /// Delete `len` characters from the text at `path`, starting at `index`.
+    pub fn delete_text(
+        &self,
+        path: &[&str],
+        index: usize,
+        len: usize,
+    ) -> Result<JsonDocumentDescription, PathError> {
+        let (node, current) = self.text(path)?;
+        if index.checked_add(len).is_none_or(|end| end > current.len()) {
+            return Err(PathError::OutOfRange {
+                path: join(path),
+                len: current.len(),
+            });
+        }
+        Ok(JsonDocumentDescription::Edit {
+            text: node,
+            edit: current.delete(index, len),
+        })
+    }
+}
+
+impl fmt::Display for JsonDocument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_json())
+    }
+}
+
+impl Applyable for JsonDocument {
+    const NAME: &'static str = "JsonDocument";
+
+    type Description = JsonDocumentDescription;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        time: Time,
+    ) -> Self {
+        // The counter we get is always an `Operation`, since it's the counter of the operation we're applying
+        let id = ItemId {
+            user_pub_key,
+            pun: counter.pun().unwrap_or_default(),
+        };
+        match desc {
+            JsonDocumentDescription::Put {
+                map,
+                key,
+                value,
+                lamport,
+            } => {
+                self.lamport = self.lamport.max(lamport);
+                put_slot(
+                    self.maps.entry(map).or_default(),
+                    key,
+                    ((lamport, id), value),
+                );
+            }
+            JsonDocumentDescription::Insert {
+                list,
+                after,
+                value,
+                lamport,
+            } => {
+                self.lamport = self.lamport.max(lamport);
+                let list = self.lists.entry(list).or_default();
+                list.order = mem::take(&mut list.order).apply_without_idempotency_check(
+                    SequenceDescription::Insert {
+                        after,
+                        lamport,
+                        value: (),
+                    },
+                    user_pub_key,
+                    counter,
+                    time,
+                );
+                put_slot(&mut list.elements, id, ((lamport, id), Some(value)));
+            }
+            JsonDocumentDescription::Set {
+                list,
+                element,
+                value,
+                lamport,
+            } => {
+                self.lamport = self.lamport.max(lamport);
+                let list = self.lists.entry(list).or_default();
+                put_slot(&mut list.elements, element, ((lamport, id), Some(value)));
+            }
+            JsonDocumentDescription::Delete { list, element } => {
+                let list = self.lists.entry(list).or_default();
+                list.order = mem::take(&mut list.order).apply_without_idempotency_check(
+                    SequenceDescription::Delete(element),
+                    user_pub_key,
+                    counter,
+                    time,
+                );
+            }
+            JsonDocumentDescription::Increment { counter: node, by } => {
+                let tally = self.counters.entry(node).or_default();
+                *tally = mem::take(tally).apply_without_idempotency_check(
+                    by,
+                    user_pub_key,
+                    counter,
+                    time,
+                );
+            }
+            JsonDocumentDescription::Edit { text, edit } => {
+                let text = self.texts.entry(text).or_default();
+                *text = mem::take(text).apply_without_idempotency_check(
+                    edit,
+                    user_pub_key,
+                    counter,
+                    time,
+                );
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{create_account, create_crdt, create_crdt_info, get_random_id, CRDT};
+    use crate::testkit;
+    use proptest::prelude::*;
+    use serde_json::json;
+    use sodiumoxide::crypto::sign;
+
+    fn merge(into: CRDT<JsonDocument>, from: &CRDT<JsonDocument>) -> CRDT<JsonDocument> {
+        let missing = from.ops_since(into.state_vector()).collect::<Vec<_>>();
+        missing.into_iter().fold(into, CRDT::apply)
+    }
+
+    #[test]
+    fn edits_by_path_merge() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(JsonDocument::new(), get_random_id()));
+        let mut crdt1 = initial.clone();
+        type Edit = fn(&JsonDocument) -> Result<JsonDocumentDescription, PathError>;
+        let edits: Vec<Edit> = vec![
+            |doc| doc.set(&["title"], JsonValue::Text),
+            |doc| doc.insert_text(&["title"], 0, "Chores"),
+            |doc| doc.set(&["todos"], JsonValue::List),
+            |doc| doc.insert(&["todos", "0"], JsonValue::Map),
+            |doc| {
+                doc.set(
+                    &["todos", "0", "what"],
+                    JsonValue::register(&json!("dishes")),
+                )
+            },
+            |doc| doc.set(&["todos", "0", "done"], JsonValue::register(&json!(false))),
+            |doc| doc.set(&["views"], JsonValue::Counter(0)),
+        ];
+        for edit in edits {
+            let desc = edit(&crdt1.value).unwrap();
+            crdt1 = crdt1.apply_desc(&account1, desc);
+        }
+        let crdt2 = merge(initial, &crdt1);
+
+        // The first user finishes the chore and looks at the list, while the second renames it, looks at it and adds
+        // another chore
+        let desc = crdt1
+            .value
+            .set(&["todos", "0", "done"], JsonValue::register(&json!(true)))
+            .unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, desc);
+        let desc = crdt1.value.increment(&["views"], 1).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, desc);
+        let desc = crdt2.value.insert_text(&["title"], 6, "!").unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, desc);
+        let desc = crdt2.value.increment(&["views"], 1).unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, desc);
+        let desc = crdt2
+            .value
+            .insert(
+                &["todos", "1"],
+                JsonValue::register(&json!({"what": "laundry"})),
+            )
+            .unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, desc);
+
+        let merged1 = merge(crdt1.clone(), &crdt2);
+        let merged2 = merge(crdt2, &crdt1);
+        assert_eq!(merged1.value, merged2.value);
+        assert_eq!(
+            merged1.value.to_json(),
+            json!({
+                "title": "Chores!",
+                "todos": [{"what": "dishes", "done": true}, {"what": "laundry"}],
+                "views": 2,
+            })
+        );
+        // The second chore is a register, so it's replaced as a whole rather than edited by path
+        assert_eq!(
+            merged1.value.get(&["todos", "1"]),
+            Some(json!({"what": "laundry"}))
+        );
+        assert_eq!(merged1.value.get(&["todos", "1", "what"]), None);
+
+        let desc = merged1.value.remove(&["todos", "0"]).unwrap();
+        let merged1 = merged1.apply_desc(&account1, desc);
+        assert_eq!(
+            merged1.value.get(&["todos"]),
+            Some(json!([{"what": "laundry"}]))
+        );
+        assert_eq!(
+            merged1.value.increment(&["title"], 1),
+            Err(PathError::WrongType {
+                path: "/title".to_string(),
+                expected: "a counter"
+            })
+        );
+        assert_eq!(
+            merged1.value.remove(&["todos", "1"]),
+            Err(PathError::NotFound("/todos/1".to_string()))
+        );
+        let out_of_range = Err(PathError::OutOfRange {
+            path: "/title".to_string(),
+            len: 7,
+        });
+        assert_eq!(merged1.value.insert_text(&["title"], 8, "?"), out_of_range);
+        assert_eq!(merged1.value.delete_text(&["title"], 6, 2), out_of_range);
+        assert!(merged1.value.delete_text(&["title"], 6, 1).is_ok());
+    }
+
+    #[test]
+    fn json_document_converges() {
+        // These refer to a handful of made-up nodes and elements, so they mostly land somewhere you can't see. That
+        // still checks that the order everything arrives in doesn't matter.
+        let (pk, _) = sign::gen_keypair();
+        let item_id = (0..4u32).prop_map(move |pun| ItemId {
+            user_pub_key: pk,
+            pun,
+        });
+        let node_id = prop_oneof![
+            Just(NodeId::Root),
+            item_id.clone().prop_map(NodeId::Created)
+        ];
+        let value = prop_oneof![
+            Just(JsonValue::Map),
+            Just(JsonValue::List),
+            Just(JsonValue::Text),
+            any::<i64>().prop_map(JsonValue::Counter),
+            any::<u8>().prop_map(|n| JsonValue::register(&json!(n))),
+        ];
+        let descriptions = prop_oneof![
+            (
+                node_id.clone(),
+                "[ab]",
+                prop::option::of(value.clone()),
+                0..5u64
+            )
+                .prop_map(|(map, key, value, lamport)| JsonDocumentDescription::Put {
+                    map,
+                    key,
+                    value,
+                    lamport
+                }),
+            (
+                node_id.clone(),
+                prop::option::of(item_id.clone()),
+                value.clone(),
+                0..5u64
+            )
+                .prop_map(|(list, after, value, lamport)| {
+                    JsonDocumentDescription::Insert {
+                        list,
+                        after,
+                        value,
+                        lamport,
+                    }
+                }),
+            (node_id.clone(), item_id.clone(), value, 0..5u64).prop_map(
+                |(list, element, value, lamport)| JsonDocumentDescription::Set {
+                    list,
+                    element,
+                    value,
+                    lamport
+                }
+            ),
+            (node_id.clone(), item_id)
+                .prop_map(|(list, element)| JsonDocumentDescription::Delete { list, element }),
+            (node_id.clone(), any::<i64>())
+                .prop_map(|(counter, by)| JsonDocumentDescription::Increment { counter, by }),
+            (node_id, "[xy]").prop_map(|(text, s)| JsonDocumentDescription::Edit {
+                text,
+                edit: TextDescription::Insert {
+                    after: None,
+                    lamport: 1,
+                    text: s
+                }
+            }),
+        ];
+        testkit::check_applyable(JsonDocument::new(), descriptions);
+    }
+}
@@ -0,0 +1,281 @@
+//! Lattices let you write a CRDT by saying how to merge two states, instead of how to apply an operation.
+//!
+//! A join-semilattice is a type with a `join` that's commutative (`a.join(b) == b.join(a)`), associative
+//! (`a.join(b.join(c)) == a.join(b).join(c)`) and idempotent (`a.join(a) == a`). Those are exactly the properties
+//! that make joining states in any order, any number of times, end up in the same place. So any lattice is a CRDT:
+//! wrap it in `StateBased`, and every operation just joins in a value.
+//!
+//! `MaxRegister` and `MinRegister` are the simplest ones. Sets (joined by union), maps (joined key by key), `bool`
+//! (joined by `||`), `Option` and tuples of lattices are lattices too, so you can build bigger ones out of them.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Time, UserPubKey};
+
+/// A join-semilattice. `join` has to be commutative, associative and idempotent.
+pub trait Lattice {
+    /// The smallest value that's at least as big as both `self` and `other`.
+    fn join(self, other: Self) -> Self;
+}
+
+/// A lattice with a smallest value, which joining with doesn't change anything.
+pub trait BoundedLattice: Lattice {
+    fn bottom() -> Self;
+}
+
+/// Turns any lattice into an `Applyable`. An operation is a value to join into the state.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct StateBased<L>(pub L);
+
+impl<L> StateBased<L> {
+    pub fn new(value: L) -> Self {
+        StateBased(value)
+    }
+
+    pub fn value(&self) -> &L {
+        &self.0
+    }
+}
+
+impl<L: BoundedLattice> Default for StateBased<L> {
+    fn default() -> Self {
+        StateBased(L::bottom())
+    }
+}
+
+impl<L: fmt::Display> fmt::Display for StateBased<L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<L: Lattice + Clone> Applyable for StateBased<L> {
+    const NAME: &'static str = "StateBased";
+
+    type Description = L;
+
+    fn apply_without_idempotency_check(self, desc: L, _: UserPubKey, _: Counter, _: Time) -> Self {
+        StateBased(self.0.join(desc))
+    }
+}
+
+/// MaxRegister holds the biggest value anyone has written to it.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Default,
+)]
+pub struct MaxRegister<T>(pub T);
+
+impl<T> MaxRegister<T> {
+    pub fn value(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Ord> Lattice for MaxRegister<T> {
+    fn join(self, other: Self) -> Self {
+        MaxRegister(self.0.max(other.0))
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for MaxRegister<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<T: Ord + Clone> Applyable for MaxRegister<T> {
+    const NAME: &'static str = "MaxRegister";
+
+    /// A value to write. It only changes the register if it's bigger than what's there.
+    type Description = T;
+
+    fn apply_without_idempotency_check(self, desc: T, _: UserPubKey, _: Counter, _: Time) -> Self {
+        self.join(MaxRegister(desc))
+    }
+}
+
+/// MinRegister holds the smallest value anyone has written to it.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Default,
+)]
+pub struct MinRegister<T>(pub T);
+
+impl<T> MinRegister<T> {
+    pub fn value(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Ord> Lattice for MinRegister<T> {
+    fn join(self, other: Self) -> Self {
+        MinRegister(self.0.min(other.0))
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for MinRegister<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<T: Ord + Clone> Applyable for MinRegister<T> {
+    const NAME: &'static str = "MinRegister";
+
+    /// A value to write. It only changes the register if it's smaller than what's there.
+    type Description = T;
+
+    fn apply_without_idempotency_check(self, desc: T, _: UserPubKey, _: Counter, _: Time) -> Self {
+        self.join(MinRegister(desc))
+    }
+}
+
+impl Lattice for bool {
+    fn join(self, other: Self) -> Self {
+        self || other
+    }
+}
+
+impl BoundedLattice for bool {
+    fn bottom() -> Self {
+        false
+    }
+}
+
+/// `None` is smaller than everything else.
+impl<L: Lattice> Lattice for Option<L> {
+    fn join(self, other: Self) -> Self {
+        match (self, other) {
+            (Some(a), Some(b)) => Some(a.join(b)),
+            (a, None) => a,
+            (None, b) => b,
+        }
+    }
+}
+
+impl<L: Lattice> BoundedLattice for Option<L> {
+    fn bottom() -> Self {
+        None
+    }
+}
+
+impl<E: Ord> Lattice for BTreeSet<E> {
+    fn join(mut self, other: Self) -> Self {
+        self.extend(other);
+        self
+    }
+}
+
+impl<E: Ord> BoundedLattice for BTreeSet<E> {
+    fn bottom() -> Self {
+        BTreeSet::new()
+    }
+}
+
+/// Keys in either map are in the join, and keys in both get their values joined.
+impl<K: Ord, L: Lattice> Lattice for BTreeMap<K, L> {
+    fn join(mut self, other: Self) -> Self {
+        for (key, value) in other {
+            let joined = match self.remove(&key) {
+                Some(mine) => mine.join(value),
+                None => value,
+            };
+            self.insert(key, joined);
+        }
+        self
+    }
+}
+
+impl<K: Ord, L: Lattice> BoundedLattice for BTreeMap<K, L> {
+    fn bottom() -> Self {
+        BTreeMap::new()
+    }
+}
+
+// Tuples of lattices are joined element by element.
+macro_rules! lattice_product {
+    ($($field:tt $type:ident),+) => {
+        impl<$($type: Lattice),+> Lattice for ($($type,)+) {
+            fn join(self, other: Self) -> Self {
+                ($(self.$field.join(other.$field),)+)
+            }
+        }
+
+        impl<$($type: BoundedLattice),+> BoundedLattice for ($($type,)+) {
+            fn bottom() -> Self {
+                ($($type::bottom(),)+)
+            }
+        }
+    };
+}
+
+lattice_product!(0 A, 1 B);
+lattice_product!(0 A, 1 B, 2 C);
+lattice_product!(0 A, 1 B, 2 C, 3 D);
+lattice_product!(0 A, 1 B, 2 C, 3 D, 4 E);
+lattice_product!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testkit;
+    use proptest::prelude::*;
+
+    type Example = (
+        MaxRegister<u8>,
+        MinRegister<u8>,
+        BTreeSet<u8>,
+        Option<BTreeMap<u8, bool>>,
+    );
+
+    fn example() -> impl Strategy<Value = Example> {
+        (
+            any::<u8>().prop_map(MaxRegister),
+            any::<u8>().prop_map(MinRegister),
+            prop::collection::btree_set(0..8u8, 0..4),
+            prop::option::of(prop::collection::btree_map(0..4u8, any::<bool>(), 0..4)),
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn join_is_a_join(a in example(), b in example(), c in example()) {
+            prop_assert_eq!(a.clone().join(b.clone()), b.clone().join(a.clone()));
+            prop_assert_eq!(
+                a.clone().join(b.clone().join(c.clone())),
+                a.clone().join(b).join(c)
+            );
+            prop_assert_eq!(a.clone().join(a.clone()), a);
+        }
+    }
+
+    #[test]
+    fn registers_keep_the_extreme() {
+        let max = MaxRegister(3).join(MaxRegister(7)).join(MaxRegister(5));
+        assert_eq!(max.value(), &7);
+        let min = MinRegister(3).join(MinRegister(7)).join(MinRegister(5));
+        assert_eq!(min.value(), &3);
+    }
+
+    #[test]
+    fn max_register_converges() {
+        testkit::check_applyable(MaxRegister(0u8), any::<u8>());
+    }
+
+    #[test]
+    fn min_register_converges() {
+        testkit::check_applyable(MinRegister(u8::MAX), any::<u8>());
+    }
+
+    #[test]
+    fn state_based_converges() {
+        testkit::check_applyable(
+            StateBased::<(MaxRegister<u8>, BTreeSet<u8>)>::new((MaxRegister(0), BTreeSet::new())),
+            (
+                any::<u8>().prop_map(MaxRegister),
+                prop::collection::btree_set(0..8u8, 0..4),
+            ),
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Invertible, Pun, Time, UserPubKey};
+
+/// LwwRegister holds a single value, like a title, a setting or a status. If two people write to it at the same
+/// time, the last write wins.
+///
+/// "Last" means the write with the latest time, according to the clock of whoever made it. If two writes have the
+/// same time we compare the users' public keys, and if the same user made both we take the one they made second.
+/// Everyone agrees on that order, so everyone ends up with the same value.
+///
+/// Since we trust the writers' clocks, someone whose clock is set far in the future will win every conflict until
+/// everyone else catches up with them.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct LwwRegister<V> {
+    value: V,
+    // When the current value was written, and by whom. `None` means it's still the initial value.
+    written: Option<(Time, UserPubKey, Pun)>,
+}
+
+impl<V> LwwRegister<V> {
+    pub fn new(value: V) -> Self {
+        LwwRegister {
+            value,
+            written: None,
+        }
+    }
+
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    /// Who wrote the current value, and when. `None` if nobody has written to it yet.
+    pub fn written_by(&self) -> Option<(UserPubKey, Time)> {
+        self.written
+            .map(|(time, user_pub_key, _)| (user_pub_key, time))
+    }
+}
+
+impl<V> From<V> for LwwRegister<V> {
+    fn from(value: V) -> Self {
+        LwwRegister::new(value)
+    }
+}
+
+impl<V: fmt::Display> fmt::Display for LwwRegister<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+impl<V: Clone> Applyable for LwwRegister<V> {
+    const NAME: &'static str = "LwwRegister";
+
+    /// The new value.
+    type Description = V;
+
+    fn apply_without_idempotency_check(
+        self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        time: Time,
+    ) -> Self {
+        // The counter we get is always an `Operation`, since it's the counter of the operation we're applying
+        let pun = counter.pun().unwrap_or_default();
+        let written = Some((time, user_pub_key, pun));
+        if written > self.written {
+            LwwRegister {
+                value: desc,
+                written,
+            }
+        } else {
+            self
+        }
+    }
+
+    fn inverse(&self, _: &V, _: UserPubKey, _: Counter) -> Option<V> {
+        Some(self.value.clone())
+    }
+}
+
+/// Undoing a write puts back whatever it wrote over, unless someone has written to the register since (then their
+/// write stays).
+impl<V: Clone> Invertible for LwwRegister<V> {
+    fn invert(&self, inverse: &V, user_pub_key: UserPubKey, counter: Counter) -> Option<V> {
+        match self.written {
+            Some((_, writer, pun)) if writer == user_pub_key && counter.pun() == Some(pun) => {
+                Some(inverse.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{create_account, create_crdt, create_crdt_info, get_random_id, CRDT};
+    use crate::testkit;
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+    use std::collections::HashMap;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn the_last_write_wins() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(
+            LwwRegister::new("Untitled".to_string()),
+            get_random_id(),
+        ));
+        assert_eq!(initial.value.written_by(), None);
+
+        let crdt1 = initial
+            .clone()
+            .apply_desc(&account1, "First draft".to_string());
+        thread::sleep(Duration::from_millis(10));
+        let crdt2 = initial.apply_desc(&account2, "Final draft".to_string());
+
+        // It doesn't matter which order we hear about the writes in
+        let merged1 = crdt2
+            .ops_since(&HashMap::new())
+            .fold(crdt1.clone(), CRDT::apply);
+        let merged2 = crdt1.ops_since(&HashMap::new()).fold(crdt2, CRDT::apply);
+        assert_eq!(merged1.value.value(), "Final draft");
+        assert_eq!(merged1.value, merged2.value);
+        assert_eq!(merged1.value.written_by().map(|(pk, _)| pk), Some(pk2));
+    }
+
+    #[test]
+    fn lww_register_converges() {
+        testkit::check_applyable(LwwRegister::new(0u8), any::<u8>());
+    }
+}
@@ -0,0 +1,237 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Pun, Time, UserPubKey};
+
+/// Identifies a value written to an `MvRegister`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Tag {
+    /// The value the register started with.
+    Initial,
+    /// A value written by this user, in the operation with this counter.
+    Write(UserPubKey, Pun),
+}
+
+/// Writing to an `MvRegister`. Make these with `MvRegister::write`.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct MvRegisterWrite<V> {
+    pub value: V,
+    /// The values the writer could see when they wrote this one. They all get replaced.
+    pub supersedes: BTreeSet<Tag>,
+}
+
+/// MvRegister holds a single value, but never throws away a write just because it was concurrent with another.
+///
+/// Every write replaces the values its writer had seen. If two people write without having seen each other's
+/// writes, both values stay, and the register is in conflict until someone writes a value (usually one of the
+/// conflicting ones) that replaces them both.
+///
+/// A write can arrive before the value it replaces does, so we remember the tags of replaced values we haven't
+/// seen yet, and forget each one once its value shows up. Besides that, the register only keeps the latest counter
+/// it's seen from each user.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct MvRegister<V> {
+    values: BTreeMap<Tag, V>,
+    // Tags of values that have been replaced before they got here
+    superseded: BTreeSet<Tag>,
+    // The latest counter we've seen from each user. Each user's writes are applied in order, so this tells us
+    // which of their values we've already seen.
+    latest: BTreeMap<UserPubKey, Pun>,
+}
+
+impl<V> MvRegister<V> {
+    pub fn new(value: V) -> Self {
+        let mut values = BTreeMap::new();
+        values.insert(Tag::Initial, value);
+        MvRegister {
+            values,
+            superseded: BTreeSet::new(),
+            latest: BTreeMap::new(),
+        }
+    }
+
+    /// Every value the register currently holds. There's more than one if there's a conflict.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.values.values()
+    }
+
+    /// Every value the register currently holds, along with who wrote it (or `Tag::Initial`).
+    pub fn tagged_values(&self) -> impl Iterator<Item = (Tag, &V)> {
+        self.values.iter().map(|(tag, value)| (*tag, value))
+    }
+
+    pub fn is_conflicted(&self) -> bool {
+        self.values.len() > 1
+    }
+
+    /// Write a new value, replacing every value we can currently see. To resolve a conflict, write whichever of
+    /// the conflicting values should win.
+    pub fn write(&self, value: V) -> MvRegisterWrite<V> {
+        MvRegisterWrite {
+            value,
+            supersedes: self.values.keys().copied().collect(),
+        }
+    }
+
+    fn has_seen(&self, tag: Tag) -> bool {
+        match tag {
+            Tag::Initial => true,
+            Tag::Write(user_pub_key, pun) => self
+                .latest
+                .get(&user_pub_key)
+                .is_some_and(|latest| pun <= *latest),
+        }
+    }
+}
+
+impl<V: Default> Default for MvRegister<V> {
+    fn default() -> Self {
+        MvRegister::new(V::default())
+    }
+}
+
+impl<V> From<V> for MvRegister<V> {
+    fn from(value: V) -> Self {
+        MvRegister::new(value)
+    }
+}
+
+impl<V: fmt::Display> fmt::Display for MvRegister<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_conflicted() {
+            write!(f, "{} conflicting values:", self.values.len())?;
+            for (i, value) in self.values().enumerate() {
+                write!(f, "\n  {}) {}", i + 1, value)?;
+            }
+            Ok(())
+        } else {
+            self.values().try_for_each(|value| write!(f, "{}", value))
+        }
+    }
+}
+
+impl<V: Clone> Applyable for MvRegister<V> {
+    const NAME: &'static str = "MvRegister";
+
+    type Description = MvRegisterWrite<V>;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        _: Time,
+    ) -> Self {
+        // The counter we get is always an `Operation`, since it's the counter of the operation we're applying
+        let pun = counter.pun().unwrap_or_default();
+        for tag in desc.supersedes {
+            self.values.remove(&tag);
+            if !self.has_seen(tag) {
+                self.superseded.insert(tag);
+            }
+        }
+        self.latest.insert(user_pub_key, pun);
+        let tag = Tag::Write(user_pub_key, pun);
+        // A value can only arrive once, so once it's here we don't need to remember that it was replaced
+        if !self.superseded.remove(&tag) {
+            self.values.insert(tag, desc.value);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{create_account, create_crdt, create_crdt_info, get_random_id, CRDT};
+    use crate::testkit;
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+    use std::collections::HashMap;
+
+    #[test]
+    fn concurrent_writes_conflict_until_resolved() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(MvRegister::new("draft"), get_random_id()));
+
+        let write = initial.value.write("approved");
+        let crdt1 = initial.clone().apply_desc(&account1, write);
+        let write = initial.value.write("rejected");
+        let crdt2 = initial.apply_desc(&account2, write);
+
+        let missing = crdt2.ops_since(crdt1.state_vector()).collect::<Vec<_>>();
+        let merged = missing.into_iter().fold(crdt1.clone(), CRDT::apply);
+        assert!(merged.value.is_conflicted());
+        assert_eq!(
+            merged.value.values().copied().collect::<BTreeSet<_>>(),
+            vec!["approved", "rejected"].into_iter().collect()
+        );
+
+        // The first user picks a winner. Someone who only hears about that and the first write (but not the
+        // second) still ends up without a conflict once the second write shows up.
+        let write = merged.value.write("rejected");
+        let resolved = merged.apply_desc(&account1, write);
+        assert_eq!(
+            resolved.value.values().collect::<Vec<_>>(),
+            vec![&"rejected"]
+        );
+
+        let latecomer = resolved
+            .ops_since(&HashMap::new())
+            .filter(|op| op.user_pub_key == pk1)
+            .fold(crdt1, CRDT::apply);
+        assert!(!latecomer.value.is_conflicted());
+        let latecomer = crdt2
+            .ops_since(&HashMap::new())
+            .fold(latecomer, CRDT::apply);
+        assert_eq!(latecomer.value, resolved.value);
+    }
+
+    #[test]
+    fn replaced_values_are_only_remembered_until_they_arrive() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(MvRegister::new(0), get_random_id()));
+        let mut crdt1 = initial.clone();
+        for i in 1..10 {
+            let write = crdt1.value.write(i);
+            crdt1 = crdt1.apply_desc(&account1, write);
+        }
+        assert!(crdt1.value.superseded.is_empty());
+
+        // Someone else replaces the latest value, and a newcomer hears about that before the value itself
+        let write = crdt1.value.write(10);
+        let crdt2 = crdt1
+            .ops_since(&HashMap::new())
+            .fold(initial.clone(), CRDT::apply)
+            .apply_desc(&account2, write);
+        let newcomer = crdt2
+            .ops_since(&HashMap::new())
+            .filter(|op| op.user_pub_key == pk2)
+            .fold(initial, CRDT::apply);
+        assert_eq!(newcomer.value.superseded.len(), 1);
+        let newcomer = crdt1.ops_since(&HashMap::new()).fold(newcomer, CRDT::apply);
+        assert!(newcomer.value.superseded.is_empty());
+        assert_eq!(newcomer.value.values().collect::<Vec<_>>(), vec![&10]);
+    }
+
+    #[test]
+    fn mv_register_converges() {
+        let writes =
+            (any::<u8>(), any::<bool>()).prop_map(|(value, over_initial)| MvRegisterWrite {
+                value,
+                supersedes: if over_initial {
+                    vec![Tag::Initial].into_iter().collect()
+                } else {
+                    BTreeSet::new()
+                },
+            });
+        testkit::check_applyable(MvRegister::new(0u8), writes);
+    }
+}
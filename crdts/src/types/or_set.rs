@@ -0,0 +1,236 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Time, UserPubKey};
+use crate::types::write_set;
+
+/// Identifies one add. Every operation already has a unique counter per user, so we just use that.
+pub type OrSetTag = (UserPubKey, Counter);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum OrSetDescription<E> {
+    Add(E),
+    /// Remove the adds of `element` with these tags. Make these with `OrSet::remove`.
+    Remove {
+        element: E,
+        tags: BTreeSet<OrSetTag>,
+    },
+}
+
+/// OrSet is an observed-remove set. You can add and remove elements as often as you like, and if someone adds an
+/// element at the same time as someone else removes it, the add wins.
+///
+/// Every add is tagged with the operation that made it. Removing an element only removes the adds the remover
+/// could see, so an add they didn't know about survives. An element is in the set as long as it has at least one
+/// add that hasn't been removed.
+///
+/// A remove can arrive before the add it removes, so we remember the tags of every add that's been removed.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct OrSet<E: Ord> {
+    elements: BTreeMap<E, BTreeSet<OrSetTag>>,
+    removed: BTreeSet<OrSetTag>,
+}
+
+impl<E: Ord> OrSet<E> {
+    pub fn new() -> Self {
+        OrSet {
+            elements: BTreeMap::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+
+    pub fn contains(&self, element: &E) -> bool {
+        self.elements.contains_key(element)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &E> {
+        self.elements.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn add(element: E) -> OrSetDescription<E> {
+        OrSetDescription::Add(element)
+    }
+
+    /// Remove an element, along with every add of it we've seen. `None` if it isn't in the set.
+    pub fn remove(&self, element: E) -> Option<OrSetDescription<E>> {
+        let tags = self.elements.get(&element)?.clone();
+        Some(OrSetDescription::Remove { element, tags })
+    }
+}
+
+impl<E: Ord> Default for OrSet<E> {
+    fn default() -> Self {
+        OrSet::new()
+    }
+}
+
+impl<E: Ord + fmt::Display> fmt::Display for OrSet<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_set(f, self.iter())
+    }
+}
+
+impl<E: Ord + Clone> Applyable for OrSet<E> {
+    const NAME: &'static str = "OrSet";
+
+    type Description = OrSetDescription<E>;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        _: Time,
+    ) -> Self {
+        match desc {
+            OrSetDescription::Add(element) => {
+                let tag = (user_pub_key, counter);
+                if !self.removed.contains(&tag) {
+                    self.elements.entry(element).or_default().insert(tag);
+                }
+            }
+            OrSetDescription::Remove { element, tags } => {
+                if let Some(remaining) = self.elements.get_mut(&element) {
+                    remaining.retain(|tag| !tags.contains(tag));
+                    if remaining.is_empty() {
+                        self.elements.remove(&element);
+                    }
+                }
+                self.removed.extend(tags);
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{
+        create_account, create_crdt, create_crdt_info, get_random_id, Account, CRDT,
+    };
+    use crate::testkit::{self, TestKitConfig};
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+
+    fn merge(into: CRDT<OrSet<u8>>, from: &CRDT<OrSet<u8>>) -> CRDT<OrSet<u8>> {
+        let missing = from.ops_since(into.state_vector()).collect::<Vec<_>>();
+        missing.into_iter().fold(into, CRDT::apply)
+    }
+
+    #[test]
+    fn concurrent_adds_win_over_removes() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(OrSet::new(), get_random_id()));
+        let crdt1 = initial
+            .clone()
+            .apply_desc(&account1, OrSet::add(1))
+            .apply_desc(&account1, OrSet::add(2));
+        let crdt2 = merge(initial, &crdt1);
+        assert!(crdt2.value.contains(&1));
+
+        // The first user removes 1 while the second adds it again. Both remove 2.
+        let remove = crdt1.value.remove(1).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, remove);
+        let remove = crdt1.value.remove(2).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, remove);
+        let crdt2 = crdt2.apply_desc(&account2, OrSet::add(1));
+        let remove = crdt2.value.remove(2).unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, remove);
+        assert_eq!(crdt1.value.remove(1), None);
+
+        let merged1 = merge(crdt1.clone(), &crdt2);
+        let merged2 = merge(crdt2, &crdt1);
+        assert_eq!(merged1.value, merged2.value);
+        assert_eq!(merged1.value.iter().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(format!("{}", merged1.value), "{1}");
+    }
+
+    #[derive(Debug, Clone)]
+    enum Action {
+        Add(u8),
+        Remove(u8),
+        // Catch up on everything another user has
+        SyncFrom(usize),
+    }
+
+    const USERS: usize = 6;
+
+    fn action() -> impl Strategy<Value = (usize, Action)> {
+        let action = prop_oneof![
+            (0..8u8).prop_map(Action::Add),
+            (0..8u8).prop_map(Action::Remove),
+            (0..USERS).prop_map(Action::SyncFrom),
+        ];
+        (0..USERS, action)
+    }
+
+    proptest! {
+        // Removes have to refer to adds that really happened, so unlike `testkit` we drive each user's replica
+        // ourselves and have them remove whatever they can see.
+        #[test]
+        fn replicas_with_many_users_converge(actions in prop::collection::vec(action(), 1..60)) {
+            let initial = create_crdt(create_crdt_info(OrSet::new(), get_random_id()));
+            let accounts: Vec<Account> = (0..USERS)
+                .map(|_| {
+                    let (pk, sk) = sign::gen_keypair();
+                    create_account(pk, sk)
+                })
+                .collect();
+            let mut replicas = vec![initial; USERS];
+            for (user, action) in actions {
+                let replica = replicas[user].clone();
+                replicas[user] = match action {
+                    Action::Add(element) => replica.apply_desc(&accounts[user], OrSet::add(element)),
+                    Action::Remove(element) => match replica.value.remove(element) {
+                        Some(remove) => {
+                            let replica = replica.apply_desc(&accounts[user], remove);
+                            prop_assert!(!replica.value.contains(&element));
+                            replica
+                        }
+                        None => replica,
+                    },
+                    Action::SyncFrom(other) => merge(replica, &replicas[other]),
+                };
+            }
+
+            // Everyone catches up with everyone, in two different orders
+            let forwards = replicas.iter().fold(replicas[0].clone(), merge);
+            let backwards = replicas.iter().rev().fold(replicas[USERS - 1].clone(), merge);
+            prop_assert_eq!(&forwards.value, &backwards.value);
+            prop_assert_eq!(forwards.state_vector(), backwards.state_vector());
+        }
+    }
+
+    #[test]
+    fn or_set_converges() {
+        // The testkit can't know what tags will exist, so these removes mostly refer to adds that never happen.
+        // That still checks that the order everything arrives in doesn't matter.
+        let (pk, _) = sign::gen_keypair();
+        let descriptions = prop_oneof![
+            any::<u8>().prop_map(OrSet::add),
+            (any::<u8>(), any::<u32>()).prop_map(move |(element, pun)| {
+                let mut tags = BTreeSet::new();
+                tags.insert((pk, Counter::Operation(pun, sign::Signature([0; 64]))));
+                OrSetDescription::Remove { element, tags }
+            }),
+        ];
+        let config = TestKitConfig {
+            users: 8,
+            ..TestKitConfig::default()
+        };
+        testkit::check_applyable_with(config, OrSet::new(), descriptions);
+    }
+}
@@ -0,0 +1,103 @@
+//! Tuples of `Applyable`s are `Applyable` too, so you can put an app's whole state in one CRDT without writing an
+//! `apply_without_idempotency_check` yourself. Something like `{title: LwwRegister, likes: Nat, tags: OrSet}` is
+//! just `(LwwRegister<String>, Nat, OrSet<String>)`.
+//!
+//! Each operation changes one element of the tuple. Its description says which element, and holds a description
+//! for that element's type: `Product3::Second(1)` adds one to the `Nat` above. The elements don't know about each
+//! other, so the tuple converges as long as each of them does.
+//!
+//! This works for tuples of two to six elements. If you want names instead of positions, derive `Applyable` for a
+//! struct instead.
+
+use serde::{Deserialize, Serialize};
+
+use crate::replicant::{Applyable, Counter, Time, UserPubKey};
+
+macro_rules! product {
+    ($description:ident, $arity:literal: $($variant:ident $field:tt $type:ident),+) => {
+        #[doc = concat!("Changes one element of a tuple of ", $arity, " `Applyable`s.")]
+        #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+        pub enum $description<$($type),+> {
+            $($variant($type)),+
+        }
+
+        impl<$($type: Applyable),+> Applyable for ($($type,)+) {
+            const NAME: &'static str = stringify!($description);
+
+            type Description = $description<$(<$type as Applyable>::Description),+>;
+
+            fn apply_without_idempotency_check(
+                mut self,
+                desc: Self::Description,
+                user_pub_key: UserPubKey,
+                counter: Counter,
+                time: Time,
+            ) -> Self {
+                match desc {
+                    $($description::$variant(desc) => {
+                        self.$field = self.$field.apply_without_idempotency_check(
+                            desc,
+                            user_pub_key,
+                            counter,
+                            time,
+                        );
+                    })+
+                }
+                self
+            }
+        }
+    };
+}
+
+product!(Product2, "two": First 0 A, Second 1 B);
+product!(Product3, "three": First 0 A, Second 1 B, Third 2 C);
+product!(Product4, "four": First 0 A, Second 1 B, Third 2 C, Fourth 3 D);
+product!(Product5, "five": First 0 A, Second 1 B, Third 2 C, Fourth 3 D, Fifth 4 E);
+product!(Product6, "six": First 0 A, Second 1 B, Third 2 C, Fourth 3 D, Fifth 4 E, Sixth 5 F);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{
+        create_account, create_crdt, create_crdt_info, get_random_id, Nat, CRDT,
+    };
+    use crate::testkit;
+    use crate::types::{LwwRegister, OrSet, PNCounter};
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+
+    #[test]
+    fn each_operation_changes_one_element() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let post = (LwwRegister::new("Untitled"), Nat::from(0), OrSet::new());
+        let initial = create_crdt(create_crdt_info(post, get_random_id()));
+
+        let crdt1 = initial
+            .clone()
+            .apply_desc(&account1, Product3::First("Hello"))
+            .apply_desc(&account1, Product3::Second(1))
+            .apply_desc(&account1, Product3::Third(OrSet::add("greeting")));
+        let crdt2 = initial
+            .apply_desc(&account2, Product3::Second(1))
+            .apply_desc(&account2, Product3::Third(OrSet::add("news")));
+
+        let missing = crdt2.ops_since(crdt1.state_vector()).collect::<Vec<_>>();
+        let merged = missing.into_iter().fold(crdt1, CRDT::apply);
+        let (title, likes, tags) = merged.value;
+        assert_eq!(title.value(), &"Hello");
+        assert_eq!(likes.value, 2);
+        assert_eq!(tags.iter().collect::<Vec<_>>(), vec![&"greeting", &"news"]);
+    }
+
+    #[test]
+    fn product_converges() {
+        let descriptions = prop_oneof![
+            any::<i64>().prop_map(Product2::First),
+            any::<u8>().prop_map(|element| Product2::Second(OrSet::add(element))),
+        ];
+        testkit::check_applyable((PNCounter::default(), OrSet::new()), descriptions);
+    }
+}
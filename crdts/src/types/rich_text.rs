@@ -0,0 +1,402 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Pun, Time, UserPubKey};
+use crate::types::text::{CharId, Text, TextDescription};
+
+/// Formatting that can be applied to a span of text.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Mark {
+    Bold,
+    Italic,
+    /// A link to this URL.
+    Link(String),
+}
+
+impl Mark {
+    // Whether typing at the end of the span should continue it. You'd expect to keep typing in bold after some
+    // bold text, but not to keep adding to a link.
+    fn expands(&self) -> bool {
+        match self {
+            Mark::Bold | Mark::Italic => true,
+            Mark::Link(_) => false,
+        }
+    }
+
+    // Marks of the same kind replace each other. A piece of text can be bold and italic, but it can't be two
+    // different links.
+    fn kind(&self) -> u8 {
+        match self {
+            Mark::Bold => 0,
+            Mark::Italic => 1,
+            Mark::Link(_) => 2,
+        }
+    }
+}
+
+/// A point in the text that stays put while people edit around it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Anchor {
+    Before(CharId),
+    After(CharId),
+    End,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum RichTextDescription {
+    /// Change the text itself. Make these with `RichText::insert` and `RichText::delete`.
+    Edit(TextDescription),
+    /// Add or remove a mark between two anchors. Make these with `RichText::format` and `RichText::unformat`.
+    Format {
+        mark: Mark,
+        add: bool,
+        start: Anchor,
+        end: Anchor,
+        // One more than the biggest `lamport` of any formatting the writer had seen
+        lamport: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+struct Format {
+    mark: Mark,
+    add: bool,
+    start: Anchor,
+    end: Anchor,
+}
+
+/// A run of text that all has the same formatting.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Span {
+    pub text: String,
+    pub marks: BTreeSet<Mark>,
+}
+
+/// RichText is `Text` with formatting (bold, italic and links), like a note in a note-taking app.
+///
+/// This works like Peritext. Formatting isn't stored on the characters. Instead, every time someone formats some
+/// text, we remember the anchors at either end of it, which are attached to characters so they don't move when
+/// other people edit. To figure out how a character is formatted, we go through every formatting operation that
+/// covers it, oldest first, and the last one of each kind wins. "Oldest" is decided by lamport numbers, like in
+/// `Text`, so it doesn't depend on the order operations arrive in.
+///
+/// Where the end of a span is anchored decides what happens when someone types right after it. Bold and italic
+/// end just before the next character, so text typed at the end is included. Links end just after their last
+/// character, so it isn't.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct RichText {
+    text: Text,
+    // Formatting, in the order it's applied
+    formats: BTreeMap<(u64, UserPubKey, Pun), Format>,
+}
+
+impl RichText {
+    pub fn new() -> Self {
+        RichText::default()
+    }
+
+    /// The text, without any formatting.
+    pub fn text(&self) -> &Text {
+        &self.text
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Insert `text` so that it starts at character `index`. `None` if `index` is past the end of the text.
+    pub fn insert(&self, index: usize, text: &str) -> Option<RichTextDescription> {
+        self.text.insert(index, text).map(RichTextDescription::Edit)
+    }
+
+    /// Delete `len` characters starting at `index`.
+    pub fn delete(&self, index: usize, len: usize) -> RichTextDescription {
+        RichTextDescription::Edit(self.text.delete(index, len))
+    }
+
+    /// Apply `mark` to `len` characters starting at `index`. `None` if that doesn't cover any characters.
+    pub fn format(&self, index: usize, len: usize, mark: Mark) -> Option<RichTextDescription> {
+        self.change_format(index, len, mark, true)
+    }
+
+    /// Remove `mark` from `len` characters starting at `index`. For links, the URL doesn't matter: any link is
+    /// removed. `None` if that doesn't cover any characters.
+    pub fn unformat(&self, index: usize, len: usize, mark: Mark) -> Option<RichTextDescription> {
+        self.change_format(index, len, mark, false)
+    }
+
+    fn change_format(
+        &self,
+        index: usize,
+        len: usize,
+        mark: Mark,
+        add: bool,
+    ) -> Option<RichTextDescription> {
+        let visible = self.text.visible_chars();
+        let selected = visible.get(index..(index + len).min(visible.len()))?;
+        let (first, last) = (*selected.first()?, *selected.last()?);
+        let end = if mark.expands() {
+            // The character straight after the last one, even if it's been deleted, since that's where new text
+            // typed at the end of the span goes.
+            let sequence = self.text.sequence();
+            let position = sequence.iter().position(|(id, _)| *id == last)?;
+            sequence
+                .get(position + 1)
+                .map_or(Anchor::End, |(next, _)| Anchor::Before(*next))
+        } else {
+            Anchor::After(last)
+        };
+        let lamport = self.formats.keys().map(|(l, _, _)| *l).max().unwrap_or(0) + 1;
+        Some(RichTextDescription::Format {
+            mark,
+            add,
+            start: Anchor::Before(first),
+            end,
+            lamport,
+        })
+    }
+
+    /// The text, split up into runs that all have the same formatting.
+    pub fn spans(&self) -> Vec<Span> {
+        let sequence = self.text.sequence();
+
+        // Every character takes up two points, one before it and one after it
+        let positions = sequence
+            .iter()
+            .enumerate()
+            .map(|(i, (id, _))| (*id, 2 * i))
+            .collect::<BTreeMap<_, _>>();
+        let point = |anchor: &Anchor| match anchor {
+            Anchor::Before(id) => positions.get(id).copied(),
+            Anchor::After(id) => positions.get(id).map(|p| p + 1),
+            Anchor::End => Some(2 * sequence.len()),
+        };
+
+        // The marks on every character, by kind
+        let mut marks: Vec<BTreeMap<u8, Mark>> = vec![BTreeMap::new(); sequence.len()];
+        for format in self.formats.values() {
+            // If we haven't got the characters it's anchored to yet, we don't know where it goes
+            let (start, end) = match (point(&format.start), point(&format.end)) {
+                (Some(start), Some(end)) => (start, end),
+                _ => continue,
+            };
+            for (i, char_marks) in marks.iter_mut().enumerate() {
+                if start <= 2 * i && 2 * i < end {
+                    if format.add {
+                        char_marks.insert(format.mark.kind(), format.mark.clone());
+                    } else {
+                        char_marks.remove(&format.mark.kind());
+                    }
+                }
+            }
+        }
+
+        let mut spans: Vec<Span> = vec![];
+        for ((_, value), char_marks) in sequence.into_iter().zip(marks) {
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+            let char_marks = char_marks.into_values().collect();
+            match spans.last_mut() {
+                Some(span) if span.marks == char_marks => span.text.push(value),
+                _ => spans.push(Span {
+                    text: value.to_string(),
+                    marks: char_marks,
+                }),
+            }
+        }
+        spans
+    }
+}
+
+// This writes the text as Markdown.
+impl fmt::Display for RichText {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for span in self.spans() {
+            let mut text = span.text;
+            for mark in span.marks.iter().rev() {
+                text = match mark {
+                    Mark::Bold => format!("**{}**", text),
+                    Mark::Italic => format!("_{}_", text),
+                    Mark::Link(url) => format!("[{}]({})", text, url),
+                };
+            }
+            write!(f, "{}", text)?;
+        }
+        Ok(())
+    }
+}
+
+impl Applyable for RichText {
+    const NAME: &'static str = "RichText";
+
+    type Description = RichTextDescription;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        time: Time,
+    ) -> Self {
+        match desc {
+            RichTextDescription::Edit(edit) => {
+                self.text =
+                    self.text
+                        .apply_without_idempotency_check(edit, user_pub_key, counter, time);
+            }
+            RichTextDescription::Format {
+                mark,
+                add,
+                start,
+                end,
+                lamport,
+            } => {
+                // The counter we get is always an `Operation`, since it's the counter of the operation we're applying
+                let pun = counter.pun().unwrap_or_default();
+                self.formats.insert(
+                    (lamport, user_pub_key, pun),
+                    Format {
+                        mark,
+                        add,
+                        start,
+                        end,
+                    },
+                );
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{create_account, create_crdt, create_crdt_info, get_random_id, CRDT};
+    use crate::testkit;
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+
+    fn merge(into: CRDT<RichText>, from: &CRDT<RichText>) -> CRDT<RichText> {
+        let missing = from.ops_since(into.state_vector()).collect::<Vec<_>>();
+        missing.into_iter().fold(into, CRDT::apply)
+    }
+
+    #[test]
+    fn bold_grows_when_you_type_at_the_end_but_links_dont() {
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+        let crdt = create_crdt(create_crdt_info(RichText::new(), get_random_id()));
+        let insert = crdt.value.insert(0, "bold link").unwrap();
+        let crdt = crdt.apply_desc(&account, insert);
+        let bold = crdt.value.format(0, 4, Mark::Bold).unwrap();
+        let crdt = crdt.apply_desc(&account, bold);
+        let link = crdt
+            .value
+            .format(5, 4, Mark::Link("https://example.com".to_string()))
+            .unwrap();
+        let crdt = crdt.apply_desc(&account, link);
+        assert_eq!(
+            crdt.value.to_string(),
+            "**bold** [link](https://example.com)"
+        );
+
+        let insert = crdt.value.insert(4, "er").unwrap();
+        let crdt = crdt.apply_desc(&account, insert);
+        let insert = crdt.value.insert(11, "s").unwrap();
+        let crdt = crdt.apply_desc(&account, insert);
+        // Typing before the start of a span doesn't extend it either
+        let insert = crdt.value.insert(0, "a ").unwrap();
+        let crdt = crdt.apply_desc(&account, insert);
+        assert_eq!(
+            crdt.value.to_string(),
+            "a **bolder** [link](https://example.com)s"
+        );
+    }
+
+    #[test]
+    fn concurrent_formatting_converges() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(RichText::new(), get_random_id()));
+        let insert = initial.value.insert(0, "The quick fox").unwrap();
+        let crdt1 = initial.apply_desc(&account1, insert);
+        let crdt2 = crdt1.clone();
+
+        // One user bolds "quick fox" while the other italicises "The quick" and types at the end
+        let bold = crdt1.value.format(4, 9, Mark::Bold).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, bold);
+        let italic = crdt2.value.format(0, 9, Mark::Italic).unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, italic);
+        let insert = crdt2.value.insert(13, "es").unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, insert);
+
+        let merged1 = merge(crdt1.clone(), &crdt2);
+        let merged2 = merge(crdt2, &crdt1);
+        assert_eq!(merged1.value, merged2.value);
+        let spans = merged1.value.spans();
+        let marks = |text: &str| {
+            spans
+                .iter()
+                .find(|span| span.text == text)
+                .map(|span| span.marks.iter().cloned().collect::<Vec<_>>())
+        };
+        assert_eq!(marks("The "), Some(vec![Mark::Italic]));
+        assert_eq!(marks("quick"), Some(vec![Mark::Bold, Mark::Italic]));
+        // The bold was anchored to the end of the text, so the new "es" is bold too
+        assert_eq!(marks(" foxes"), Some(vec![Mark::Bold]));
+
+        // Unbolding afterwards wins over the earlier bold
+        let unbold = merged1.value.unformat(0, 19, Mark::Bold).unwrap();
+        let unbolded = merged1.apply_desc(&account1, unbold);
+        assert_eq!(unbolded.value.to_string(), "_The quick_ foxes");
+    }
+
+    #[test]
+    fn rich_text_converges() {
+        // These mostly refer to characters that don't exist, which is fine: they just stay hidden
+        let (pk, _) = sign::gen_keypair();
+        let char_id = (0..4u32, 0..3u32).prop_map(move |(pun, offset)| CharId {
+            user_pub_key: pk,
+            pun,
+            offset,
+        });
+        let anchor = prop_oneof![
+            char_id.clone().prop_map(Anchor::Before),
+            char_id.clone().prop_map(Anchor::After),
+            Just(Anchor::End),
+        ];
+        let mark = prop_oneof![
+            Just(Mark::Bold),
+            Just(Mark::Italic),
+            "[a-c]".prop_map(Mark::Link),
+        ];
+        let descriptions = prop_oneof![
+            (prop::option::of(char_id), 0..5u64, "[a-z]{0,3}").prop_map(
+                |(after, lamport, text)| RichTextDescription::Edit(TextDescription::Insert {
+                    after,
+                    lamport,
+                    text
+                })
+            ),
+            (mark, any::<bool>(), anchor.clone(), anchor, 0..5u64).prop_map(
+                |(mark, add, start, end, lamport)| RichTextDescription::Format {
+                    mark,
+                    add,
+                    start,
+                    end,
+                    lamport
+                }
+            ),
+        ];
+        testkit::check_applyable(RichText::new(), descriptions);
+    }
+}
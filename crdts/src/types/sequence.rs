@@ -0,0 +1,377 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Pun, Time, UserPubKey};
+use crate::types::text::rga_order;
+
+/// Identifies an operation that made a place in the list (an insert or a move). An element is identified by the
+/// place it was first inserted at.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ItemId {
+    pub user_pub_key: UserPubKey,
+    pub pun: Pun,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum SequenceDescription<E> {
+    /// Insert `value` right after the place `after` (or at the start, if it's `None`). Make these with
+    /// `Sequence::insert`.
+    Insert {
+        after: Option<ItemId>,
+        lamport: u64,
+        value: E,
+    },
+    /// Move `element` so it's right after the place `after`. Make these with `Sequence::move_element`.
+    Move {
+        element: ItemId,
+        after: Option<ItemId>,
+        lamport: u64,
+    },
+    /// Delete an element. Make these with `Sequence::delete`.
+    Delete(ItemId),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+struct Place {
+    after: Option<ItemId>,
+    lamport: u64,
+}
+
+/// Sequence is a list of anything (like todo items, or slides) that lots of people can edit at once. You can
+/// insert, delete and move elements.
+///
+/// Underneath, it's an RGA like `Text`, but of places rather than characters. Inserting an element makes a new
+/// place for it. Moving an element makes another new place and says the element lives there now. Every element
+/// keeps track of where it lives like an `LwwRegister`, so if two people move the same element at the same time,
+/// one of the moves wins and the element only shows up once. The places an element used to live are left
+/// empty.
+///
+/// You normally edit it by index with `insert`, `delete` and `move_element`, which turn the edit into a
+/// description.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Sequence<E> {
+    places: BTreeMap<ItemId, Place>,
+    values: BTreeMap<ItemId, E>,
+    // Where every element lives, and the (lamport, id) of the insert or move that put it there. The biggest wins.
+    // A move can arrive before the element it moves, so this can mention elements we don't have yet.
+    locations: BTreeMap<ItemId, ((u64, ItemId), ItemId)>,
+    deleted: BTreeSet<ItemId>,
+}
+
+impl<E> Sequence<E> {
+    pub fn new() -> Self {
+        Sequence {
+            places: BTreeMap::new(),
+            values: BTreeMap::new(),
+            locations: BTreeMap::new(),
+            deleted: BTreeSet::new(),
+        }
+    }
+
+    /// The ids of the elements, in order.
+    pub fn ids(&self) -> Vec<ItemId> {
+        // Which element lives in each place
+        let residents = self
+            .locations
+            .iter()
+            .filter(|(element, _)| {
+                self.values.contains_key(element) && !self.deleted.contains(element)
+            })
+            .map(|(element, (_, place))| (*place, *element))
+            .collect::<BTreeMap<_, _>>();
+        rga_order(
+            self.places
+                .iter()
+                .map(|(id, place)| (*id, place.after, place.lamport)),
+        )
+        .into_iter()
+        .filter_map(|place| residents.get(&place).copied())
+        .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &E> {
+        self.ids().into_iter().map(move |id| &self.values[&id])
+    }
+
+    pub fn get(&self, index: usize) -> Option<&E> {
+        self.ids().get(index).map(|id| &self.values[id])
+    }
+
+    /// Where an element is in the list, so you can keep track of it while other people edit.
+    pub fn index_of(&self, element: ItemId) -> Option<usize> {
+        self.ids().iter().position(|id| *id == element)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn next_lamport(&self) -> u64 {
+        self.places
+            .values()
+            .map(|place| place.lamport)
+            .max()
+            .unwrap_or(0)
+            + 1
+    }
+
+    // The place you'd put something to make it end up at `index` in `ids`. `None` if `index` is past the end.
+    fn place_before(&self, ids: &[ItemId], index: usize) -> Option<Option<ItemId>> {
+        match index {
+            0 => Some(None),
+            _ => ids
+                .get(index - 1)
+                .map(|element| Some(self.locations[element].1)),
+        }
+    }
+
+    /// Insert `value` at `index`. `None` if `index` is past the end of the list.
+    pub fn insert(&self, index: usize, value: E) -> Option<SequenceDescription<E>> {
+        Some(SequenceDescription::Insert {
+            after: self.place_before(&self.ids(), index)?,
+            lamport: self.next_lamport(),
+            value,
+        })
+    }
+
+    /// Delete the element at `index`. `None` if there isn't one.
+    pub fn delete(&self, index: usize) -> Option<SequenceDescription<E>> {
+        self.ids()
+            .get(index)
+            .copied()
+            .map(SequenceDescription::Delete)
+    }
+
+    /// Move the element at `from` so that it ends up at `to`. `None` if there isn't an element at `from`, or if `to`
+    /// is past the end of the list.
+    pub fn move_element(&self, from: usize, to: usize) -> Option<SequenceDescription<E>> {
+        let mut ids = self.ids();
+        if from >= ids.len() {
+            return None;
+        }
+        let element = ids.remove(from);
+        Some(SequenceDescription::Move {
+            element,
+            after: self.place_before(&ids, to)?,
+            lamport: self.next_lamport(),
+        })
+    }
+}
+
+impl<E> Default for Sequence<E> {
+    fn default() -> Self {
+        Sequence::new()
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Sequence<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, element) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", element)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl<E: Clone> Applyable for Sequence<E> {
+    const NAME: &'static str = "Sequence";
+
+    type Description = SequenceDescription<E>;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        _: Time,
+    ) -> Self {
+        // The counter we get is always an `Operation`, since it's the counter of the operation we're applying
+        let id = ItemId {
+            user_pub_key,
+            pun: counter.pun().unwrap_or_default(),
+        };
+        let (element, after, lamport) = match desc {
+            SequenceDescription::Insert {
+                after,
+                lamport,
+                value,
+            } => {
+                self.values.insert(id, value);
+                (id, after, lamport)
+            }
+            SequenceDescription::Move {
+                element,
+                after,
+                lamport,
+            } => (element, after, lamport),
+            SequenceDescription::Delete(element) => {
+                self.deleted.insert(element);
+                return self;
+            }
+        };
+        self.places.insert(id, Place { after, lamport });
+        let location = ((lamport, id), id);
+        let current = self.locations.entry(element).or_insert(location);
+        if location > *current {
+            *current = location;
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{
+        create_account, create_crdt, create_crdt_info, get_random_id, Account, CRDT,
+    };
+    use crate::testkit;
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+
+    fn merge(into: CRDT<Sequence<u8>>, from: &CRDT<Sequence<u8>>) -> CRDT<Sequence<u8>> {
+        let missing = from.ops_since(into.state_vector()).collect::<Vec<_>>();
+        missing.into_iter().fold(into, CRDT::apply)
+    }
+
+    #[test]
+    fn concurrent_moves_dont_duplicate() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let mut crdt1 = create_crdt(create_crdt_info(Sequence::new(), get_random_id()));
+        for (i, value) in [1, 2, 3, 4].iter().enumerate() {
+            let insert = crdt1.value.insert(i, *value).unwrap();
+            crdt1 = crdt1.apply_desc(&account1, insert);
+        }
+        let crdt2 = crdt1.clone();
+        assert_eq!(crdt1.value.insert(5, 5), None);
+        assert_eq!(crdt1.value.move_element(0, 4), None);
+
+        // Both users move 1, to different places
+        let to_the_end = crdt1.value.move_element(0, 3).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, to_the_end);
+        assert_eq!(crdt1.value.iter().collect::<Vec<_>>(), vec![&2, &3, &4, &1]);
+        let to_the_middle = crdt2.value.move_element(0, 2).unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, to_the_middle);
+        assert_eq!(crdt2.value.iter().collect::<Vec<_>>(), vec![&2, &3, &1, &4]);
+
+        let merged1 = merge(crdt1.clone(), &crdt2);
+        let merged2 = merge(crdt2, &crdt1);
+        assert_eq!(merged1.value, merged2.value);
+        assert_eq!(merged1.value.len(), 4);
+        assert_eq!(merged1.value.iter().filter(|value| **value == 1).count(), 1);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Action {
+        // Indexes are picked as a fraction of the length of the list
+        Insert(f64, u8),
+        Delete(f64),
+        Move(f64, f64),
+        SyncFrom(usize),
+    }
+
+    const USERS: usize = 4;
+
+    fn action() -> impl Strategy<Value = (usize, Action)> {
+        let action = prop_oneof![
+            (0.0..=1.0, any::<u8>()).prop_map(|(at, value)| Action::Insert(at, value)),
+            (0.0..1.0).prop_map(Action::Delete),
+            (0.0..1.0, 0.0..1.0).prop_map(|(from, to)| Action::Move(from, to)),
+            (0..USERS).prop_map(Action::SyncFrom),
+        ];
+        (0..USERS, action)
+    }
+
+    proptest! {
+        #[test]
+        fn edits_by_index_converge(actions in prop::collection::vec(action(), 1..40)) {
+            let initial = create_crdt(create_crdt_info(Sequence::new(), get_random_id()));
+            let accounts: Vec<Account> = (0..USERS)
+                .map(|_| {
+                    let (pk, sk) = sign::gen_keypair();
+                    create_account(pk, sk)
+                })
+                .collect();
+            let mut replicas = vec![initial; USERS];
+            for (user, action) in actions {
+                let replica = replicas[user].clone();
+                let mut expected = replica.value.iter().copied().collect::<Vec<_>>();
+                let len = expected.len();
+                let at = |fraction: f64, len: usize| (fraction * len as f64) as usize;
+                let desc = match action {
+                    Action::Insert(fraction, value) => {
+                        expected.insert(at(fraction, len), value);
+                        replica.value.insert(at(fraction, len), value)
+                    }
+                    Action::Delete(fraction) if len > 0 => {
+                        expected.remove(at(fraction, len));
+                        replica.value.delete(at(fraction, len))
+                    }
+                    Action::Move(from, to) if len > 0 => {
+                        let value = expected.remove(at(from, len));
+                        expected.insert(at(to, len - 1), value);
+                        replica.value.move_element(at(from, len), at(to, len - 1))
+                    }
+                    Action::SyncFrom(other) => {
+                        replicas[user] = merge(replica, &replicas[other]);
+                        continue;
+                    }
+                    _ => None,
+                };
+                replicas[user] = match desc {
+                    Some(desc) => replica.apply_desc(&accounts[user], desc),
+                    None => replica,
+                };
+                // The edit did exactly what we asked
+                prop_assert_eq!(replicas[user].value.iter().copied().collect::<Vec<_>>(), expected);
+            }
+
+            let forwards = replicas.iter().fold(replicas[0].clone(), merge);
+            let backwards = replicas.iter().rev().fold(replicas[USERS - 1].clone(), merge);
+            prop_assert_eq!(&forwards.value, &backwards.value);
+            // No element shows up twice
+            let ids = forwards.value.ids();
+            prop_assert_eq!(ids.len(), ids.iter().collect::<BTreeSet<_>>().len());
+        }
+    }
+
+    #[test]
+    fn sequence_converges() {
+        // These mostly refer to places that don't exist, which is fine: they just stay hidden
+        let (pk, _) = sign::gen_keypair();
+        let item_id = (0..4u32).prop_map(move |pun| ItemId {
+            user_pub_key: pk,
+            pun,
+        });
+        let descriptions = prop_oneof![
+            (prop::option::of(item_id.clone()), 0..5u64, any::<u8>()).prop_map(
+                |(after, lamport, value)| SequenceDescription::Insert {
+                    after,
+                    lamport,
+                    value
+                }
+            ),
+            (item_id.clone(), prop::option::of(item_id.clone()), 0..5u64).prop_map(
+                |(element, after, lamport)| SequenceDescription::Move {
+                    element,
+                    after,
+                    lamport
+                }
+            ),
+            item_id.prop_map(SequenceDescription::Delete),
+        ];
+        testkit::check_applyable(Sequence::new(), descriptions);
+    }
+}
@@ -0,0 +1,460 @@
+//! A table of text, like a spreadsheet, that lots of people can edit at once.
+//!
+//! The rows and the columns are each a `Sequence`, so people can insert, delete and move them at the same time
+//! without stepping on each other. Every cell is an `LwwRegister`, named by the row and column it's in rather than
+//! by where they are, so a cell follows its row and column around when they move. If two people write to the same
+//! cell at the same time, the last write wins. If someone writes to a cell while someone else deletes its row or
+//! column, the row or column stays deleted.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+
+use crate::replicant::{Applyable, Counter, Time, UserPubKey};
+use crate::types::sequence::{ItemId, SequenceDescription};
+use crate::types::{LwwRegister, Sequence};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum TableDescription {
+    /// Insert, delete or move a row. Make these with `Table::insert_row`, `Table::delete_row` and `Table::move_row`.
+    Row(SequenceDescription<()>),
+    /// Insert, delete or move a column. Make these with `Table::insert_column`, `Table::delete_column` and
+    /// `Table::move_column`.
+    Column(SequenceDescription<()>),
+    /// Write to a cell. Make these with `Table::set`.
+    Set {
+        row: ItemId,
+        column: ItemId,
+        value: String,
+    },
+}
+
+/// Table is a grid of text. You edit it by index, the same way as a `Sequence`. See the module docs for how
+/// conflicts get resolved.
+///
+/// Cells that have never been written to are empty. Cells in deleted rows and columns are still stored, but you
+/// can't see them.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct Table {
+    rows: Sequence<()>,
+    columns: Sequence<()>,
+    cells: BTreeMap<(ItemId, ItemId), LwwRegister<String>>,
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Table::default()
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.columns.len()
+    }
+
+    fn cell(&self, row: ItemId, column: ItemId) -> &str {
+        self.cells
+            .get(&(row, column))
+            .map_or("", |cell| cell.value().as_str())
+    }
+
+    /// The text in a cell. `None` if the row or column doesn't exist.
+    pub fn get(&self, row: usize, column: usize) -> Option<&str> {
+        let row = *self.rows.ids().get(row)?;
+        let column = *self.columns.ids().get(column)?;
+        Some(self.cell(row, column))
+    }
+
+    /// Every cell, row by row.
+    pub fn to_rows(&self) -> Vec<Vec<&str>> {
+        let columns = self.columns.ids();
+        self.rows
+            .ids()
+            .into_iter()
+            .map(|row| {
+                columns
+                    .iter()
+                    .map(|column| self.cell(row, *column))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The table as CSV. Cells with commas, quotes or line breaks in them get quoted, and every row ends with a
+    /// line break.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        for row in self.to_rows() {
+            let cells = row
+                .into_iter()
+                .map(|cell| {
+                    if cell.contains(&[',', '"', '\n', '\r'][..]) {
+                        format!("\"{}\"", cell.replace('"', "\"\""))
+                    } else {
+                        cell.to_string()
+                    }
+                })
+                .collect::<Vec<_>>();
+            csv.push_str(&cells.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// Insert an empty row at `index`. `None` if `index` is past the last row.
+    pub fn insert_row(&self, index: usize) -> Option<TableDescription> {
+        self.rows.insert(index, ()).map(TableDescription::Row)
+    }
+
+    /// `None` if there isn't a row at `index`.
+    pub fn delete_row(&self, index: usize) -> Option<TableDescription> {
+        self.rows.delete(index).map(TableDescription::Row)
+    }
+
+    /// Move the row at `from` so it ends up at `to`. `None` if there isn't a row at `from`, or if `to` is past the
+    /// last row.
+    pub fn move_row(&self, from: usize, to: usize) -> Option<TableDescription> {
+        self.rows.move_element(from, to).map(TableDescription::Row)
+    }
+
+    /// Insert an empty column at `index`. `None` if `index` is past the last column.
+    pub fn insert_column(&self, index: usize) -> Option<TableDescription> {
+        self.columns.insert(index, ()).map(TableDescription::Column)
+    }
+
+    /// `None` if there isn't a column at `index`.
+    pub fn delete_column(&self, index: usize) -> Option<TableDescription> {
+        self.columns.delete(index).map(TableDescription::Column)
+    }
+
+    /// Move the column at `from` so it ends up at `to`. `None` if there isn't a column at `from`, or if `to` is past
+    /// the last column.
+    pub fn move_column(&self, from: usize, to: usize) -> Option<TableDescription> {
+        self.columns
+            .move_element(from, to)
+            .map(TableDescription::Column)
+    }
+
+    /// Write `value` to a cell. `None` if the row or column doesn't exist.
+    pub fn set(&self, row: usize, column: usize, value: String) -> Option<TableDescription> {
+        Some(TableDescription::Set {
+            row: *self.rows.ids().get(row)?,
+            column: *self.columns.ids().get(column)?,
+            value,
+        })
+    }
+}
+
+/// Written as a grid, with the columns lined up.
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = self.to_rows();
+        let widths = (0..self.column_count())
+            .map(|column| {
+                rows.iter()
+                    .map(|row| row[column].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+        for (i, row) in rows.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            for (column, cell) in row.iter().enumerate() {
+                if column > 0 {
+                    write!(f, " | ")?;
+                }
+                write!(f, "{:width$}", cell, width = widths[column])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Applyable for Table {
+    const NAME: &'static str = "Table";
+
+    type Description = TableDescription;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        time: Time,
+    ) -> Self {
+        match desc {
+            TableDescription::Row(desc) => {
+                self.rows = mem::take(&mut self.rows).apply_without_idempotency_check(
+                    desc,
+                    user_pub_key,
+                    counter,
+                    time,
+                );
+            }
+            TableDescription::Column(desc) => {
+                self.columns = mem::take(&mut self.columns).apply_without_idempotency_check(
+                    desc,
+                    user_pub_key,
+                    counter,
+                    time,
+                );
+            }
+            TableDescription::Set { row, column, value } => {
+                let cell = self.cells.entry((row, column)).or_default();
+                *cell = mem::take(cell).apply_without_idempotency_check(
+                    value,
+                    user_pub_key,
+                    counter,
+                    time,
+                );
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{
+        create_account, create_crdt, create_crdt_info, get_random_id, Account, CRDT,
+    };
+    use crate::testkit;
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+
+    fn merge(into: CRDT<Table>, from: &CRDT<Table>) -> CRDT<Table> {
+        let missing = from.ops_since(into.state_vector()).collect::<Vec<_>>();
+        missing.into_iter().fold(into, CRDT::apply)
+    }
+
+    fn set(
+        crdt: CRDT<Table>,
+        account: &Account,
+        row: usize,
+        column: usize,
+        value: &str,
+    ) -> CRDT<Table> {
+        let desc = crdt.value.set(row, column, value.to_string()).unwrap();
+        crdt.apply_desc(account, desc)
+    }
+
+    #[test]
+    fn cells_follow_their_rows_and_columns() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(Table::new(), get_random_id()));
+
+        // A 2x2 table
+        let mut crdt1 = initial.clone();
+        for _ in 0..2 {
+            let desc = crdt1.value.insert_row(0).unwrap();
+            crdt1 = crdt1.apply_desc(&account1, desc);
+            let desc = crdt1.value.insert_column(0).unwrap();
+            crdt1 = crdt1.apply_desc(&account1, desc);
+        }
+        let crdt1 = set(crdt1, &account1, 0, 0, "name");
+        let crdt1 = set(crdt1, &account1, 0, 1, "age");
+        let crdt1 = set(crdt1, &account1, 1, 0, "Ada");
+        let crdt1 = set(crdt1, &account1, 1, 1, "36");
+        let crdt2 = merge(initial, &crdt1);
+        assert_eq!(
+            crdt2.value.to_rows(),
+            vec![vec!["name", "age"], vec!["Ada", "36"]]
+        );
+
+        // The first user moves the age column to the front and adds a row. At the same time, the second user
+        // inserts a column between the other two and fills it in.
+        let desc = crdt1.value.move_column(1, 0).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, desc);
+        assert_eq!(crdt1.value.insert_row(3), None);
+        assert_eq!(crdt1.value.move_column(0, 2), None);
+        let desc = crdt1.value.insert_row(2).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, desc);
+        let crdt1 = set(crdt1, &account1, 2, 1, "Grace");
+        let desc = crdt2.value.insert_column(1).unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, desc);
+        let crdt2 = set(crdt2, &account2, 0, 1, "city");
+        let crdt2 = set(crdt2, &account2, 1, 1, "London");
+
+        let merged1 = merge(crdt1.clone(), &crdt2);
+        let merged2 = merge(crdt2, &crdt1);
+        assert_eq!(merged1.value, merged2.value);
+        assert_eq!(
+            merged1.value.to_rows(),
+            vec![
+                vec!["age", "name", "city"],
+                vec!["36", "Ada", "London"],
+                vec!["", "Grace", ""],
+            ]
+        );
+        assert_eq!(merged1.value.get(1, 2), Some("London"));
+        assert_eq!(merged1.value.get(3, 0), None);
+        assert_eq!(
+            format!("{}", merged1.value),
+            "age | name  | city  \n36  | Ada   | London\n    | Grace |       "
+        );
+    }
+
+    #[test]
+    fn writing_to_a_deleted_row_does_not_bring_it_back() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(Table::new(), get_random_id()));
+        let desc = initial.value.insert_row(0).unwrap();
+        let crdt1 = initial.clone().apply_desc(&account1, desc);
+        let desc = crdt1.value.insert_column(0).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, desc);
+        let crdt2 = merge(initial, &crdt1);
+
+        let desc = crdt1.value.delete_row(0).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, desc);
+        let crdt2 = set(crdt2, &account2, 0, 0, "too late");
+
+        let merged1 = merge(crdt1.clone(), &crdt2);
+        let merged2 = merge(crdt2, &crdt1);
+        assert_eq!(merged1.value, merged2.value);
+        assert_eq!(merged1.value.row_count(), 0);
+        assert_eq!(merged1.value.column_count(), 1);
+        assert_eq!(merged1.value.to_csv(), "");
+    }
+
+    #[test]
+    fn csv_quotes_cells_that_need_it() {
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+        let mut crdt = create_crdt(create_crdt_info(Table::new(), get_random_id()));
+        for _ in 0..2 {
+            let desc = crdt.value.insert_row(0).unwrap();
+            crdt = crdt.apply_desc(&account, desc);
+        }
+        for _ in 0..3 {
+            let desc = crdt.value.insert_column(0).unwrap();
+            crdt = crdt.apply_desc(&account, desc);
+        }
+        let crdt = set(crdt, &account, 0, 0, "plain");
+        let crdt = set(crdt, &account, 0, 1, "a, b");
+        let crdt = set(crdt, &account, 0, 2, "say \"hi\"");
+        let crdt = set(crdt, &account, 1, 1, "two\nlines");
+        assert_eq!(
+            crdt.value.to_csv(),
+            "plain,\"a, b\",\"say \"\"hi\"\"\"\n,\"two\nlines\",\n"
+        );
+    }
+
+    #[derive(Debug, Clone)]
+    enum Action {
+        InsertRow(usize),
+        InsertColumn(usize),
+        DeleteRow(usize),
+        DeleteColumn(usize),
+        MoveRow(usize, usize),
+        MoveColumn(usize, usize),
+        Set(usize, usize, u8),
+        // Catch up on everything another user has
+        SyncFrom(usize),
+    }
+
+    const USERS: usize = 4;
+
+    fn action() -> impl Strategy<Value = (usize, Action)> {
+        let action = prop_oneof![
+            (0..4usize).prop_map(Action::InsertRow),
+            (0..4usize).prop_map(Action::InsertColumn),
+            (0..4usize).prop_map(Action::DeleteRow),
+            (0..4usize).prop_map(Action::DeleteColumn),
+            (0..4usize, 0..4usize).prop_map(|(from, to)| Action::MoveRow(from, to)),
+            (0..4usize, 0..4usize).prop_map(|(from, to)| Action::MoveColumn(from, to)),
+            (0..4usize, 0..4usize, any::<u8>())
+                .prop_map(|(row, column, value)| Action::Set(row, column, value)),
+            (0..USERS).prop_map(Action::SyncFrom),
+        ];
+        (0..USERS, action)
+    }
+
+    proptest! {
+        // Edits refer to rows and columns that really exist, so like the `Sequence` test we drive each user's
+        // replica ourselves. Anything that's out of range for them comes back as `None`, and we skip it.
+        #[test]
+        fn replicas_with_many_users_converge(actions in prop::collection::vec(action(), 1..50)) {
+            let initial = create_crdt(create_crdt_info(Table::new(), get_random_id()));
+            let accounts: Vec<Account> = (0..USERS)
+                .map(|_| {
+                    let (pk, sk) = sign::gen_keypair();
+                    create_account(pk, sk)
+                })
+                .collect();
+            let mut replicas = vec![initial; USERS];
+            for (user, action) in actions {
+                let replica = replicas[user].clone();
+                let table = &replica.value;
+                let desc = match action {
+                    Action::InsertRow(index) => table.insert_row(index),
+                    Action::InsertColumn(index) => table.insert_column(index),
+                    Action::DeleteRow(index) => table.delete_row(index),
+                    Action::DeleteColumn(index) => table.delete_column(index),
+                    Action::MoveRow(from, to) => table.move_row(from, to),
+                    Action::MoveColumn(from, to) => table.move_column(from, to),
+                    Action::Set(row, column, value) => table.set(row, column, value.to_string()),
+                    Action::SyncFrom(other) => {
+                        replicas[user] = merge(replica, &replicas[other]);
+                        continue;
+                    }
+                };
+                if let Some(desc) = desc {
+                    replicas[user] = replica.apply_desc(&accounts[user], desc);
+                }
+            }
+
+            // Everyone catches up with everyone, in two different orders
+            let forwards = replicas.iter().fold(replicas[0].clone(), merge);
+            let backwards = replicas.iter().rev().fold(replicas[USERS - 1].clone(), merge);
+            prop_assert_eq!(&forwards.value, &backwards.value);
+            prop_assert_eq!(forwards.value.to_csv(), backwards.value.to_csv());
+        }
+    }
+
+    #[test]
+    fn table_converges() {
+        // The testkit can't know which rows and columns will exist, so cells get written by whatever ids we make
+        // up. Most of them never turn up, but the order everything arrives in still mustn't matter.
+        let (pk, _) = sign::gen_keypair();
+        let id = move |pun: u32| ItemId {
+            user_pub_key: pk,
+            pun: pun % 4,
+        };
+        let descriptions = prop_oneof![
+            (0..4u64).prop_map(
+                |lamport| TableDescription::Row(SequenceDescription::Insert {
+                    after: None,
+                    lamport,
+                    value: (),
+                })
+            ),
+            (0..4u64).prop_map(
+                |lamport| TableDescription::Column(SequenceDescription::Insert {
+                    after: None,
+                    lamport,
+                    value: (),
+                })
+            ),
+            (any::<u32>(), any::<u32>(), any::<u8>()).prop_map(move |(row, column, value)| {
+                TableDescription::Set {
+                    row: id(row),
+                    column: id(column),
+                    value: value.to_string(),
+                }
+            }),
+        ];
+        testkit::check_applyable(Table::new(), descriptions);
+    }
+}
@@ -0,0 +1,466 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Invertible, Pun, Time, UserPubKey};
+
+/// Identifies one character, forever. It's the operation that inserted it (who made it, and their counter), and
+/// how far into that insertion the character was.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct CharId {
+    pub user_pub_key: UserPubKey,
+    pub pun: Pun,
+    pub offset: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum TextDescription {
+    /// Insert `text` right after the character `after` (or at the start, if it's `None`).
+    /// Make these with `Text::insert`.
+    Insert {
+        after: Option<CharId>,
+        // One more than the biggest `lamport` the writer had seen. It's what puts newer insertions first.
+        lamport: u64,
+        text: String,
+    },
+    /// Delete these characters. Make these with `Text::delete`.
+    Delete(BTreeSet<CharId>),
+    /// Bring these characters back, taking back a delete of them. `CRDT::undo` makes these.
+    Restore(BTreeSet<CharId>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+struct Char {
+    after: Option<CharId>,
+    lamport: u64,
+    value: char,
+}
+
+// Puts the nodes of an RGA tree in order. Each node is (its id, what it was inserted after, its lamport number).
+// After a node come the nodes inserted after it, biggest lamport number (then id) first, each followed by
+// everything inserted after them, and so on. Nodes inserted after something that isn't there are left out.
+pub(crate) fn rga_order<K: Ord + Copy>(
+    nodes: impl IntoIterator<Item = (K, Option<K>, u64)>,
+) -> Vec<K> {
+    // Who was inserted after whom, with the ones that come first at the end (so we can pop them off)
+    let mut children: BTreeMap<Option<K>, Vec<(u64, K)>> = BTreeMap::new();
+    for (id, after, lamport) in nodes {
+        children.entry(after).or_default().push((lamport, id));
+    }
+    for siblings in children.values_mut() {
+        siblings.sort();
+    }
+
+    // We don't recurse, since pasting a big block of text makes the tree very deep
+    let mut order = vec![];
+    let mut stack = children.remove(&None).unwrap_or_default();
+    while let Some((_, id)) = stack.pop() {
+        order.push(id);
+        if let Some(after) = children.remove(&Some(id)) {
+            stack.extend(after);
+        }
+    }
+    order
+}
+
+/// Text is plain text that lots of people can edit at once, like in a Google Doc.
+///
+/// It works like RGA. Every character that's ever been typed has a `CharId`, and remembers the character it was
+/// typed after. That makes a tree, and reading it depth first gives you the text. When two characters were typed
+/// after the same one, the one typed "later" comes first, where later means it has the bigger lamport number (or,
+/// if they're the same, the bigger `CharId`). Since every insertion gets a lamport number bigger than anything its
+/// writer had seen, text always ends up where its writer put it, and two people typing at the same place don't
+/// get their words mixed together.
+///
+/// Deleted characters stay in the tree (but aren't shown), since other characters might have been typed after them.
+/// We count how many times each one has been deleted, so that undoing one delete doesn't bring back a character
+/// someone else deleted too. A character whose predecessor hasn't arrived yet isn't shown until it does.
+///
+/// You normally edit it by index with `insert` and `delete`, which turn the edit into a description.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct Text {
+    chars: BTreeMap<CharId, Char>,
+    // How many deletes of each character haven't been taken back. A character is hidden while this is above zero.
+    deleted: BTreeMap<CharId, i64>,
+}
+
+impl Text {
+    pub fn new() -> Self {
+        Text::default()
+    }
+
+    fn is_deleted(&self, id: &CharId) -> bool {
+        self.deleted.get(id).is_some_and(|deletes| *deletes > 0)
+    }
+
+    // Adds `by` to how many times each of `ids` has been deleted
+    fn count_deletes(&mut self, ids: BTreeSet<CharId>, by: i64) {
+        for id in ids {
+            let deletes = self.deleted.entry(id).or_insert(0);
+            *deletes += by;
+            if *deletes == 0 {
+                self.deleted.remove(&id);
+            }
+        }
+    }
+
+    /// Every character in the text (including deleted ones), in order.
+    fn all_chars(&self) -> Vec<CharId> {
+        rga_order(self.chars.iter().map(|(id, c)| (*id, c.after, c.lamport)))
+    }
+
+    /// Every character in the text in order, including deleted ones (which are `None`).
+    pub(crate) fn sequence(&self) -> Vec<(CharId, Option<char>)> {
+        self.all_chars()
+            .into_iter()
+            .map(|id| {
+                let value = Some(self.chars[&id].value).filter(|_| !self.is_deleted(&id));
+                (id, value)
+            })
+            .collect()
+    }
+
+    /// The characters you can see, in order.
+    pub(crate) fn visible_chars(&self) -> Vec<CharId> {
+        self.all_chars()
+            .into_iter()
+            .filter(|id| !self.is_deleted(id))
+            .collect()
+    }
+
+    /// The number of characters in the text.
+    pub fn len(&self) -> usize {
+        self.visible_chars().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The id of the character at `index`, so you can keep track of a position while other people edit.
+    pub fn char_id(&self, index: usize) -> Option<CharId> {
+        self.visible_chars().get(index).copied()
+    }
+
+    // One more than the biggest lamport number we've seen, so that new text goes before anything else typed at
+    // the same place.
+    fn next_lamport(&self) -> u64 {
+        self.chars.values().map(|c| c.lamport).max().unwrap_or(0) + 1
+    }
+
+    /// Insert `text` so that it starts at character `index`. `None` if `index` is past the end of the text.
+    pub fn insert(&self, index: usize, text: &str) -> Option<TextDescription> {
+        let after = match index {
+            0 => None,
+            _ => Some(self.visible_chars().get(index - 1).copied()?),
+        };
+        Some(TextDescription::Insert {
+            after,
+            lamport: self.next_lamport(),
+            text: text.to_string(),
+        })
+    }
+
+    /// Delete `len` characters starting at `index`. If that goes past the end of the text, we delete up to the end.
+    pub fn delete(&self, index: usize, len: usize) -> TextDescription {
+        TextDescription::Delete(
+            self.visible_chars()
+                .into_iter()
+                .skip(index)
+                .take(len)
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = self
+            .visible_chars()
+            .into_iter()
+            .map(|id| self.chars[&id].value)
+            .collect::<String>();
+        write!(f, "{}", text)
+    }
+}
+
+impl Applyable for Text {
+    const NAME: &'static str = "Text";
+
+    type Description = TextDescription;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        _: Time,
+    ) -> Self {
+        match desc {
+            TextDescription::Insert {
+                after,
+                lamport,
+                text,
+            } => {
+                // The counter we get is always an `Operation`, since it's the counter of the operation we're applying
+                let pun = counter.pun().unwrap_or_default();
+                let mut after = after;
+                for (offset, value) in text.chars().enumerate() {
+                    let id = CharId {
+                        user_pub_key,
+                        pun,
+                        offset: offset as u32,
+                    };
+                    self.chars.insert(
+                        id,
+                        Char {
+                            after,
+                            lamport,
+                            value,
+                        },
+                    );
+                    after = Some(id);
+                }
+            }
+            TextDescription::Delete(ids) => self.count_deletes(ids, 1),
+            TextDescription::Restore(ids) => self.count_deletes(ids, -1),
+        }
+        self
+    }
+
+    fn inverse(
+        &self,
+        desc: &TextDescription,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+    ) -> Option<TextDescription> {
+        match desc {
+            TextDescription::Insert { text, .. } => {
+                let pun = counter.pun()?;
+                let inserted = (0..text.chars().count() as u32)
+                    .map(|offset| CharId {
+                        user_pub_key,
+                        pun,
+                        offset,
+                    })
+                    .collect::<BTreeSet<_>>();
+                Some(inserted)
+                    .filter(|inserted| !inserted.is_empty())
+                    .map(TextDescription::Delete)
+            }
+            TextDescription::Delete(ids) => Some(TextDescription::Restore(ids.clone())),
+            TextDescription::Restore(ids) => Some(TextDescription::Delete(ids.clone())),
+        }
+    }
+}
+
+/// Undoing an insert deletes the text it inserted, and undoing a delete brings back the characters it deleted. They
+/// come back as the same characters, so undoing the insert that typed them still finds them, and anything typed
+/// in the meantime stays where it is. A character someone else deleted too stays deleted.
+impl Invertible for Text {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{
+        create_account, create_crdt, create_crdt_info, get_random_id, Account, CRDT,
+    };
+    use crate::testkit;
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+
+    fn merge(into: CRDT<Text>, from: &CRDT<Text>) -> CRDT<Text> {
+        let missing = from.ops_since(into.state_vector()).collect::<Vec<_>>();
+        missing.into_iter().fold(into, CRDT::apply)
+    }
+
+    #[test]
+    fn concurrent_edits_keep_everyones_words_together() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(Text::new(), get_random_id()));
+
+        let insert = initial.value.insert(0, "Hello world").unwrap();
+        let crdt1 = initial.apply_desc(&account1, insert);
+        let crdt2 = crdt1.clone();
+        assert_eq!(crdt1.value.to_string(), "Hello world");
+        assert!(crdt1.value.insert(12, "!").is_none());
+
+        // Both users type at the same place, and the second one also deletes "world"
+        let insert = crdt1.value.insert(5, " there").unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, insert);
+        let insert = crdt2.value.insert(5, ", dear").unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, insert);
+        let delete = crdt2.value.delete(12, 5);
+        let crdt2 = crdt2.apply_desc(&account2, delete);
+        assert_eq!(crdt2.value.to_string(), "Hello, dear ");
+
+        let merged1 = merge(crdt1.clone(), &crdt2);
+        let merged2 = merge(crdt2, &crdt1);
+        assert_eq!(merged1.value, merged2.value);
+        let merged = merged1.value.to_string();
+        assert!(
+            merged == "Hello there, dear " || merged == "Hello, dear there ",
+            "{:?}",
+            merged
+        );
+    }
+
+    #[test]
+    fn big_pastes_dont_overflow_the_stack() {
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+        let text = "a".repeat(100_000);
+        let crdt = create_crdt(create_crdt_info(Text::new(), get_random_id())).apply_desc(
+            &account,
+            TextDescription::Insert {
+                after: None,
+                lamport: 1,
+                text: text.clone(),
+            },
+        );
+        assert_eq!(crdt.value.to_string(), text);
+    }
+
+    #[test]
+    fn undo_keeps_other_peoples_typing() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(Text::new(), get_random_id()));
+
+        let insert = initial.value.insert(0, "Hello world").unwrap();
+        let crdt1 = initial.apply_desc(&account1, insert);
+
+        // The second user adds to the end while the first one deletes "world"
+        let insert = crdt1.value.insert(11, "!").unwrap();
+        let crdt2 = crdt1.clone().apply_desc(&account2, insert);
+        let delete = crdt1.value.delete(5, 6);
+        let crdt1 = crdt1.apply_desc(&account1, delete);
+        let crdt1 = merge(crdt1, &crdt2);
+        assert_eq!(crdt1.value.to_string(), "Hello!");
+
+        // Undoing the delete brings "world" back without losing the "!"
+        let pun = crdt1.last_undoable(&pk1).unwrap();
+        let crdt1 = crdt1.undo(&account1, pun);
+        assert_eq!(crdt1.value.to_string(), "Hello world!");
+
+        // Undoing the first insert deletes all of the first user's text, including what the undo brought back
+        let pun = crdt1.last_undoable(&pk1).unwrap();
+        let crdt1 = crdt1.undo(&account1, pun);
+        assert_eq!(crdt1.value.to_string(), "!");
+
+        // Redo both, and make sure the other user ends up in the same place
+        let pun = crdt1.last_redoable(&pk1).unwrap();
+        let crdt1 = crdt1.undo(&account1, pun);
+        assert_eq!(crdt1.value.to_string(), "Hello world!");
+        let pun = crdt1.last_redoable(&pk1).unwrap();
+        let crdt1 = crdt1.undo(&account1, pun);
+        assert_eq!(crdt1.value.to_string(), "Hello!");
+        let crdt2 = merge(crdt2, &crdt1);
+        assert_eq!(crdt1.value, crdt2.value);
+
+        // If the second user deletes "Hello" too, undoing the first user's delete of it doesn't bring it back
+        let delete = crdt2.value.delete(0, 5);
+        let crdt2 = crdt2.apply_desc(&account2, delete);
+        let delete = crdt1.value.delete(0, 5);
+        let crdt1 = crdt1.apply_desc(&account1, delete);
+        let crdt1 = merge(crdt1, &crdt2);
+        let pun = crdt1.last_undoable(&pk1).unwrap();
+        let crdt1 = crdt1.undo(&account1, pun);
+        assert_eq!(crdt1.value.to_string(), "!");
+        let pun = crdt2.last_undoable(&pk2).unwrap();
+        let crdt2 = merge(crdt2.undo(&account2, pun), &crdt1);
+        assert_eq!(crdt2.value.to_string(), "Hello!");
+    }
+
+    #[derive(Debug, Clone)]
+    enum Action {
+        // Where to insert or delete is picked as a fraction of the length of the text
+        Insert(f64, String),
+        Delete(f64, usize),
+        SyncFrom(usize),
+    }
+
+    const USERS: usize = 4;
+
+    fn action() -> impl Strategy<Value = (usize, Action)> {
+        let action = prop_oneof![
+            (0.0..=1.0, "[a-z]{1,4}").prop_map(|(at, text)| Action::Insert(at, text)),
+            (0.0..=1.0, 1..4usize).prop_map(|(at, len)| Action::Delete(at, len)),
+            (0..USERS).prop_map(Action::SyncFrom),
+        ];
+        (0..USERS, action)
+    }
+
+    proptest! {
+        #[test]
+        fn edits_by_index_converge(actions in prop::collection::vec(action(), 1..40)) {
+            let initial = create_crdt(create_crdt_info(Text::new(), get_random_id()));
+            let accounts: Vec<Account> = (0..USERS)
+                .map(|_| {
+                    let (pk, sk) = sign::gen_keypair();
+                    create_account(pk, sk)
+                })
+                .collect();
+            let mut replicas = vec![initial; USERS];
+            for (user, action) in actions {
+                let replica = replicas[user].clone();
+                let before = replica.value.to_string().chars().collect::<Vec<_>>();
+                let at = |fraction: f64| (fraction * before.len() as f64) as usize;
+                replicas[user] = match action {
+                    Action::Insert(fraction, text) => {
+                        let insert = replica.value.insert(at(fraction), &text).unwrap();
+                        let replica = replica.apply_desc(&accounts[user], insert);
+                        // The text goes exactly where we asked
+                        let mut expected = before.clone();
+                        expected.splice(at(fraction)..at(fraction), text.chars());
+                        prop_assert_eq!(replica.value.to_string(), expected.into_iter().collect::<String>());
+                        replica
+                    }
+                    Action::Delete(fraction, len) => {
+                        let delete = replica.value.delete(at(fraction), len);
+                        let replica = replica.apply_desc(&accounts[user], delete);
+                        let mut expected = before.clone();
+                        let end = (at(fraction) + len).min(before.len());
+                        expected.drain(at(fraction)..end);
+                        prop_assert_eq!(replica.value.to_string(), expected.into_iter().collect::<String>());
+                        replica
+                    }
+                    Action::SyncFrom(other) => merge(replica, &replicas[other]),
+                };
+            }
+
+            let forwards = replicas.iter().fold(replicas[0].clone(), merge);
+            let backwards = replicas.iter().rev().fold(replicas[USERS - 1].clone(), merge);
+            prop_assert_eq!(forwards.value.to_string(), backwards.value.to_string());
+            prop_assert_eq!(&forwards.value, &backwards.value);
+        }
+    }
+
+    #[test]
+    fn text_converges() {
+        // These refer to characters that mostly don't exist, which is fine: they just stay hidden
+        let (pk, _) = sign::gen_keypair();
+        let char_id = (0..4u32, 0..3u32).prop_map(move |(pun, offset)| CharId {
+            user_pub_key: pk,
+            pun,
+            offset,
+        });
+        let descriptions = prop_oneof![
+            (prop::option::of(char_id.clone()), 0..5u64, "[a-z]{0,3}").prop_map(
+                |(after, lamport, text)| TextDescription::Insert {
+                    after,
+                    lamport,
+                    text
+                }
+            ),
+            prop::collection::btree_set(char_id.clone(), 0..3).prop_map(TextDescription::Delete),
+            prop::collection::btree_set(char_id, 0..3).prop_map(TextDescription::Restore),
+        ];
+        testkit::check_applyable(Text::new(), descriptions);
+    }
+}
@@ -0,0 +1,451 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Pun, Time, UserPubKey};
+use crate::types::sequence::ItemId;
+
+/// Identifies a node in a `Tree`. Every node but the root and the trash is identified by the operation that created
+/// it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum TreeNodeId {
+    Root,
+    /// Deleted nodes live in here. You can't see it, but you can move things back out of it.
+    Trash,
+    Created(ItemId),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum TreeDescription {
+    /// Make a new node called `name` under `parent`. Make these with `Tree::create`.
+    Create {
+        parent: TreeNodeId,
+        name: String,
+        lamport: u64,
+    },
+    /// Move `node` under `parent` and call it `name`. Renaming and deleting a node are moves too. Make these with
+    /// `Tree::move_node`, `Tree::rename` and `Tree::delete`.
+    Move {
+        node: TreeNodeId,
+        parent: TreeNodeId,
+        name: String,
+        lamport: u64,
+    },
+}
+
+/// When a move happened. Moves are done in this order, whatever order they arrive in.
+type Timestamp = (u64, UserPubKey, Pun);
+
+// Where a node is, and what it's called.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+struct Placement {
+    parent: TreeNodeId,
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+struct LogEntry {
+    timestamp: Timestamp,
+    node: TreeNodeId,
+    to: Placement,
+    // Where the node was before this move, so we can undo it. `None` if this move made it.
+    from: Option<Placement>,
+}
+
+/// Tree is a hierarchy of named nodes, like folders. You can create, rename, move and delete nodes, and no matter
+/// how people move things around at the same time, it never ends up with a cycle.
+///
+/// It's the algorithm from "A highly-available move operation for replicated trees" (Kleppmann et al.). Every
+/// change is a move, and moves happen in order of their (lamport, user, counter) timestamp. A move that would put
+/// a node inside itself does nothing. When a move arrives that should have happened before moves we've already
+/// done, we undo the later ones, do it, and then do them again. So if I move A into B while you move B into A,
+/// whichever move comes later does nothing, and everyone agrees on which one that is.
+///
+/// We keep every move ever made so we can undo them, so this grows with every change. Deleting a node moves it
+/// (along with everything under it) into the trash.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct Tree {
+    nodes: BTreeMap<TreeNodeId, Placement>,
+    // Every move, sorted by timestamp
+    log: Vec<LogEntry>,
+}
+
+impl Tree {
+    pub fn new() -> Self {
+        Tree::default()
+    }
+
+    /// Whether `ancestor` is `node`, or somewhere above it.
+    fn is_ancestor(&self, ancestor: TreeNodeId, mut node: TreeNodeId) -> bool {
+        loop {
+            if node == ancestor {
+                return true;
+            }
+            match self.nodes.get(&node) {
+                Some(placement) => node = placement.parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Whether the node is in the tree (rather than in the trash, or under a node we haven't heard about yet).
+    pub fn contains(&self, node: TreeNodeId) -> bool {
+        self.is_ancestor(TreeNodeId::Root, node)
+    }
+
+    pub fn parent(&self, node: TreeNodeId) -> Option<TreeNodeId> {
+        Some(self.nodes.get(&node)?.parent).filter(|_| self.contains(node))
+    }
+
+    pub fn name(&self, node: TreeNodeId) -> Option<&str> {
+        Some(self.nodes.get(&node)?.name.as_str()).filter(|_| self.contains(node))
+    }
+
+    /// The nodes right under `node`, sorted by name.
+    pub fn children(&self, node: TreeNodeId) -> Vec<TreeNodeId> {
+        let mut children = self
+            .nodes
+            .iter()
+            .filter(|(_, placement)| placement.parent == node)
+            .map(|(child, placement)| (placement.name.as_str(), *child))
+            .collect::<Vec<_>>();
+        children.sort();
+        children.into_iter().map(|(_, child)| child).collect()
+    }
+
+    /// The names of the nodes from the root down to `node`. `None` if it isn't in the tree.
+    pub fn path(&self, node: TreeNodeId) -> Option<Vec<&str>> {
+        if !self.contains(node) {
+            return None;
+        }
+        let mut path = vec![];
+        let mut node = node;
+        while let Some(placement) = self.nodes.get(&node) {
+            path.push(placement.name.as_str());
+            node = placement.parent;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// The node at the end of a path of names, starting from the root. If a few nodes have the same name, we pick
+    /// the first.
+    pub fn find(&self, path: &[&str]) -> Option<TreeNodeId> {
+        path.iter().try_fold(TreeNodeId::Root, |node, name| {
+            self.children(node)
+                .into_iter()
+                .find(|child| self.nodes[child].name == *name)
+        })
+    }
+
+    /// Every node in the tree along with how deep it is, parents before their children.
+    pub fn walk(&self) -> Vec<(usize, TreeNodeId)> {
+        let mut walked = vec![];
+        let mut stack = vec![(0, TreeNodeId::Root)];
+        while let Some((depth, node)) = stack.pop() {
+            if node != TreeNodeId::Root {
+                walked.push((depth, node));
+            }
+            stack.extend(
+                self.children(node)
+                    .into_iter()
+                    .rev()
+                    .map(|child| (depth + 1, child)),
+            );
+        }
+        walked
+    }
+
+    fn next_lamport(&self) -> u64 {
+        self.log.last().map_or(0, |entry| entry.timestamp.0) + 1
+    }
+
+    /// Make a new node called `name` under `parent`. `None` if `parent` isn't in the tree.
+    pub fn create(&self, parent: TreeNodeId, name: &str) -> Option<TreeDescription> {
+        if !self.contains(parent) {
+            return None;
+        }
+        Some(TreeDescription::Create {
+            parent,
+            name: name.to_string(),
+            lamport: self.next_lamport(),
+        })
+    }
+
+    /// Move `node` under `parent`, keeping its name. `None` if either of them isn't in the tree, or if `parent` is
+    /// under `node`.
+    pub fn move_node(&self, node: TreeNodeId, parent: TreeNodeId) -> Option<TreeDescription> {
+        if !self.contains(parent) || self.is_ancestor(node, parent) {
+            return None;
+        }
+        Some(TreeDescription::Move {
+            node,
+            parent,
+            name: self.name(node)?.to_string(),
+            lamport: self.next_lamport(),
+        })
+    }
+
+    /// `None` if the node isn't in the tree.
+    pub fn rename(&self, node: TreeNodeId, name: &str) -> Option<TreeDescription> {
+        Some(TreeDescription::Move {
+            node,
+            parent: self.parent(node)?,
+            name: name.to_string(),
+            lamport: self.next_lamport(),
+        })
+    }
+
+    /// Delete a node and everything under it. `None` if the node isn't in the tree.
+    pub fn delete(&self, node: TreeNodeId) -> Option<TreeDescription> {
+        Some(TreeDescription::Move {
+            node,
+            parent: TreeNodeId::Trash,
+            name: self.name(node)?.to_string(),
+            lamport: self.next_lamport(),
+        })
+    }
+
+    // Do a move that comes after every move we've done so far.
+    fn do_move(&mut self, timestamp: Timestamp, node: TreeNodeId, to: Placement) {
+        let from = self.nodes.get(&node).cloned();
+        let movable = node != TreeNodeId::Root && node != TreeNodeId::Trash;
+        if movable && !self.is_ancestor(node, to.parent) {
+            self.nodes.insert(node, to.clone());
+        }
+        self.log.push(LogEntry {
+            timestamp,
+            node,
+            to,
+            from,
+        });
+    }
+
+    fn undo_move(&mut self, entry: &LogEntry) {
+        match &entry.from {
+            Some(from) => self.nodes.insert(entry.node, from.clone()),
+            None => self.nodes.remove(&entry.node),
+        };
+    }
+}
+
+impl fmt::Display for Tree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (depth, node) in self.walk() {
+            write!(f, "\n{}{}", "  ".repeat(depth), self.nodes[&node].name)?;
+        }
+        Ok(())
+    }
+}
+
+impl Applyable for Tree {
+    const NAME: &'static str = "Tree";
+
+    type Description = TreeDescription;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        user_pub_key: UserPubKey,
+        counter: Counter,
+        _: Time,
+    ) -> Self {
+        // The counter we get is always an `Operation`, since it's the counter of the operation we're applying
+        let pun = counter.pun().unwrap_or_default();
+        let (node, parent, name, lamport) = match desc {
+            TreeDescription::Create {
+                parent,
+                name,
+                lamport,
+            } => {
+                let node = TreeNodeId::Created(ItemId { user_pub_key, pun });
+                (node, parent, name, lamport)
+            }
+            TreeDescription::Move {
+                node,
+                parent,
+                name,
+                lamport,
+            } => (node, parent, name, lamport),
+        };
+        let timestamp = (lamport, user_pub_key, pun);
+
+        // Undo everything that should have happened after this move, do it, then redo them
+        let later = self
+            .log
+            .iter()
+            .position(|entry| entry.timestamp > timestamp)
+            .unwrap_or(self.log.len());
+        let undone = self.log.split_off(later);
+        for entry in undone.iter().rev() {
+            self.undo_move(entry);
+        }
+        self.do_move(timestamp, node, Placement { parent, name });
+        for entry in undone {
+            self.do_move(entry.timestamp, entry.node, entry.to);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{
+        create_account, create_crdt, create_crdt_info, get_random_id, Account, CRDT,
+    };
+    use crate::testkit;
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+
+    fn merge(into: CRDT<Tree>, from: &CRDT<Tree>) -> CRDT<Tree> {
+        let missing = from.ops_since(into.state_vector()).collect::<Vec<_>>();
+        missing.into_iter().fold(into, CRDT::apply)
+    }
+
+    fn create(crdt: CRDT<Tree>, account: &Account, path: &[&str]) -> CRDT<Tree> {
+        let (name, parent) = path.split_last().unwrap();
+        let parent = crdt.value.find(parent).unwrap();
+        let desc = crdt.value.create(parent, name).unwrap();
+        crdt.apply_desc(account, desc)
+    }
+
+    #[test]
+    fn concurrent_moves_dont_make_cycles() {
+        let (pk1, sk1) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        let account1 = create_account(pk1, sk1);
+        let account2 = create_account(pk2, sk2);
+        let initial = create_crdt(create_crdt_info(Tree::new(), get_random_id()));
+        let crdt1 = create(initial.clone(), &account1, &["a"]);
+        let crdt1 = create(crdt1, &account1, &["b"]);
+        let crdt1 = create(crdt1, &account1, &["b", "c"]);
+        let crdt2 = merge(initial, &crdt1);
+        let a = crdt1.value.find(&["a"]).unwrap();
+        let b = crdt1.value.find(&["b"]).unwrap();
+        let c = crdt1.value.find(&["b", "c"]).unwrap();
+
+        // The first user moves a into b, while the second moves b into a and deletes c
+        let desc = crdt1.value.move_node(a, b).unwrap();
+        let crdt1 = crdt1.apply_desc(&account1, desc);
+        assert_eq!(crdt1.value.path(a), Some(vec!["b", "a"]));
+        let desc = crdt2.value.move_node(b, a).unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, desc);
+        let desc = crdt2.value.delete(c).unwrap();
+        let crdt2 = crdt2.apply_desc(&account2, desc);
+        assert_eq!(crdt2.value.path(b), Some(vec!["a", "b"]));
+        assert_eq!(crdt2.value.path(c), None);
+
+        let merged1 = merge(crdt1.clone(), &crdt2);
+        let merged2 = merge(crdt2, &crdt1);
+        assert_eq!(merged1.value, merged2.value);
+        // Only one of the moves happened
+        let top = merged1.value.children(TreeNodeId::Root);
+        assert_eq!(top.len(), 1);
+        assert!(top == vec![a] || top == vec![b]);
+        assert!(merged1.value.contains(a) && merged1.value.contains(b));
+        assert!(!merged1.value.contains(c));
+        assert_eq!(merged1.value.walk().len(), 2);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Action {
+        // Nodes are picked as a fraction of how many there are
+        Create(f64, String),
+        Move(f64, f64),
+        Delete(f64),
+        SyncFrom(usize),
+    }
+
+    const USERS: usize = 4;
+
+    fn action() -> impl Strategy<Value = (usize, Action)> {
+        let action = prop_oneof![
+            (0.0..1.0, "[a-c]").prop_map(|(parent, name)| Action::Create(parent, name)),
+            (0.0..1.0, 0.0..1.0).prop_map(|(node, parent)| Action::Move(node, parent)),
+            (0.0..1.0).prop_map(Action::Delete),
+            (0..USERS).prop_map(Action::SyncFrom),
+        ];
+        (0..USERS, action)
+    }
+
+    proptest! {
+        #[test]
+        fn concurrent_edits_converge_to_a_tree(actions in prop::collection::vec(action(), 1..50)) {
+            let initial = create_crdt(create_crdt_info(Tree::new(), get_random_id()));
+            let accounts: Vec<Account> = (0..USERS)
+                .map(|_| {
+                    let (pk, sk) = sign::gen_keypair();
+                    create_account(pk, sk)
+                })
+                .collect();
+            let mut replicas = vec![initial; USERS];
+            for (user, action) in actions {
+                let replica = replicas[user].clone();
+                // The root, then every node in the tree
+                let nodes = std::iter::once(TreeNodeId::Root)
+                    .chain(replica.value.walk().into_iter().map(|(_, node)| node))
+                    .collect::<Vec<_>>();
+                let pick = |fraction: f64| nodes[(fraction * nodes.len() as f64) as usize];
+                let desc = match action {
+                    Action::Create(parent, name) => replica.value.create(pick(parent), &name),
+                    Action::Move(node, parent) => replica.value.move_node(pick(node), pick(parent)),
+                    Action::Delete(node) => replica.value.delete(pick(node)),
+                    Action::SyncFrom(other) => {
+                        replicas[user] = merge(replica, &replicas[other]);
+                        continue;
+                    }
+                };
+                replicas[user] = match desc {
+                    Some(desc) => replica.apply_desc(&accounts[user], desc),
+                    None => replica,
+                };
+            }
+
+            let forwards = replicas.iter().fold(replicas[0].clone(), merge);
+            let backwards = replicas.iter().rev().fold(replicas[USERS - 1].clone(), merge);
+            prop_assert_eq!(&forwards.value, &backwards.value);
+            // Every node we can see is reachable from the root exactly once
+            let walked = forwards.value.walk();
+            for (_, node) in &walked {
+                prop_assert!(forwards.value.path(*node).is_some());
+            }
+            let mut unique = walked.iter().map(|(_, node)| *node).collect::<Vec<_>>();
+            unique.sort();
+            unique.dedup();
+            prop_assert_eq!(unique.len(), walked.len());
+        }
+    }
+
+    #[test]
+    fn tree_converges() {
+        // These move a handful of made-up nodes around, including into each other
+        let (pk, _) = sign::gen_keypair();
+        let node = prop_oneof![
+            Just(TreeNodeId::Root),
+            Just(TreeNodeId::Trash),
+            (0..4u32).prop_map(move |pun| TreeNodeId::Created(ItemId {
+                user_pub_key: pk,
+                pun
+            })),
+        ];
+        let descriptions = prop_oneof![
+            (node.clone(), "[ab]", 0..5u64).prop_map(|(parent, name, lamport)| {
+                TreeDescription::Create {
+                    parent,
+                    name,
+                    lamport,
+                }
+            }),
+            (node.clone(), node, "[ab]", 0..5u64).prop_map(|(node, parent, name, lamport)| {
+                TreeDescription::Move {
+                    node,
+                    parent,
+                    name,
+                    lamport,
+                }
+            }),
+        ];
+        testkit::check_applyable(Tree::new(), descriptions);
+    }
+}
@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::replicant::{Applyable, Counter, Time, UserPubKey};
+use crate::types::write_set;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum TwoPhaseSetDescription<E> {
+    Add(E),
+    Remove(E),
+}
+
+/// TwoPhaseSet is a set where elements can be added and then removed, but once an element has been removed it can
+/// never come back. If someone adds an element at the same time as someone else removes it, the remove wins.
+///
+/// It's really two grow-only sets: everything that's ever been added, and everything that's ever been removed.
+/// That's why the order operations arrive in doesn't matter.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct TwoPhaseSet<E: Ord> {
+    added: BTreeSet<E>,
+    removed: BTreeSet<E>,
+}
+
+impl<E: Ord> TwoPhaseSet<E> {
+    pub fn new() -> Self {
+        TwoPhaseSet {
+            added: BTreeSet::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+
+    pub fn contains(&self, element: &E) -> bool {
+        self.added.contains(element) && !self.removed.contains(element)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &E> {
+        self.added.difference(&self.removed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Whether this element has been removed, meaning it can't be added again.
+    pub fn was_removed(&self, element: &E) -> bool {
+        self.removed.contains(element)
+    }
+
+    /// Add an element. `None` if it's already been removed, since then adding it wouldn't do anything.
+    pub fn add(&self, element: E) -> Option<TwoPhaseSetDescription<E>> {
+        if self.was_removed(&element) {
+            None
+        } else {
+            Some(TwoPhaseSetDescription::Add(element))
+        }
+    }
+
+    /// Remove an element for good. `None` if it isn't in the set.
+    pub fn remove(&self, element: E) -> Option<TwoPhaseSetDescription<E>> {
+        if self.contains(&element) {
+            Some(TwoPhaseSetDescription::Remove(element))
+        } else {
+            None
+        }
+    }
+}
+
+impl<E: Ord> Default for TwoPhaseSet<E> {
+    fn default() -> Self {
+        TwoPhaseSet::new()
+    }
+}
+
+impl<E: Ord + fmt::Display> fmt::Display for TwoPhaseSet<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_set(f, self.iter())
+    }
+}
+
+impl<E: Ord + Clone> Applyable for TwoPhaseSet<E> {
+    const NAME: &'static str = "TwoPhaseSet";
+
+    type Description = TwoPhaseSetDescription<E>;
+
+    fn apply_without_idempotency_check(
+        mut self,
+        desc: Self::Description,
+        _: UserPubKey,
+        _: Counter,
+        _: Time,
+    ) -> Self {
+        match desc {
+            TwoPhaseSetDescription::Add(element) => self.added.insert(element),
+            TwoPhaseSetDescription::Remove(element) => self.removed.insert(element),
+        };
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicant::{create_account, create_crdt, create_crdt_info, get_random_id};
+    use crate::testkit;
+    use proptest::prelude::*;
+    use sodiumoxide::crypto::sign;
+
+    #[test]
+    fn removed_elements_stay_removed() {
+        let (pk, sk) = sign::gen_keypair();
+        let account = create_account(pk, sk);
+        let crdt = create_crdt(create_crdt_info(TwoPhaseSet::new(), get_random_id()))
+            .apply_desc(&account, TwoPhaseSetDescription::Add("a"))
+            .apply_desc(&account, TwoPhaseSetDescription::Add("b"));
+        let remove = crdt.value.remove("a").unwrap();
+        let crdt = crdt
+            .apply_desc(&account, remove)
+            .apply_desc(&account, TwoPhaseSetDescription::Add("a"));
+        assert!(!crdt.value.contains(&"a"));
+        assert_eq!(crdt.value.add("a"), None);
+        assert_eq!(crdt.value.remove("c"), None);
+        assert_eq!(format!("{}", crdt.value), "{b}");
+    }
+
+    #[test]
+    fn two_phase_set_converges() {
+        let descriptions = prop_oneof![
+            (0..8u8).prop_map(TwoPhaseSetDescription::Add),
+            (0..8u8).prop_map(TwoPhaseSetDescription::Remove),
+        ];
+        testkit::check_applyable(TwoPhaseSet::new(), descriptions);
+    }
+}